@@ -0,0 +1,51 @@
+use anchor_lang::prelude::*;
+use crate::errors::MessagingErrorCode;
+
+/// Keeps the attachment account under the 10KB a program can allocate in one instruction
+pub const MAX_ATTACHMENT_LEN: u32 = 10_000;
+pub const MAX_ATTACHMENTS: u8 = 16;
+
+/// A payload too large for `Message.data`, written in chunks by the message's sender over
+/// several transactions. Attachments of a message are numbered from zero in creation order.
+#[account]
+#[derive(Default)]
+pub struct Attachment {
+    pub message: Pubkey,
+    /// SHA-256 of `data`, set once the sender finalizes it; no writes are allowed afterwards
+    pub content_hash: Option<[u8; 32]>,
+    pub data: Vec<u8>,
+}
+
+impl Attachment {
+    pub fn space(size: u32) -> usize {
+        8                           // account discriminator
+        + 32                        // message pubkey
+        + 1 + 32                    // content hash option
+        + 4 + size as usize         // data
+    }
+}
+
+pub fn attachment_address(message: &Pubkey, attachment_index: u8) -> Pubkey {
+    Pubkey::find_program_address(
+        &[crate::PROTOCOL_SEED.as_bytes(), crate::ATTACHMENT_SEED.as_bytes(), message.as_ref(), &[attachment_index]],
+        &crate::ID,
+    ).0
+}
+
+/// Close every attachment of a message being closed and return their rent. The attachments
+/// must be the first extra accounts, in order.
+pub fn close_attachments(message: &Pubkey, attachment_count: u8, extra_accounts: &[AccountInfo],
+                         rent_destination: &AccountInfo) -> Result<()> {
+    require!(extra_accounts.len() >= attachment_count as usize, MessagingErrorCode::MissingAttachmentAccounts);
+    for (attachment_index, attachment_info) in extra_accounts.iter().take(attachment_count as usize).enumerate() {
+        require!(
+            attachment_info.key() == attachment_address(message, attachment_index as u8),
+            MessagingErrorCode::MissingAttachmentAccounts,
+        );
+        let lamports = attachment_info.lamports();
+        **rent_destination.try_borrow_mut_lamports()? += lamports;
+        **attachment_info.try_borrow_mut_lamports()? = 0;
+        attachment_info.try_borrow_mut_data()?.fill(0);
+    }
+    Ok(())
+}
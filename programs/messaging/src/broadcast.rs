@@ -0,0 +1,41 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{program, system_instruction};
+
+/// Keeps a broadcast within the default compute budget and the transaction size limit
+pub const MAX_BROADCAST_RECIPIENTS: usize = 4;
+
+/// Each recipient takes up this many remaining accounts, in order: receiver, mailbox,
+/// mailbox settings, contacts, the sender's rate limit state and the new message
+pub const ACCOUNTS_PER_RECIPIENT: usize = 6;
+
+/// Create one of our PDAs and write the default value of `T` into it, as `#[account(init)]`
+/// would. Accounts someone already sent lamports to are topped up, allocated and assigned.
+pub fn init_pda_account<'info, T>(payer: &AccountInfo<'info>, account: &AccountInfo<'info>,
+                                  system_program: &AccountInfo<'info>, space: usize,
+                                  signer_seeds: &[&[u8]]) -> Result<Account<'info, T>>
+where
+    T: AccountSerialize + AccountDeserialize + Owner + Clone + Default,
+{
+    let minimum_balance = Rent::get()?.minimum_balance(space);
+    let current_lamports = account.lamports();
+    if current_lamports == 0 {
+        let ix = system_instruction::create_account(payer.key, account.key, minimum_balance, space as u64, &T::owner());
+        program::invoke_signed(&ix, &[payer.clone(), account.clone(), system_program.clone()], &[signer_seeds])?;
+    } else {
+        if minimum_balance > current_lamports {
+            let ix = system_instruction::transfer(payer.key, account.key, minimum_balance - current_lamports);
+            program::invoke(&ix, &[payer.clone(), account.clone(), system_program.clone()])?;
+        }
+        let ix = system_instruction::allocate(account.key, space as u64);
+        program::invoke_signed(&ix, &[account.clone(), system_program.clone()], &[signer_seeds])?;
+        let ix = system_instruction::assign(account.key, &T::owner());
+        program::invoke_signed(&ix, &[account.clone(), system_program.clone()], &[signer_seeds])?;
+    }
+
+    {
+        let mut data = account.try_borrow_mut_data()?;
+        let mut writer: &mut [u8] = &mut data;
+        T::default().try_serialize(&mut writer)?;
+    }
+    Account::try_from(account)
+}
@@ -0,0 +1,92 @@
+use anchor_lang::prelude::*;
+
+pub use postbox::config::MAX_PASS_DISCOUNT_BPS;
+
+/// A protocol pass: holding it discounts the protocol fees. Postbox owns the pass and its
+/// ownership checks; this copy of the enum only exists so the messaging IDL can describe it.
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    PartialEq,
+    Eq
+)]
+pub enum FeePass {
+    Token { mint: Pubkey, amount: u64 },
+    NftCollection { collection_id: Pubkey },
+}
+
+impl FeePass {
+    /// The proof is the trailing extra accounts, checked exactly as postbox checks its pass.
+    pub fn is_held_by(&self, holder: &Pubkey, extra_accounts: &[AccountInfo]) -> bool {
+        return postbox::config::FeePass::from(self).is_held_by(holder, extra_accounts);
+    }
+}
+
+impl From<&FeePass> for postbox::config::FeePass {
+    fn from(pass: &FeePass) -> Self {
+        return match pass {
+            FeePass::Token { mint, amount } => postbox::config::FeePass::Token { mint: *mint, amount: *amount },
+            FeePass::NftCollection { collection_id } => {
+                postbox::config::FeePass::NftCollection { collection_id: *collection_id }
+            },
+        };
+    }
+}
+
+impl From<postbox::config::FeePass> for FeePass {
+    fn from(pass: postbox::config::FeePass) -> Self {
+        // Exhaustive, so a pass postbox adds does not build until messaging can store it too
+        return match pass {
+            postbox::config::FeePass::Token { mint, amount } => FeePass::Token { mint, amount },
+            postbox::config::FeePass::NftCollection { collection_id } => FeePass::NftCollection { collection_id },
+        };
+    }
+}
+
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    Default,
+    PartialEq,
+    Eq
+)]
+pub struct FeeSchedule {
+    pub message_fee: u64,
+    pub pass: Option<FeePass>,
+    pub pass_discount_bps: u16,
+}
+
+/// Protocol-wide parameters that used to be compiled in. The admin can change them at any
+/// time without a redeploy.
+#[account]
+#[derive(Default)]
+pub struct ProtocolConfig {
+    pub admin: Pubkey,
+    pub treasury: Pubkey,
+    pub fees: FeeSchedule,
+}
+
+impl ProtocolConfig {
+    /// The message fee, discounted if the sender proved they hold the protocol pass
+    pub fn message_fee_for(&self, sender: &Pubkey, extra_accounts: &[AccountInfo]) -> u64 {
+        let fee = self.fees.message_fee;
+        return match &self.fees.pass {
+            Some(pass) if pass.is_held_by(sender, extra_accounts) => {
+                let discount = (fee as u128) * (self.fees.pass_discount_bps as u128) / (MAX_PASS_DISCOUNT_BPS as u128);
+                fee.saturating_sub(discount as u64)
+            },
+            _ => fee,
+        };
+    }
+
+    pub fn get_size(&self) -> usize {
+        // discriminator + admin + treasury + fees
+        return 8 + 32 + 32 + crate::borsh_size(&self.fees);
+    }
+}
+
+pub fn config_address() -> Pubkey {
+    Pubkey::find_program_address(&[crate::PROTOCOL_SEED.as_bytes(), crate::CONFIG_SEED.as_bytes()], &crate::ID).0
+}
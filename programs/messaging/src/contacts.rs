@@ -0,0 +1,29 @@
+use anchor_lang::prelude::*;
+
+#[account]
+#[derive(Default)]
+pub struct Contacts {
+    pub contacts: Vec<Pubkey>,
+}
+
+impl Contacts {
+    /// A receiver that never accepted anyone has no contacts account.
+    pub fn load(contacts_info: &AccountInfo) -> Result<Option<Contacts>> {
+        if contacts_info.data_is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Account::<Contacts>::try_from(contacts_info)?.into_inner()))
+    }
+
+    pub fn get_size(&self) -> usize {
+        // discriminator + contacts_length + contacts
+        return 8 + 4 + 32 * self.contacts.len();
+    }
+}
+
+pub fn contacts_address(mailbox: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[crate::PROTOCOL_SEED.as_bytes(), crate::CONTACTS_SEED.as_bytes(), mailbox.as_ref()],
+        &crate::ID,
+    ).0
+}
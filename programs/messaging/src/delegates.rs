@@ -0,0 +1,98 @@
+use anchor_lang::prelude::*;
+use crate::shared_mailbox::SharedMailbox;
+
+/// What a delegate may do on the receiver's behalf. Incentives claimed by a delegate are
+/// still paid to the receiver.
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    Default,
+    PartialEq,
+    Eq
+)]
+pub struct DelegatePermissions {
+    pub update_read_messages: bool,
+    pub delete_messages: bool,
+    pub claim_incentives: bool,
+}
+
+impl DelegatePermissions {
+    pub fn is_empty(&self) -> bool {
+        !self.update_read_messages && !self.delete_messages && !self.claim_incentives
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct Delegate {
+    pub key: Pubkey,
+    pub permissions: DelegatePermissions,
+}
+
+#[derive(Clone, Copy)]
+pub enum DelegateScope {
+    UpdateReadMessages,
+    DeleteMessages,
+    ClaimIncentives,
+}
+
+#[account]
+#[derive(Default)]
+pub struct Delegates {
+    pub delegates: Vec<Delegate>,
+}
+
+impl Delegates {
+    /// A receiver that never delegated anything has no delegates account.
+    pub fn load(delegates_info: &AccountInfo) -> Result<Option<Delegates>> {
+        if delegates_info.data_is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Account::<Delegates>::try_from(delegates_info)?.into_inner()))
+    }
+
+    /// Add or update a delegate; empty permissions remove it.
+    pub fn set(&mut self, key: Pubkey, permissions: DelegatePermissions) {
+        self.delegates.retain(|delegate| delegate.key != key);
+        if !permissions.is_empty() {
+            self.delegates.push(Delegate { key, permissions });
+        }
+    }
+
+    pub fn permits(&self, key: &Pubkey, scope: DelegateScope) -> bool {
+        self.delegates.iter().any(|delegate| {
+            delegate.key == *key && match scope {
+                DelegateScope::UpdateReadMessages => delegate.permissions.update_read_messages,
+                DelegateScope::DeleteMessages => delegate.permissions.delete_messages,
+                DelegateScope::ClaimIncentives => delegate.permissions.claim_incentives,
+            }
+        })
+    }
+
+    pub fn get_size(&self) -> usize {
+        // discriminator + delegates_length + (key + three permission flags) per delegate
+        return 8 + 4 + (32 + 3) * self.delegates.len();
+    }
+}
+
+/// Configuring or closing a mailbox is not delegated: only the receiver can, or an admin when
+/// the receiver is a shared mailbox, which has no key to sign with.
+pub fn is_mailbox_admin(receiver: &AccountInfo, authority: &Pubkey) -> bool {
+    authority == receiver.key
+        || matches!(SharedMailbox::load(receiver), Ok(Some(shared_mailbox)) if shared_mailbox.is_admin(authority))
+}
+
+/// The receiver can always act on their own mailbox; anyone else needs a delegation in scope
+/// or, for a shared mailbox, a member role that covers it.
+pub fn is_authorized(receiver: &AccountInfo, authority: &Pubkey, delegates_info: &AccountInfo, scope: DelegateScope) -> bool {
+    is_mailbox_admin(receiver, authority)
+        || matches!(Delegates::load(delegates_info), Ok(Some(delegates)) if delegates.permits(authority, scope))
+        || matches!(SharedMailbox::load(receiver), Ok(Some(shared_mailbox)) if shared_mailbox.permits(authority, scope))
+}
+
+pub fn delegates_address(mailbox: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[crate::PROTOCOL_SEED.as_bytes(), crate::DELEGATES_SEED.as_bytes(), mailbox.as_ref()],
+        &crate::ID,
+    ).0
+}
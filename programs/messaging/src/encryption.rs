@@ -0,0 +1,39 @@
+use anchor_lang::prelude::*;
+
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    PartialEq,
+    Eq
+)]
+pub enum EncryptionScheme {
+    // nacl box: x25519 key agreement with xsalsa20-poly1305
+    X25519XSalsa20Poly1305,
+}
+
+impl Default for EncryptionScheme {
+    fn default() -> Self {
+        EncryptionScheme::X25519XSalsa20Poly1305
+    }
+}
+
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    PartialEq,
+    Eq
+)]
+pub struct EncryptionHeader {
+    pub scheme: EncryptionScheme,
+    pub sender_ephemeral_key: [u8; 32],
+    pub nonce: [u8; 24],
+}
+
+#[account]
+#[derive(Default)]
+pub struct EncryptionKey {
+    pub scheme: EncryptionScheme,
+    pub public_key: [u8; 32],
+}
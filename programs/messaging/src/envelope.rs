@@ -0,0 +1,47 @@
+use anchor_lang::prelude::*;
+use crate::errors::MessagingErrorCode;
+
+/// The only envelope layout this program accepts; bumped when fields are added
+pub const ENVELOPE_SCHEMA_VERSION: u8 = 1;
+pub const MAX_CONTENT_TYPE_LEN: usize = 64;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct MetadataEntry {
+    pub key: String,
+    pub value: String,
+}
+
+/// A structured alternative to the free-form `Message.data` string, checked on send so
+/// consumers can rely on its shape.
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    Default,
+    PartialEq,
+    Eq
+)]
+pub struct MessageEnvelope {
+    pub schema_version: u8,
+    /// MIME type of the body, like `text/plain` or `text/markdown`
+    pub content_type: String,
+    pub subject: Option<String>,
+    pub body: String,
+    /// Key value pairs with unique keys
+    pub metadata: Vec<MetadataEntry>,
+}
+
+impl MessageEnvelope {
+    pub fn validate(&self) -> Result<()> {
+        require!(self.schema_version == ENVELOPE_SCHEMA_VERSION, MessagingErrorCode::UnsupportedEnvelopeSchema);
+        require!(
+            !self.content_type.is_empty() && self.content_type.len() <= MAX_CONTENT_TYPE_LEN,
+            MessagingErrorCode::InvalidEnvelopeContentType,
+        );
+        for (i, entry) in self.metadata.iter().enumerate() {
+            let duplicate = self.metadata[..i].iter().any(|other| other.key == entry.key);
+            require!(!duplicate, MessagingErrorCode::DuplicateEnvelopeMetadataKey);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use anchor_lang::prelude::*;
+
+/// Codes are grouped in ranges of 100 per instruction family and only ever appended to, so
+/// a code keeps its meaning across releases. Anchor reports them offset by 6000.
+///   0   send (including requests, replies and broadcasts)
+///   100 delete message
+///   200 update read messages
+///   300 claim incentive
+///   400 refund incentive
+///   500 close mailbox
+///   600 account checks shared by several instructions
+///   700 protocol config
+///   800 mailbox delegates
+///   900 shared mailboxes
+///   1000 expired message cleanup
+///   1100 message attachments
+///   1200 rate limits
+///   1300 account migrations
+#[error_code]
+pub enum MessagingErrorCode {
+    // Send message errors
+    #[msg("The incentive expiry must be in the future")]
+    IncentiveExpiryInPast,
+    #[msg("The incentive amount must be greater than zero")]
+    IncentiveAmountZero,
+    #[msg("Postage requires the payer and receiver token accounts and the token program, or the shared mailbox owner")]
+    MissingPostageAccounts,
+    #[msg("The receiver has blocked this sender")]
+    SenderBlocked,
+    #[msg("The sender is not on the receiver's allow list")]
+    SenderNotAllowed,
+    #[msg("The sender's associated token account is required by the receiver's block list")]
+    MissingSenderTokenAccount,
+    #[msg("The receiver only accepts messages from contacts, send a message request instead")]
+    SenderNotInContacts,
+    #[msg("The receiver does not have message requests enabled")]
+    MessageRequestsNotEnabled,
+    #[msg("Replies must go to the sender of the original message")]
+    ReplyReceiverNotOriginalSender,
+    #[msg("Too many recipients for a single broadcast")]
+    TooManyRecipients,
+    #[msg("Broadcast recipient accounts are missing or do not match the expected addresses")]
+    InvalidBroadcastAccounts,
+    #[msg("The relay nonce does not match the sender's next nonce")]
+    InvalidRelayNonce,
+    #[msg("A relayed message must follow an ed25519 signature check instruction")]
+    MissingRelaySignature,
+    #[msg("The ed25519 signature check does not cover this sender and message")]
+    InvalidRelaySignature,
+    #[msg("An NFT incentive must be a mint with a supply of one and no decimals")]
+    NotAnNft,
+    #[msg("The metadata account is not the Metaplex metadata of the NFT")]
+    InvalidNftMetadata,
+    #[msg("The message time-to-live must be greater than zero")]
+    InvalidMessageTtl,
+    #[msg("Ring mailbox messages cannot be longer than 256 bytes")]
+    RingMessageTooLong,
+    #[msg("The message envelope schema version is not supported")]
+    UnsupportedEnvelopeSchema,
+    #[msg("The envelope content type must be between 1 and 64 bytes")]
+    InvalidEnvelopeContentType,
+    #[msg("Envelope metadata keys must be unique")]
+    DuplicateEnvelopeMetadataKey,
+    #[msg("Shared mailboxes cannot reply, so their incentives cannot require a reply")]
+    SharedMailboxCannotReply,
+
+    // Delete message errors
+    #[msg("The message still holds a lamport incentive that must be claimed or refunded first")]
+    LamportIncentiveOutstanding = 100,
+    #[msg("Only the receiver, sender, payer, or a delegate or shared mailbox member with delete rights can delete a message")]
+    NotAuthorizedToDelete,
+    #[msg("The message still holds a token or NFT incentive that must be claimed or refunded first")]
+    TokenIncentiveOutstanding,
+
+    // Update read messages errors
+    #[msg("The read message count cannot exceed the message count")]
+    ReadCountExceedsMessageCount = 200,
+
+    // Claim incentive errors
+    #[msg("The incentive has expired and can only be refunded")]
+    IncentiveExpired = 300,
+    #[msg("The message has no lamport incentive")]
+    NoLamportIncentive,
+    #[msg("The incentive mint does not match the message's incentive")]
+    IncentiveMintMismatch,
+    #[msg("Incentives must be paid to the receiver, or the owner of a shared mailbox")]
+    IncentiveBeneficiaryMismatch,
+    #[msg("This incentive can only be claimed in the same transaction as a reply to the message")]
+    ReplyRequiredToClaim,
+
+    // Refund incentive errors
+    #[msg("The incentive has no expiry so it cannot be refunded")]
+    IncentiveHasNoExpiry = 400,
+    #[msg("The incentive has not expired yet")]
+    IncentiveNotExpired,
+    #[msg("Only the sender or payer can refund an incentive")]
+    NotAuthorizedToRefund,
+
+    // Close mailbox errors
+    #[msg("Every message sent to the mailbox must be deleted before it can be closed")]
+    MailboxNotEmpty = 500,
+
+    // Shared account errors
+    #[msg("Rent must be returned to the account that paid for it")]
+    RentDestinationNotPayer = 600,
+    #[msg("The fee receiver is not the protocol treasury")]
+    InvalidFeeReceiver,
+    #[msg("The config account is not the protocol config")]
+    InvalidConfigAccount,
+    #[msg("The mailbox account is not the receiver's mailbox")]
+    InvalidMailboxAccount,
+    #[msg("The message account is not the message at this index of the mailbox")]
+    InvalidMessageAccount,
+    #[msg("The settings account is not the settings of this mailbox")]
+    InvalidSettingsAccount,
+    #[msg("The contacts account is not the contacts of this mailbox")]
+    InvalidContactsAccount,
+    #[msg("The delegates account is not the delegates of this mailbox")]
+    InvalidDelegatesAccount,
+    #[msg("The outbox account is not the sender's outbox")]
+    InvalidOutboxAccount,
+    #[msg("The attachment account is not the attachment at this index of the message")]
+    InvalidAttachmentAccount,
+    #[msg("The ring mailbox account is not the receiver's ring mailbox")]
+    InvalidRingMailboxAccount,
+    #[msg("The shared mailbox account is not at the address of its owner and name")]
+    InvalidSharedMailboxAccount,
+    #[msg("The instructions account is not the instructions sysvar")]
+    InvalidInstructionsSysvar,
+
+    // Protocol config errors
+    #[msg("Only the config admin can update the protocol config")]
+    NotConfigAdmin = 700,
+    #[msg("Only the program upgrade authority can initialize the protocol config")]
+    NotUpgradeAuthority,
+    #[msg("The pass discount cannot exceed 10000 basis points")]
+    InvalidPassDiscount,
+
+    // Mailbox delegate errors
+    #[msg("Only the receiver or a delegate with this permission can manage the mailbox")]
+    NotMailboxAuthority = 800,
+
+    // Shared mailbox errors
+    #[msg("Shared mailbox names must be between 1 and 32 bytes")]
+    InvalidSharedMailboxName = 900,
+    #[msg("Only the owner or an admin can manage shared mailbox members")]
+    NotSharedMailboxAdmin,
+
+    // Expired message cleanup errors
+    #[msg("The message has no expiry so only its receiver, sender or payer can delete it")]
+    MessageHasNoExpiry = 1000,
+    #[msg("The message has not expired yet")]
+    MessageNotExpired,
+
+    // Message attachment errors
+    #[msg("Attachments cannot be larger than 10000 bytes")]
+    AttachmentTooLarge = 1100,
+    #[msg("A message cannot have more than 16 attachments")]
+    TooManyAttachments,
+    #[msg("Only the sender of the message can add or write attachments")]
+    NotMessageSender,
+    #[msg("The chunk does not fit inside the attachment")]
+    AttachmentWriteOutOfBounds,
+    #[msg("The attachment is finalized and can no longer be written")]
+    AttachmentAlreadyFinalized,
+    #[msg("The content hash does not match the attachment data")]
+    AttachmentHashMismatch,
+    #[msg("Every attachment of the message must be passed, in order, to close it")]
+    MissingAttachmentAccounts,
+
+    // Rate limit errors
+    #[msg("The sender has reached the receiver's message limit for this time window")]
+    RateLimitExceeded = 1200,
+    #[msg("A rate limit needs a message allowance and a window greater than zero")]
+    InvalidRateLimit,
+    #[msg("The rate limit account is not the sender's rate limit for this mailbox")]
+    InvalidRateLimitAccount,
+    #[msg("The rate limit window has not ended yet")]
+    RateLimitWindowOpen,
+
+    // Account migration errors
+    #[msg("The account is not in a legacy layout that can be migrated")]
+    NotLegacyAccount = 1300,
+}
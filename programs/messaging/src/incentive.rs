@@ -0,0 +1,61 @@
+use anchor_lang::prelude::*;
+use crate::errors::MessagingErrorCode;
+use crate::shared_mailbox::SharedMailbox;
+
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    PartialEq,
+    Eq
+)]
+pub enum IncentiveType {
+    Token,
+    Lamports,
+    Nft,
+}
+
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    Default,
+    PartialEq,
+    Eq
+)]
+pub struct IncentiveConditions {
+    /// Unix timestamp after which the receiver can no longer claim and the sender or payer
+    /// can reclaim the escrow. No expiry means the incentive can only ever be claimed.
+    pub expires_at: Option<i64>,
+    /// The receiver can only claim in a transaction that also replies to the message
+    pub requires_reply: bool,
+}
+
+impl IncentiveConditions {
+    /// Replies are signed by the receiver, which nobody can do for a shared mailbox, so
+    /// incentives sent to one cannot require a reply.
+    pub fn validate_new(&self, now: i64, receiver: &AccountInfo) -> Result<()> {
+        if let Some(expires_at) = self.expires_at {
+            require!(expires_at > now, MessagingErrorCode::IncentiveExpiryInPast);
+        }
+        if self.requires_reply {
+            require!(SharedMailbox::load(receiver)?.is_none(), MessagingErrorCode::SharedMailboxCannotReply);
+        }
+        Ok(())
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        return match self.expires_at {
+            Some(expires_at) => now >= expires_at,
+            None => false,
+        };
+    }
+}
+
+/// Lamport incentives sit in the message account on top of its rent, so the program can move
+/// them directly without a CPI.
+pub fn release_lamport_incentive(message: &AccountInfo, destination: &AccountInfo, amount: u64) -> Result<()> {
+    **message.try_borrow_mut_lamports()? -= amount;
+    **destination.try_borrow_mut_lamports()? += amount;
+    Ok(())
+}
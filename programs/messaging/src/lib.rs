@@ -1,6 +1,44 @@
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program;
 use anchor_spl::{token, associated_token};
+use attachment::{Attachment, MAX_ATTACHMENTS, MAX_ATTACHMENT_LEN};
+use config::{FeeSchedule, ProtocolConfig, MAX_PASS_DISCOUNT_BPS};
+use contacts::Contacts;
+use delegates::{DelegatePermissions, DelegateScope, Delegates};
+use encryption::{EncryptionHeader, EncryptionKey, EncryptionScheme};
+use envelope::MessageEnvelope;
+use errors::MessagingErrorCode;
+use incentive::{IncentiveConditions, IncentiveType};
+use nft::NftIncentive;
+use outbox::Outbox;
+use rate_limit::SenderRateLimit;
+use postbox::nft_metadata::Metadata;
+use relay::SenderNonce;
+use ring::{RingMailbox, RING_ENTRY_DATA_LEN};
+use settings::{MailboxSettings, SettingsData, SettingsType};
+use shared_mailbox::{MemberRole, Member, SharedMailbox, MAX_SHARED_MAILBOX_NAME_LEN};
+use thread::MessageReference;
+
+mod attachment;
+mod broadcast;
+mod config;
+mod contacts;
+mod delegates;
+mod encryption;
+mod envelope;
+mod errors;
+mod incentive;
+mod migration;
+mod nft;
+mod outbox;
+mod postage;
+mod rate_limit;
+mod relay;
+mod ring;
+mod sender_restrictions;
+mod settings;
+mod shared_mailbox;
+mod thread;
 mod treasury;
 
 #[cfg(feature = "mainnet")]
@@ -8,83 +46,854 @@ declare_id!("BHJ4tRcogS88tUhYotPfYWDjR4q7MGdizdiguY3N54rb");
 #[cfg(not(feature = "mainnet"))]
 declare_id!("BHJ4tRcogS88tUhYotPfYWDjR4q7MGdizdiguY3N54rb");
 
-#[constant]
-const MESSAGE_FEE_LAMPORTS: u64 = 50000;
 const PROTOCOL_SEED: & str = "dispatch";
 const MAILBOX_SEED: & str = "mailbox";
 const MESSAGE_SEED: & str = "message";
+const CONFIG_SEED: & str = "config";
+const SENDER_NONCE_SEED: & str = "sender_nonce";
+/// Bumped whenever the layout of any event below changes
+const EVENT_VERSION: u8 = 5;
+const ENCRYPTION_KEY_SEED: & str = "encryption_key";
+const MAILBOX_SETTINGS_SEED: & str = "mailbox_settings";
+const CONTACTS_SEED: & str = "contacts";
+const DELEGATES_SEED: & str = "delegates";
+const SHARED_MAILBOX_SEED: & str = "shared_mailbox";
+const REQUEST_BOX_SEED: & str = "requests";
+const RING_MAILBOX_SEED: & str = "ring_mailbox";
+const ATTACHMENT_SEED: & str = "attachment";
+const OUTBOX_SEED: & str = "outbox";
+const RATE_LIMIT_SEED: & str = "rate_limit";
+
+/// Accounts and fee every send passes to the settings, fee and outbox bookkeeping below.
+struct SendContext<'a, 'info> {
+    sender: Pubkey,
+    payer: AccountInfo<'info>,
+    receiver: AccountInfo<'info>,
+    fee_receiver: AccountInfo<'info>,
+    fee: u64,
+    mailbox_settings: AccountInfo<'info>,
+    /// Only passed for the main mailbox; messages headed for the request queue are checked without it.
+    contacts: Option<AccountInfo<'info>>,
+    /// Recorded to if the sender created an outbox
+    outbox: AccountInfo<'info>,
+    rate_limit: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    extra_accounts: &'a [AccountInfo<'info>],
+}
+
+impl<'a, 'info> SendContext<'a, 'info> {
+    fn pay_fee(&self) -> Result<()> {
+        let ix = solana_program::system_instruction::transfer(&self.payer.key(), &self.fee_receiver.key(), self.fee);
+        solana_program::program::invoke(&ix, &[self.payer.clone(), self.fee_receiver.clone()])?;
+        Ok(())
+    }
+}
+
+/// Apply the receiver's mailbox settings to an incoming message. Messages headed for the
+/// request queue come without contacts. The rate limit is counted per sender for the mailbox
+/// the message is written to.
+fn enforce_mailbox_settings(mailbox: &Pubkey, send: &SendContext) -> Result<()> {
+    let settings = MailboxSettings::load(&send.mailbox_settings)?.unwrap_or_default();
+    if send.contacts.is_none() {
+        require!(settings.message_requests_enabled(), MessagingErrorCode::MessageRequestsNotEnabled);
+    }
+    // Notes to self are never charged or filtered
+    if send.sender == send.receiver.key() {
+        return Ok(());
+    }
+    let allow_list = match settings.get_setting(SettingsType::SenderAllowList) {
+        Some(SettingsData::SenderAllowList { rules }) => Some(rules),
+        _ => None,
+    };
+    let block_list = match settings.get_setting(SettingsType::SenderBlockList) {
+        Some(SettingsData::SenderBlockList { rules }) => Some(rules),
+        _ => None,
+    };
+    sender_restrictions::validate_sender_allowed(&send.sender, allow_list, block_list, send.extra_accounts)?;
+    if let (true, Some(contacts_info)) = (settings.message_requests_enabled(), &send.contacts) {
+        let is_contact = Contacts::load(contacts_info)?.map_or(false, |c| c.contacts.contains(&send.sender));
+        require!(is_contact, MessagingErrorCode::SenderNotInContacts);
+    }
+    if let Some(SettingsData::RateLimit { rule }) = settings.get_setting(SettingsType::RateLimit) {
+        rule.enforce(mailbox, &send.sender, &send.payer, &send.rate_limit, &send.system_program)?;
+    }
+    if let Some(SettingsData::Postage { postage }) = settings.get_setting(SettingsType::Postage) {
+        postage.pay(&send.payer, &send.receiver, send.extra_accounts)?;
+    }
+    Ok(())
+}
 
-fn inner_send_message<'info>(mailbox: &mut Mailbox, message: &mut Message, data: String, sender: Pubkey,
-                      payer: AccountInfo<'info>, receiver: Pubkey, fee_receiver: AccountInfo<'info>) -> Result<()> {
+/// Settings, fee and mailbox bookkeeping shared by every send to a mailbox; returns the new message's index.
+fn record_send<'info>(mailbox: &mut Account<'info, Mailbox>, send: &SendContext<'_, 'info>) -> Result<u32> {
+    enforce_mailbox_settings(&mailbox.key(), send)?;
+    // Whoever pays for the first message also paid to create the mailbox
+    if mailbox.payer == Pubkey::default() {
+        mailbox.payer = send.payer.key();
+        emit!(MailboxCreated {
+            version: EVENT_VERSION,
+            receiver_pubkey: send.receiver.key(),
+            mailbox_pubkey: mailbox.key(),
+            payer_pubkey: mailbox.payer,
+        });
+    }
     mailbox.message_count += 1;
-    message.sender = sender;
-    message.payer = payer.key();
-    message.data = data;
-    let ix = solana_program::system_instruction::transfer(&payer.key(), &fee_receiver.key(), MESSAGE_FEE_LAMPORTS);
-    solana_program::program::invoke(&ix, &[payer, fee_receiver])?;
+    send.pay_fee()?;
+    Ok(mailbox.message_count - 1)
+}
+
+/// Bookkeeping shared by every send. The caller fills in the message payload beforehand.
+fn inner_send_message<'info>(mailbox: &mut Account<'info, Mailbox>, message: &mut Message,
+                             send: SendContext<'_, 'info>) -> Result<()> {
+    message.payer = send.payer.key();
+    let message_index = record_send(mailbox, &send)?;
+    let kind = if send.contacts.is_some() { outbox::SENT_TO_MAILBOX } else { outbox::SENT_AS_REQUEST };
+    message.outbox_slot = outbox::record_sent(&send.outbox, send.receiver.key(), message_index, kind)?;
+    message.sender = send.sender;
     emit!(DispatchMessage {
+        version: EVENT_VERSION,
         sender_pubkey: message.sender,
-        receiver_pubkey: receiver,
-        message_index: mailbox.message_count - 1,
+        receiver_pubkey: send.receiver.key(),
+        mailbox_pubkey: mailbox.key(),
+        message_index,
         message: message.data.clone(),
+        encryption: message.encryption.clone(),
+        ciphertext: message.ciphertext.clone(),
+        reply_to: message.reply_to.clone(),
+        content_hash: None,
+        content_type: message.envelope.as_ref().map(|envelope| envelope.content_type.clone()),
     });
     Ok(())
 }
 
+/// Incentives that require a reply can only be claimed alongside one; returns its address.
+fn reply_to_claim(message: &Account<Message>, instructions: &AccountInfo) -> Result<Option<Pubkey>> {
+    if !message.incentive_conditions.requires_reply {
+        return Ok(None);
+    }
+    let reply_message = thread::find_reply_in_transaction(instructions, &message.key());
+    require!(reply_message.is_some(), MessagingErrorCode::ReplyRequiredToClaim);
+    Ok(reply_message)
+}
+
+/// Move the whole escrow out of the message-owned incentive account and close it.
+fn release_incentive<'info>(token_program: AccountInfo<'info>, message: AccountInfo<'info>,
+                            incentive_token_account: AccountInfo<'info>, destination_token_account: AccountInfo<'info>,
+                            rent_destination: AccountInfo<'info>, signer_seeds: &[&[&[u8]]], amount: u64) -> Result<()> {
+    let transfer_ctx = CpiContext::new_with_signer(token_program.clone(), token::Transfer {
+        authority: message.clone(),
+        from: incentive_token_account.clone(),
+        to: destination_token_account,
+    }, signer_seeds);
+    token::transfer(transfer_ctx, amount)?;
+
+    let close_ctx = CpiContext::new_with_signer(token_program, token::CloseAccount {
+        authority: message,
+        account: incentive_token_account,
+        destination: rent_destination,
+    }, signer_seeds);
+    token::close_account(close_ctx)?;
+    Ok(())
+}
+
 #[program]
 pub mod messaging {
     use super::*;
+    /// Create the protocol config. Only the program's upgrade authority may do this, and only
+    /// once; afterwards the config admin takes over through `update_config`.
+    pub fn initialize_config(ctx: Context<InitializeConfig>, admin: Pubkey, treasury: Pubkey, fees: FeeSchedule) -> Result<()> {
+        require!(fees.pass_discount_bps <= MAX_PASS_DISCOUNT_BPS, MessagingErrorCode::InvalidPassDiscount);
+        if let Some(programdata_address) = ctx.accounts.program.programdata_address()? {
+            require!(ctx.accounts.program_data.key() == programdata_address, MessagingErrorCode::NotUpgradeAuthority);
+            let program_data = Account::<ProgramData>::try_from(&ctx.accounts.program_data)?;
+            require!(
+                program_data.upgrade_authority_address == Some(ctx.accounts.authority.key()),
+                MessagingErrorCode::NotUpgradeAuthority,
+            );
+        }
+        // Programs loaded without an upgrade authority (e.g. by a local test validator) have
+        // nobody else to defer to, so whoever initializes first becomes the admin
+
+        let config = &mut ctx.accounts.config;
+        config.admin = admin;
+        config.treasury = treasury;
+        config.fees = fees;
+        emit!(ConfigUpdated {
+            version: EVENT_VERSION,
+            admin: config.admin,
+            treasury: config.treasury,
+            fees: config.fees.clone(),
+        });
+        Ok(())
+    }
+
+    /// Allow the config admin to change the fees, the treasury or hand over the admin role.
+    pub fn update_config(ctx: Context<UpdateConfig>, admin: Pubkey, treasury: Pubkey, fees: FeeSchedule) -> Result<()> {
+        require!(fees.pass_discount_bps <= MAX_PASS_DISCOUNT_BPS, MessagingErrorCode::InvalidPassDiscount);
+        let config = &mut ctx.accounts.config;
+        config.admin = admin;
+        config.treasury = treasury;
+        config.fees = fees;
+        resize_account(config.to_account_info().as_ref(), &ctx.accounts.admin, config.get_size())?;
+        emit!(ConfigUpdated {
+            version: EVENT_VERSION,
+            admin: config.admin,
+            treasury: config.treasury,
+            fees: config.fees.clone(),
+        });
+        Ok(())
+    }
+
     /// Send a message to the receiver. Note that anyone can create a mailbox for the receiver
     /// and send messages.
-    pub fn send_message(ctx: Context<SendMessage>, data: String) -> Result<()> {
+    pub fn send_message<'info>(ctx: Context<'_, '_, '_, 'info, SendMessage<'info>>, data: String) -> Result<()> {
+        ctx.accounts.message.data = data;
+        inner_send_message(
+            &mut ctx.accounts.mailbox,
+            &mut ctx.accounts.message,
+            SendContext {
+                sender: ctx.accounts.sender.key(),
+                payer: ctx.accounts.payer.to_account_info(),
+                receiver: ctx.accounts.receiver.to_account_info(),
+                fee_receiver: ctx.accounts.fee_receiver.to_account_info(),
+                fee: ctx.accounts.config.message_fee_for(&ctx.accounts.sender.key(), ctx.remaining_accounts),
+                mailbox_settings: ctx.accounts.mailbox_settings.to_account_info(),
+                contacts: Some(ctx.accounts.contacts.to_account_info()),
+                outbox: ctx.accounts.outbox.to_account_info(),
+                rate_limit: ctx.accounts.rate_limit.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                extra_accounts: ctx.remaining_accounts,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Start tracking the messages this sender sends, so they can be listed from one account.
+    pub fn create_outbox(ctx: Context<CreateOutbox>) -> Result<()> {
+        ctx.accounts.outbox.load_init()?;
+        Ok(())
+    }
+
+    /// Stop tracking sent messages and return the outbox rent to the sender.
+    pub fn close_outbox(_ctx: Context<CloseOutbox>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Add an empty attachment of `size` bytes to a message. Only the sender can attach, and the
+    /// message's payer pays for it so deleting the message can refund both together.
+    pub fn create_attachment(ctx: Context<CreateAttachment>, _message_index: u32, size: u32) -> Result<()> {
+        require!(size <= MAX_ATTACHMENT_LEN, MessagingErrorCode::AttachmentTooLarge);
+        let message = &mut ctx.accounts.message;
+        require!(message.attachment_count < MAX_ATTACHMENTS, MessagingErrorCode::TooManyAttachments);
+        message.attachment_count += 1;
+
+        let attachment = &mut ctx.accounts.attachment;
+        attachment.message = message.key();
+        attachment.data = vec![0; size as usize];
+        Ok(())
+    }
+
+    /// Write a chunk of an attachment starting at `offset`.
+    pub fn write_attachment(ctx: Context<UpdateAttachment>, _message_index: u32, _attachment_index: u8,
+                            offset: u32, chunk: Vec<u8>) -> Result<()> {
+        let attachment = &mut ctx.accounts.attachment;
+        require!(attachment.content_hash.is_none(), MessagingErrorCode::AttachmentAlreadyFinalized);
+        let start = offset as usize;
+        let end = start.checked_add(chunk.len()).filter(|end| *end <= attachment.data.len())
+            .ok_or(MessagingErrorCode::AttachmentWriteOutOfBounds)?;
+        attachment.data[start..end].copy_from_slice(&chunk);
+        Ok(())
+    }
+
+    /// Seal an attachment once every chunk is written. The hash has to match what was written,
+    /// so readers can trust a finalized attachment is complete.
+    pub fn finalize_attachment(ctx: Context<UpdateAttachment>, _message_index: u32, attachment_index: u8,
+                               content_hash: [u8; 32]) -> Result<()> {
+        let attachment = &mut ctx.accounts.attachment;
+        require!(attachment.content_hash.is_none(), MessagingErrorCode::AttachmentAlreadyFinalized);
+        require!(
+            solana_program::hash::hash(&attachment.data).to_bytes() == content_hash,
+            MessagingErrorCode::AttachmentHashMismatch,
+        );
+        attachment.content_hash = Some(content_hash);
+
+        emit!(AttachmentFinalized {
+            version: EVENT_VERSION,
+            message_pubkey: attachment.message,
+            attachment_pubkey: attachment.key(),
+            attachment_index,
+            size: attachment.data.len() as u32,
+            content_hash,
+        });
+        Ok(())
+    }
+
+    /// Send a message that only exists in the emitted event. It takes a message index and pays
+    /// the fee like any other send, but no message account is created; the event carries a hash
+    /// of the payload so indexers can check what they store. Receivers skip these indices with
+    /// `update_read_messages` since there is nothing to delete.
+    pub fn send_ephemeral_message<'info>(ctx: Context<'_, '_, '_, 'info, SendEphemeralMessage<'info>>, data: String) -> Result<()> {
+        let sender = ctx.accounts.sender.key();
+        let receiver = ctx.accounts.receiver.to_account_info();
+        let message_index = record_send(
+            &mut ctx.accounts.mailbox,
+            &SendContext {
+                sender,
+                payer: ctx.accounts.payer.to_account_info(),
+                receiver: receiver.clone(),
+                fee_receiver: ctx.accounts.fee_receiver.to_account_info(),
+                fee: ctx.accounts.config.message_fee_for(&sender, ctx.remaining_accounts),
+                mailbox_settings: ctx.accounts.mailbox_settings.to_account_info(),
+                contacts: Some(ctx.accounts.contacts.to_account_info()),
+                outbox: ctx.accounts.outbox.to_account_info(),
+                rate_limit: ctx.accounts.rate_limit.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                extra_accounts: ctx.remaining_accounts,
+            },
+        )?;
+        outbox::record_sent(&ctx.accounts.outbox, receiver.key(), message_index, outbox::SENT_EPHEMERAL)?;
+        emit!(DispatchMessage {
+            version: EVENT_VERSION,
+            sender_pubkey: sender,
+            receiver_pubkey: receiver.key(),
+            mailbox_pubkey: ctx.accounts.mailbox.key(),
+            message_index,
+            content_hash: Some(solana_program::hash::hash(data.as_bytes()).to_bytes()),
+            message: data,
+            encryption: None,
+            ciphertext: vec![],
+            reply_to: None,
+            content_type: None,
+        });
+        Ok(())
+    }
+
+    /// Create a ring mailbox for the receiver. Messages sent to it with `send_ring_message`
+    /// cost only the fee and overwrite the oldest once it is full.
+    pub fn create_ring_mailbox(ctx: Context<CreateRingMailbox>) -> Result<()> {
+        let mut ring_mailbox = ctx.accounts.ring_mailbox.load_init()?;
+        ring_mailbox.payer = ctx.accounts.receiver.key();
+        Ok(())
+    }
+
+    /// Send a message into the receiver's ring mailbox. The receiver's settings apply as they
+    /// do for their regular mailbox.
+    pub fn send_ring_message<'info>(ctx: Context<'_, '_, '_, 'info, SendRingMessage<'info>>, data: String) -> Result<()> {
+        require!(data.len() <= RING_ENTRY_DATA_LEN, MessagingErrorCode::RingMessageTooLong);
+        let sender = ctx.accounts.sender.key();
+        let receiver = ctx.accounts.receiver.to_account_info();
+        let send = SendContext {
+            sender,
+            payer: ctx.accounts.payer.to_account_info(),
+            receiver: receiver.clone(),
+            fee_receiver: ctx.accounts.fee_receiver.to_account_info(),
+            fee: ctx.accounts.config.message_fee_for(&sender, ctx.remaining_accounts),
+            mailbox_settings: ctx.accounts.mailbox_settings.to_account_info(),
+            contacts: Some(ctx.accounts.contacts.to_account_info()),
+            outbox: ctx.accounts.outbox.to_account_info(),
+            rate_limit: ctx.accounts.rate_limit.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+            extra_accounts: ctx.remaining_accounts,
+        };
+        enforce_mailbox_settings(&ctx.accounts.ring_mailbox.key(), &send)?;
+        send.pay_fee()?;
+
+        let message_index = ctx.accounts.ring_mailbox.load_mut()?.push(sender, Clock::get()?.unix_timestamp, data.as_bytes());
+        outbox::record_sent(&ctx.accounts.outbox, receiver.key(), message_index, outbox::SENT_TO_RING)?;
+        emit!(DispatchMessage {
+            version: EVENT_VERSION,
+            sender_pubkey: sender,
+            receiver_pubkey: receiver.key(),
+            mailbox_pubkey: ctx.accounts.ring_mailbox.key(),
+            message_index,
+            message: data,
+            encryption: None,
+            ciphertext: vec![],
+            reply_to: None,
+            content_hash: None,
+            content_type: None,
+        });
+        Ok(())
+    }
+
+    /// Allow the receiver or their delegate to mark ring mailbox messages as read.
+    pub fn update_ring_read_messages(ctx: Context<UpdateRingReadMessages>, read_messages: u32) -> Result<()> {
+        let mut ring_mailbox = ctx.accounts.ring_mailbox.load_mut()?;
+        require!(read_messages <= ring_mailbox.message_count, MessagingErrorCode::ReadCountExceedsMessageCount);
+        ring_mailbox.read_message_count = read_messages.max(ring_mailbox.oldest_message_index());
+
+        emit!(ReadCountUpdated {
+            version: EVENT_VERSION,
+            receiver_pubkey: ctx.accounts.receiver.key(),
+            mailbox_pubkey: ctx.accounts.ring_mailbox.key(),
+            read_message_count: ring_mailbox.read_message_count,
+        });
+        Ok(())
+    }
+
+    /// Send a message as a structured envelope instead of a free-form string. The envelope is
+    /// validated and stored in the message account; the event only carries its content type.
+    pub fn send_envelope_message<'info>(ctx: Context<'_, '_, '_, 'info, SendEnvelopeMessage<'info>>, envelope: MessageEnvelope) -> Result<()> {
+        envelope.validate()?;
+        ctx.accounts.message.envelope = Some(envelope);
+        inner_send_message(
+            &mut ctx.accounts.mailbox,
+            &mut ctx.accounts.message,
+            SendContext {
+                sender: ctx.accounts.sender.key(),
+                payer: ctx.accounts.payer.to_account_info(),
+                receiver: ctx.accounts.receiver.to_account_info(),
+                fee_receiver: ctx.accounts.fee_receiver.to_account_info(),
+                fee: ctx.accounts.config.message_fee_for(&ctx.accounts.sender.key(), ctx.remaining_accounts),
+                mailbox_settings: ctx.accounts.mailbox_settings.to_account_info(),
+                contacts: Some(ctx.accounts.contacts.to_account_info()),
+                outbox: ctx.accounts.outbox.to_account_info(),
+                rate_limit: ctx.accounts.rate_limit.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                extra_accounts: ctx.remaining_accounts,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Send a message that anyone can clean up with `cleanup_expired_message` once
+    /// `ttl_seconds` have passed, returning its rent to the payer.
+    pub fn send_expiring_message<'info>(ctx: Context<'_, '_, '_, 'info, SendExpiringMessage<'info>>, data: String, ttl_seconds: u32) -> Result<()> {
+        require!(ttl_seconds > 0, MessagingErrorCode::InvalidMessageTtl);
+        let message = &mut ctx.accounts.message;
+        message.data = data;
+        message.expires_at = Some(Clock::get()?.unix_timestamp + i64::from(ttl_seconds));
+        inner_send_message(
+            &mut ctx.accounts.mailbox,
+            message,
+            SendContext {
+                sender: ctx.accounts.sender.key(),
+                payer: ctx.accounts.payer.to_account_info(),
+                receiver: ctx.accounts.receiver.to_account_info(),
+                fee_receiver: ctx.accounts.fee_receiver.to_account_info(),
+                fee: ctx.accounts.config.message_fee_for(&ctx.accounts.sender.key(), ctx.remaining_accounts),
+                mailbox_settings: ctx.accounts.mailbox_settings.to_account_info(),
+                contacts: Some(ctx.accounts.contacts.to_account_info()),
+                outbox: ctx.accounts.outbox.to_account_info(),
+                rate_limit: ctx.accounts.rate_limit.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                extra_accounts: ctx.remaining_accounts,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Send a message on the sender's behalf without their signature on the transaction. The
+    /// sender signs `relay::relay_message_bytes` off-chain and the relayer submits that
+    /// signature through an ed25519 program instruction placed right before this one.
+    pub fn send_relayed_message<'info>(ctx: Context<'_, '_, '_, 'info, SendRelayedMessage<'info>>, data: String, nonce: u64) -> Result<()> {
+        let sender_nonce = &mut ctx.accounts.sender_nonce;
+        require!(nonce == sender_nonce.nonce, MessagingErrorCode::InvalidRelayNonce);
+        relay::verify_ed25519_signature(
+            &ctx.accounts.instructions,
+            &ctx.accounts.sender.key(),
+            &relay::relay_message_bytes(&ctx.accounts.receiver.key(), nonce, &data),
+        )?;
+        sender_nonce.nonce += 1;
+
+        ctx.accounts.message.data = data;
+        inner_send_message(
+            &mut ctx.accounts.mailbox,
+            &mut ctx.accounts.message,
+            SendContext {
+                sender: ctx.accounts.sender.key(),
+                payer: ctx.accounts.payer.to_account_info(),
+                receiver: ctx.accounts.receiver.to_account_info(),
+                fee_receiver: ctx.accounts.fee_receiver.to_account_info(),
+                fee: ctx.accounts.config.message_fee_for(&ctx.accounts.sender.key(), ctx.remaining_accounts),
+                mailbox_settings: ctx.accounts.mailbox_settings.to_account_info(),
+                contacts: Some(ctx.accounts.contacts.to_account_info()),
+                outbox: ctx.accounts.outbox.to_account_info(),
+                rate_limit: ctx.accounts.rate_limit.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                extra_accounts: ctx.remaining_accounts,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Reply to a message in the sender's own mailbox. The reply goes to the mailbox of whoever
+    /// sent the original, and points back at it so the thread can be followed on chain.
+    pub fn send_reply<'info>(ctx: Context<'_, '_, '_, 'info, SendReply<'info>>, data: String, original_index: u32) -> Result<()> {
+        let message = &mut ctx.accounts.message;
+        message.data = data;
+        message.reply_to = Some(MessageReference {
+            mailbox: ctx.accounts.original_mailbox.key(),
+            message_index: original_index,
+        });
+        inner_send_message(
+            &mut ctx.accounts.mailbox,
+            message,
+            SendContext {
+                sender: ctx.accounts.sender.key(),
+                payer: ctx.accounts.payer.to_account_info(),
+                receiver: ctx.accounts.receiver.to_account_info(),
+                fee_receiver: ctx.accounts.fee_receiver.to_account_info(),
+                fee: ctx.accounts.config.message_fee_for(&ctx.accounts.sender.key(), ctx.remaining_accounts),
+                mailbox_settings: ctx.accounts.mailbox_settings.to_account_info(),
+                contacts: Some(ctx.accounts.contacts.to_account_info()),
+                outbox: ctx.accounts.outbox.to_account_info(),
+                rate_limit: ctx.accounts.rate_limit.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                extra_accounts: ctx.remaining_accounts,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Send the same message to several receivers at once. Each receiver's accounts are passed
+    /// through `remaining_accounts` (see `broadcast::ACCOUNTS_PER_RECIPIENT`), followed by any
+    /// extra accounts the receivers' settings need. Mailboxes are created as needed and the
+    /// fee is charged once per receiver.
+    pub fn broadcast_message<'info>(ctx: Context<'_, '_, '_, 'info, BroadcastMessage<'info>>, data: String, recipient_count: u8) -> Result<()> {
+        let recipient_count = recipient_count as usize;
+        require!(recipient_count <= broadcast::MAX_BROADCAST_RECIPIENTS, MessagingErrorCode::TooManyRecipients);
+        let recipient_accounts_len = recipient_count * broadcast::ACCOUNTS_PER_RECIPIENT;
+        require!(recipient_count > 0 && ctx.remaining_accounts.len() >= recipient_accounts_len, MessagingErrorCode::InvalidBroadcastAccounts);
+        let (recipient_accounts, extra_accounts) = ctx.remaining_accounts.split_at(recipient_accounts_len);
+
+        let payer = ctx.accounts.payer.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+        let fee = ctx.accounts.config.message_fee_for(&ctx.accounts.sender.key(), extra_accounts);
+        for recipient in recipient_accounts.chunks(broadcast::ACCOUNTS_PER_RECIPIENT) {
+            let (receiver, mailbox_info, mailbox_settings, contacts, rate_limit, message_info) =
+                (&recipient[0], &recipient[1], &recipient[2], &recipient[3], &recipient[4], &recipient[5]);
+
+            let mailbox_seeds = [PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.key.as_ref()];
+            let (mailbox_key, mailbox_bump) = Pubkey::find_program_address(&mailbox_seeds, ctx.program_id);
+            require!(mailbox_info.key() == mailbox_key, MessagingErrorCode::InvalidBroadcastAccounts);
+            let (settings_key, _) = Pubkey::find_program_address(&[PROTOCOL_SEED.as_bytes(), MAILBOX_SETTINGS_SEED.as_bytes(), mailbox_key.as_ref()], ctx.program_id);
+            require!(mailbox_settings.key() == settings_key, MessagingErrorCode::InvalidBroadcastAccounts);
+            let (contacts_key, _) = Pubkey::find_program_address(&[PROTOCOL_SEED.as_bytes(), CONTACTS_SEED.as_bytes(), mailbox_key.as_ref()], ctx.program_id);
+            require!(contacts.key() == contacts_key, MessagingErrorCode::InvalidBroadcastAccounts);
+
+            let mut mailbox = if mailbox_info.data_is_empty() {
+                broadcast::init_pda_account::<Mailbox>(&payer, mailbox_info, &system_program, Mailbox::space(),
+                    &[PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.key.as_ref(), &[mailbox_bump]])?
+            } else {
+                Account::<Mailbox>::try_from(mailbox_info)?
+            };
+
+            let message_index = mailbox.message_count.to_le_bytes();
+            let (message_key, message_bump) = Pubkey::find_program_address(
+                &[PROTOCOL_SEED.as_bytes(), MESSAGE_SEED.as_bytes(), mailbox_key.as_ref(), &message_index], ctx.program_id);
+            require!(message_info.key() == message_key, MessagingErrorCode::InvalidBroadcastAccounts);
+            let mut message = broadcast::init_pda_account::<Message>(&payer, message_info, &system_program,
+                Message { data: data.clone(), outbox_slot: Some(0), ..Default::default() }.space(),
+                &[PROTOCOL_SEED.as_bytes(), MESSAGE_SEED.as_bytes(), mailbox_key.as_ref(), &message_index, &[message_bump]])?;
+            message.data = data.clone();
+
+            inner_send_message(
+                &mut mailbox,
+                &mut message,
+                SendContext {
+                    sender: ctx.accounts.sender.key(),
+                    payer: payer.clone(),
+                    receiver: receiver.clone(),
+                    fee_receiver: ctx.accounts.fee_receiver.to_account_info(),
+                    fee,
+                    mailbox_settings: mailbox_settings.clone(),
+                    contacts: Some(contacts.clone()),
+                    outbox: ctx.accounts.outbox.to_account_info(),
+                    rate_limit: rate_limit.clone(),
+                    system_program: system_program.clone(),
+                    extra_accounts,
+                },
+            )?;
+            mailbox.exit(ctx.program_id)?;
+            message.exit(ctx.program_id)?;
+        }
+        Ok(())
+    }
+
+    /// Send an end-to-end encrypted message. The program never sees the plaintext; it only
+    /// stores the encryption header next to the opaque ciphertext so the receiver can decrypt.
+    pub fn send_encrypted_message<'info>(ctx: Context<'_, '_, '_, 'info, SendEncryptedMessage<'info>>, header: EncryptionHeader, ciphertext: Vec<u8>) -> Result<()> {
+        let message = &mut ctx.accounts.message;
+        message.encryption = Some(header);
+        message.ciphertext = ciphertext;
         inner_send_message(
             &mut ctx.accounts.mailbox,
+            message,
+            SendContext {
+                sender: ctx.accounts.sender.key(),
+                payer: ctx.accounts.payer.to_account_info(),
+                receiver: ctx.accounts.receiver.to_account_info(),
+                fee_receiver: ctx.accounts.fee_receiver.to_account_info(),
+                fee: ctx.accounts.config.message_fee_for(&ctx.accounts.sender.key(), ctx.remaining_accounts),
+                mailbox_settings: ctx.accounts.mailbox_settings.to_account_info(),
+                contacts: Some(ctx.accounts.contacts.to_account_info()),
+                outbox: ctx.accounts.outbox.to_account_info(),
+                rate_limit: ctx.accounts.rate_limit.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                extra_accounts: ctx.remaining_accounts,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Publish (or rotate) the receiver's encryption public key so senders can discover it
+    /// from the receiver's mailbox address.
+    pub fn set_encryption_key(ctx: Context<SetEncryptionKey>, scheme: EncryptionScheme, public_key: [u8; 32]) -> Result<()> {
+        let encryption_key = &mut ctx.accounts.encryption_key;
+        encryption_key.scheme = scheme;
+        encryption_key.public_key = public_key;
+        Ok(())
+    }
+
+    /// Allow the receiver, or an admin of a shared mailbox, to configure the mailbox, e.g. the postage senders must pay them.
+    pub fn add_or_update_setting(ctx: Context<AddOrUpdateSetting>, settings_data: SettingsData) -> Result<()> {
+        if let SettingsData::RateLimit { rule } = &settings_data {
+            rule.validate()?;
+        }
+        let mailbox_settings = &mut ctx.accounts.mailbox_settings;
+        mailbox_settings.settings.retain(|s| s.get_type() != settings_data.get_type());
+        mailbox_settings.settings.push(settings_data);
+        resize_account(mailbox_settings.to_account_info().as_ref(), &ctx.accounts.authority, mailbox_settings.get_size())?;
+        Ok(())
+    }
+
+    /// Send a message into the receiver's request queue. Receivers with message requests
+    /// enabled only take messages from their contacts in the main mailbox.
+    pub fn send_message_request<'info>(ctx: Context<'_, '_, '_, 'info, SendMessageRequest<'info>>, data: String) -> Result<()> {
+        ctx.accounts.message.data = data;
+        inner_send_message(
+            &mut ctx.accounts.request_box,
             &mut ctx.accounts.message,
-            data,
-            ctx.accounts.sender.key(),
-            ctx.accounts.payer.to_account_info(),
-            ctx.accounts.receiver.key(),
-            ctx.accounts.fee_receiver.to_account_info(),
+            SendContext {
+                sender: ctx.accounts.sender.key(),
+                payer: ctx.accounts.payer.to_account_info(),
+                receiver: ctx.accounts.receiver.to_account_info(),
+                fee_receiver: ctx.accounts.fee_receiver.to_account_info(),
+                fee: ctx.accounts.config.message_fee_for(&ctx.accounts.sender.key(), ctx.remaining_accounts),
+                mailbox_settings: ctx.accounts.mailbox_settings.to_account_info(),
+                contacts: None,
+                outbox: ctx.accounts.outbox.to_account_info(),
+                rate_limit: ctx.accounts.rate_limit.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                extra_accounts: ctx.remaining_accounts,
+            },
         )?;
         Ok(())
     }
 
+    /// Allow the receiver to add a sender to their contacts, so the sender's future messages
+    /// go to the main mailbox.
+    pub fn accept_sender(ctx: Context<AcceptSender>, sender: Pubkey) -> Result<()> {
+        let contacts = &mut ctx.accounts.contacts;
+        if !contacts.contacts.contains(&sender) {
+            contacts.contacts.push(sender);
+            resize_account(contacts.to_account_info().as_ref(), &ctx.accounts.authority, contacts.get_size())?;
+        }
+        Ok(())
+    }
+
+    /// Allow the receiver to let another key or a program PDA manage their mailbox within the
+    /// given permissions. Setting no permissions removes the delegate.
+    pub fn set_delegate(ctx: Context<SetDelegate>, delegate: Pubkey, permissions: DelegatePermissions) -> Result<()> {
+        let delegates = &mut ctx.accounts.delegates;
+        delegates.set(delegate, permissions);
+        resize_account(delegates.to_account_info().as_ref(), &ctx.accounts.authority, delegates.get_size())?;
+        Ok(())
+    }
+
+    /// Create a named inbox that several members can manage. Messages are sent to the shared
+    /// mailbox's address as the receiver.
+    pub fn create_shared_mailbox(ctx: Context<CreateSharedMailbox>, name: String, members: Vec<Member>) -> Result<()> {
+        require!(
+            !name.is_empty() && name.len() <= MAX_SHARED_MAILBOX_NAME_LEN,
+            MessagingErrorCode::InvalidSharedMailboxName,
+        );
+        let shared_mailbox = &mut ctx.accounts.shared_mailbox;
+        shared_mailbox.owner = ctx.accounts.owner.key();
+        shared_mailbox.name = name;
+        for member in members {
+            shared_mailbox.set_member(member.key, Some(member.role));
+        }
+        Ok(())
+    }
+
+    /// Allow the owner or an admin to add, change or, with no role, remove a member.
+    pub fn set_shared_mailbox_member(ctx: Context<SetSharedMailboxMember>, member: Pubkey, role: Option<MemberRole>) -> Result<()> {
+        let shared_mailbox = &mut ctx.accounts.shared_mailbox;
+        require!(shared_mailbox.is_admin(&ctx.accounts.authority.key()), MessagingErrorCode::NotSharedMailboxAdmin);
+        shared_mailbox.set_member(member, role);
+        resize_account(shared_mailbox.to_account_info().as_ref(), &ctx.accounts.authority, shared_mailbox.get_size())?;
+        Ok(())
+    }
+
+    /// Allow the receiver, or an admin of a shared mailbox, to reject a pending message request
+    /// and send rent to the original payer. Like deleting, rejecting the first pending request
+    /// advances the read pointer.
+    pub fn reject_message_request(ctx: Context<RejectMessageRequest>, message_index: u32) -> Result<()> {
+        outbox::remove_sent(&ctx.accounts.outbox, ctx.accounts.message.outbox_slot, ctx.accounts.receiver.key(), message_index,
+                            outbox::SENT_AS_REQUEST)?;
+        let request_box = &mut ctx.accounts.request_box;
+        if message_index == request_box.read_message_count && request_box.read_message_count < request_box.message_count {
+            request_box.read_message_count += 1;
+        }
+
+        emit!(MessageDeleted {
+            version: EVENT_VERSION,
+            receiver_pubkey: ctx.accounts.receiver.key(),
+            mailbox_pubkey: request_box.key(),
+            message_index,
+            deleted_by: ctx.accounts.authority.key(),
+            read_message_count: request_box.read_message_count,
+        });
+        Ok(())
+    }
+
+    /// Allow the receiver to close their mailbox and return its rent to whoever paid for it.
+    /// Every message ever sent to it must already be deleted, as a reopened mailbox counts
+    /// from zero again; their addresses are passed in `remaining_accounts`, in order from
+    /// `close_checked_count`, to show that. Messages behind the read pointer are checked too,
+    /// since it can be moved past messages that still exist. Long mailboxes are checked over
+    /// several calls, and the call that checks the last message closes the mailbox.
+    pub fn close_mailbox(ctx: Context<CloseMailbox>) -> Result<()> {
+        let mailbox = &mut ctx.accounts.mailbox;
+        let unchecked = mailbox.message_count - mailbox.close_checked_count;
+        for message_info in ctx.remaining_accounts.iter().take(unchecked as usize) {
+            let message_key = message_address(&mailbox.key(), mailbox.close_checked_count);
+            // Anyone can send lamports to a deleted message's address, so only look for its data
+            let deleted = message_info.data_is_empty() || message_info.owner != ctx.program_id;
+            require!(message_info.key() == message_key && deleted, MessagingErrorCode::MailboxNotEmpty);
+            mailbox.close_checked_count += 1;
+        }
+        if mailbox.close_checked_count < mailbox.message_count {
+            return Ok(());
+        }
+
+        mailbox.close(ctx.accounts.rent_destination.to_account_info())?;
+        emit!(MailboxClosed {
+            version: EVENT_VERSION,
+            receiver_pubkey: ctx.accounts.receiver.key(),
+            mailbox_pubkey: mailbox.key(),
+        });
+        Ok(())
+    }
+
     /// Delete an arbitrary message account and send rent to the original payer. Only the
-    /// sender, payer, or receiver is allowed to call this function. If the account being
-    /// deleted is the first remaining message, increment the read message count pointer.
-    pub fn delete_message(ctx: Context<DeleteMessage>, message_index: u32) -> Result<()> {
+    /// sender, payer, receiver, or a delegate or shared mailbox member with delete rights is allowed to call
+    /// this function. If the account being deleted is the first remaining message, increment the read
+    /// message count pointer.
+    pub fn delete_message<'info>(ctx: Context<'_, '_, '_, 'info, DeleteMessage<'info>>, message_index: u32) -> Result<()> {
+        // Closing the message would hand the escrowed lamports straight back to the payer, and
+        // strand escrowed tokens in an account only the message can sign for
+        require!(ctx.accounts.message.incentive_lamports == 0, MessagingErrorCode::LamportIncentiveOutstanding);
+        require!(
+            ctx.accounts.message.incentive_mint == Pubkey::default() && ctx.accounts.message.incentive_nft.is_none(),
+            MessagingErrorCode::TokenIncentiveOutstanding,
+        );
+        attachment::close_attachments(
+            &ctx.accounts.message.key(),
+            ctx.accounts.message.attachment_count,
+            ctx.remaining_accounts,
+            &ctx.accounts.rent_destination,
+        )?;
+        outbox::remove_sent(&ctx.accounts.outbox, ctx.accounts.message.outbox_slot, ctx.accounts.receiver.key(), message_index,
+                            outbox::SENT_TO_MAILBOX)?;
+
+        let mailbox = &mut ctx.accounts.mailbox;
+        if message_index == mailbox.read_message_count && mailbox.read_message_count < mailbox.message_count {
+            mailbox.read_message_count += 1;
+        }
+
+        emit!(MessageDeleted {
+            version: EVENT_VERSION,
+            receiver_pubkey: ctx.accounts.receiver.key(),
+            mailbox_pubkey: mailbox.key(),
+            message_index,
+            deleted_by: ctx.accounts.authorized_deleter.key(),
+            read_message_count: mailbox.read_message_count,
+        });
+        Ok(())
+    }
+
+    /// Close an expired message and send rent to the original payer. Anyone can crank this;
+    /// like deleting, cleaning up the first remaining message advances the read pointer.
+    pub fn cleanup_expired_message<'info>(ctx: Context<'_, '_, '_, 'info, CleanupExpiredMessage<'info>>, message_index: u32) -> Result<()> {
+        let expires_at = ctx.accounts.message.expires_at.ok_or(MessagingErrorCode::MessageHasNoExpiry)?;
+        require!(Clock::get()?.unix_timestamp >= expires_at, MessagingErrorCode::MessageNotExpired);
+        attachment::close_attachments(
+            &ctx.accounts.message.key(),
+            ctx.accounts.message.attachment_count,
+            ctx.remaining_accounts,
+            &ctx.accounts.rent_destination,
+        )?;
+        outbox::remove_sent(&ctx.accounts.outbox, ctx.accounts.message.outbox_slot, ctx.accounts.receiver.key(), message_index,
+                            outbox::SENT_TO_MAILBOX)?;
+
         let mailbox = &mut ctx.accounts.mailbox;
         if message_index == mailbox.read_message_count && mailbox.read_message_count < mailbox.message_count {
             mailbox.read_message_count += 1;
         }
 
+        emit!(MessageDeleted {
+            version: EVENT_VERSION,
+            receiver_pubkey: ctx.accounts.receiver.key(),
+            mailbox_pubkey: mailbox.key(),
+            message_index,
+            deleted_by: ctx.accounts.cranker.key(),
+            read_message_count: mailbox.read_message_count,
+        });
+        Ok(())
+    }
+
+    /// Close a sender's rate limit account once its window has ended and send the rent to
+    /// whoever paid for it. Anyone can crank this; the sender's next message to the mailbox
+    /// simply starts a new window.
+    pub fn close_rate_limit(ctx: Context<CloseRateLimit>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require!(now >= ctx.accounts.rate_limit.window_end, MessagingErrorCode::RateLimitWindowOpen);
         Ok(())
     }
 
-    /// Allow the receiver to update the count of read messages in case others have deleted
-    /// and a gap has formed.
+    /// Allow the receiver or their delegate to update the count of read messages in case
+    /// others have deleted and a gap has formed.
     pub fn update_read_messages(ctx: Context<UpdateReadMessages>, read_messages: u32) -> Result<()> {
         let mailbox = &mut ctx.accounts.mailbox;
+        require!(read_messages <= mailbox.message_count, MessagingErrorCode::ReadCountExceedsMessageCount);
         mailbox.read_message_count = read_messages;
 
-        if mailbox.read_message_count > mailbox.message_count {
-            return Err(Error::from(ProgramError::InvalidArgument).with_source(source!()));
-        }
-
+        emit!(ReadCountUpdated {
+            version: EVENT_VERSION,
+            receiver_pubkey: ctx.accounts.receiver.key(),
+            mailbox_pubkey: mailbox.key(),
+            read_message_count: read_messages,
+        });
         Ok(())
     }
 
-    /// Send a message while creating an attachment
-    pub fn send_message_with_incentive(ctx: Context<SendMessageWithIncentive>,  data: String, incentive_amount: u64) -> Result<()> {
+    /// Send a message while creating an attachment. If the conditions carry an expiry, the
+    /// sender or payer can reclaim the incentive once it passes.
+    pub fn send_message_with_incentive<'info>(ctx: Context<'_, '_, '_, 'info, SendMessageWithIncentive<'info>>,  data: String, incentive_amount: u64,
+        conditions: IncentiveConditions,
+    ) -> Result<()> {
+        conditions.validate_new(Clock::get()?.unix_timestamp, &ctx.accounts.receiver)?;
         let message = &mut ctx.accounts.message;
+        message.data = data;
+        message.incentive_conditions = conditions;
         inner_send_message(
             &mut ctx.accounts.mailbox,
             message,
-            data,
-            ctx.accounts.sender.key(),
-            ctx.accounts.payer.to_account_info(),
-            ctx.accounts.receiver.key(),
-            ctx.accounts.fee_receiver.to_account_info(),
+            SendContext {
+                sender: ctx.accounts.sender.key(),
+                payer: ctx.accounts.payer.to_account_info(),
+                receiver: ctx.accounts.receiver.to_account_info(),
+                fee_receiver: ctx.accounts.fee_receiver.to_account_info(),
+                fee: ctx.accounts.config.message_fee_for(&ctx.accounts.sender.key(), ctx.remaining_accounts),
+                mailbox_settings: ctx.accounts.mailbox_settings.to_account_info(),
+                contacts: Some(ctx.accounts.contacts.to_account_info()),
+                outbox: ctx.accounts.outbox.to_account_info(),
+                rate_limit: ctx.accounts.rate_limit.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                extra_accounts: ctx.remaining_accounts,
+            },
         )?;
         message.incentive_mint = ctx.accounts.incentive_mint.key();
 
@@ -98,205 +907,1595 @@ pub mod messaging {
         Ok(())
     }
 
-    /// Allow the receiver to claim the incentive payment
+    /// Send a message with a Metaplex NFT as a gift, escrowed like a token incentive. The
+    /// receiver claims it with `claim_incentive`, or the sender or payer reclaims it with
+    /// `refund_incentive` once the conditions expire.
+    pub fn send_message_with_nft<'info>(ctx: Context<'_, '_, '_, 'info, SendMessageWithNft<'info>>, data: String,
+        conditions: IncentiveConditions,
+    ) -> Result<()> {
+        let nft_mint = &ctx.accounts.incentive_mint;
+        require!(nft_mint.decimals == 0 && nft_mint.supply == 1, MessagingErrorCode::NotAnNft);
+        require!(ctx.accounts.nft_metadata.mint == nft_mint.key(), MessagingErrorCode::InvalidNftMetadata);
+        conditions.validate_new(Clock::get()?.unix_timestamp, &ctx.accounts.receiver)?;
+        let message = &mut ctx.accounts.message;
+        message.data = data;
+        message.incentive_conditions = conditions;
+        message.incentive_nft = Some(NftIncentive::from_metadata(&ctx.accounts.nft_metadata));
+        inner_send_message(
+            &mut ctx.accounts.mailbox,
+            message,
+            SendContext {
+                sender: ctx.accounts.sender.key(),
+                payer: ctx.accounts.payer.to_account_info(),
+                receiver: ctx.accounts.receiver.to_account_info(),
+                fee_receiver: ctx.accounts.fee_receiver.to_account_info(),
+                fee: ctx.accounts.config.message_fee_for(&ctx.accounts.sender.key(), ctx.remaining_accounts),
+                mailbox_settings: ctx.accounts.mailbox_settings.to_account_info(),
+                contacts: Some(ctx.accounts.contacts.to_account_info()),
+                outbox: ctx.accounts.outbox.to_account_info(),
+                rate_limit: ctx.accounts.rate_limit.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                extra_accounts: ctx.remaining_accounts,
+            },
+        )?;
+        message.incentive_mint = ctx.accounts.incentive_mint.key();
+
+        let transfer_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), token::Transfer {
+            authority: ctx.accounts.payer.to_account_info(),
+            from: ctx.accounts.payer_token_account.to_account_info(),
+            to: ctx.accounts.incentive_token_account.to_account_info(),
+        });
+        token::transfer(transfer_ctx, 1)?;
+
+        Ok(())
+    }
+
+    /// Send a message with a lamport incentive, escrowed in the message account itself.
+    pub fn send_message_with_sol_incentive<'info>(ctx: Context<'_, '_, '_, 'info, SendMessageWithSolIncentive<'info>>, data: String, incentive_lamports: u64,
+        conditions: IncentiveConditions,
+    ) -> Result<()> {
+        require!(incentive_lamports > 0, MessagingErrorCode::IncentiveAmountZero);
+        conditions.validate_new(Clock::get()?.unix_timestamp, &ctx.accounts.receiver)?;
+        let message = &mut ctx.accounts.message;
+        message.data = data;
+        message.incentive_conditions = conditions;
+        message.incentive_lamports = incentive_lamports;
+        inner_send_message(
+            &mut ctx.accounts.mailbox,
+            message,
+            SendContext {
+                sender: ctx.accounts.sender.key(),
+                payer: ctx.accounts.payer.to_account_info(),
+                receiver: ctx.accounts.receiver.to_account_info(),
+                fee_receiver: ctx.accounts.fee_receiver.to_account_info(),
+                fee: ctx.accounts.config.message_fee_for(&ctx.accounts.sender.key(), ctx.remaining_accounts),
+                mailbox_settings: ctx.accounts.mailbox_settings.to_account_info(),
+                contacts: Some(ctx.accounts.contacts.to_account_info()),
+                outbox: ctx.accounts.outbox.to_account_info(),
+                rate_limit: ctx.accounts.rate_limit.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                extra_accounts: ctx.remaining_accounts,
+            },
+        )?;
+
+        treasury::transfer_lamports(&ctx.accounts.payer, &ctx.accounts.message.to_account_info(), incentive_lamports)?;
+        Ok(())
+    }
+
+    /// Allow the receiver or their delegate to claim the incentive payment for the receiver
     pub fn claim_incentive(ctx: Context<ClaimIncentive>, message_index: u32) -> Result<()> {
+        require!(
+            !ctx.accounts.message.incentive_conditions.is_expired(Clock::get()?.unix_timestamp),
+            MessagingErrorCode::IncentiveExpired,
+        );
+        let reply_message = reply_to_claim(&ctx.accounts.message, &ctx.accounts.instructions)?;
+
         let incentive_amount = ctx.accounts.incentive_token_account.amount;
         let mailbox_address = ctx.accounts.mailbox.key();
+        let (_, message_bump) = find_message_address(&mailbox_address, message_index);
 
         let signer_seeds: &[&[&[u8]]] = &[&[
             PROTOCOL_SEED.as_bytes(),
             MESSAGE_SEED.as_bytes(),
             mailbox_address.as_ref(),
             &message_index.to_le_bytes(),
-            &[*ctx.bumps.get("message").unwrap()],
+            &[message_bump],
         ]];
 
-        let transfer_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), token::Transfer {
-            authority: ctx.accounts.message.to_account_info(),
-            from: ctx.accounts.incentive_token_account.to_account_info(),
-            to: ctx.accounts.receiver_token_account.to_account_info(),
-        }, signer_seeds);
-        token::transfer(transfer_ctx, incentive_amount)?;
-
-        let close_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), token::CloseAccount {
-            authority: ctx.accounts.message.to_account_info(),
-            account: ctx.accounts.incentive_token_account.to_account_info(),
-            destination: ctx.accounts.rent_destination.to_account_info(),
-        }, signer_seeds);
-        token::close_account(close_ctx)?;
+        release_incentive(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.message.to_account_info(),
+            ctx.accounts.incentive_token_account.to_account_info(),
+            ctx.accounts.receiver_token_account.to_account_info(),
+            ctx.accounts.rent_destination.to_account_info(),
+            signer_seeds,
+            incentive_amount,
+        )?;
 
         ctx.accounts.message.incentive_mint = Pubkey::default();
+        let incentive_nft = ctx.accounts.message.incentive_nft.take();
 
         emit!(IncentiveClaimed {
+            version: EVENT_VERSION,
             sender_pubkey: ctx.accounts.message.sender,
             receiver_pubkey: ctx.accounts.receiver.key(),
             message_index: message_index,
             mint: ctx.accounts.incentive_token_account.mint,
             amount: incentive_amount,
+            incentive_type: if incentive_nft.is_some() { IncentiveType::Nft } else { IncentiveType::Token },
+            collection: incentive_nft.and_then(|nft| nft.collection),
+            reply_message,
+        });
+
+        Ok(())
+    }
+
+    /// Allow the receiver or their delegate to claim a lamport incentive for the receiver
+    pub fn claim_sol_incentive(ctx: Context<ClaimSolIncentive>, message_index: u32) -> Result<()> {
+        let message = &mut ctx.accounts.message;
+        require!(message.incentive_lamports > 0, MessagingErrorCode::NoLamportIncentive);
+        require!(
+            !message.incentive_conditions.is_expired(Clock::get()?.unix_timestamp),
+            MessagingErrorCode::IncentiveExpired,
+        );
+        let reply_message = reply_to_claim(message, &ctx.accounts.instructions)?;
+
+        let incentive_amount = message.incentive_lamports;
+        incentive::release_lamport_incentive(
+            &message.to_account_info(),
+            &ctx.accounts.beneficiary.to_account_info(),
+            incentive_amount,
+        )?;
+        message.incentive_lamports = 0;
+
+        emit!(IncentiveClaimed {
+            version: EVENT_VERSION,
+            sender_pubkey: message.sender,
+            receiver_pubkey: ctx.accounts.receiver.key(),
+            message_index: message_index,
+            mint: Pubkey::default(),
+            amount: incentive_amount,
+            incentive_type: IncentiveType::Lamports,
+            collection: None,
+            reply_message,
         });
 
-        Ok(())
-    }
+        Ok(())
+    }
+
+    /// Allow the sender or payer to take back an unclaimed incentive once it has expired.
+    /// The tokens go back to the payer, who funded the escrow.
+    pub fn refund_incentive(ctx: Context<RefundIncentive>, message_index: u32) -> Result<()> {
+        let conditions = &ctx.accounts.message.incentive_conditions;
+        require!(conditions.expires_at.is_some(), MessagingErrorCode::IncentiveHasNoExpiry);
+        require!(conditions.is_expired(Clock::get()?.unix_timestamp), MessagingErrorCode::IncentiveNotExpired);
+
+        let incentive_amount = ctx.accounts.incentive_token_account.amount;
+        let mailbox_address = ctx.accounts.mailbox.key();
+        let (_, message_bump) = find_message_address(&mailbox_address, message_index);
+
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            PROTOCOL_SEED.as_bytes(),
+            MESSAGE_SEED.as_bytes(),
+            mailbox_address.as_ref(),
+            &message_index.to_le_bytes(),
+            &[message_bump],
+        ]];
+
+        release_incentive(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.message.to_account_info(),
+            ctx.accounts.incentive_token_account.to_account_info(),
+            ctx.accounts.refund_token_account.to_account_info(),
+            ctx.accounts.rent_destination.to_account_info(),
+            signer_seeds,
+            incentive_amount,
+        )?;
+
+        ctx.accounts.message.incentive_mint = Pubkey::default();
+        let incentive_nft = ctx.accounts.message.incentive_nft.take();
+
+        emit!(IncentiveRefunded {
+            version: EVENT_VERSION,
+            sender_pubkey: ctx.accounts.message.sender,
+            receiver_pubkey: ctx.accounts.receiver.key(),
+            message_index: message_index,
+            mint: ctx.accounts.incentive_token_account.mint,
+            amount: incentive_amount,
+            incentive_type: if incentive_nft.is_some() { IncentiveType::Nft } else { IncentiveType::Token },
+            collection: incentive_nft.and_then(|nft| nft.collection),
+        });
+
+        Ok(())
+    }
+
+    /// Allow the sender or payer to take back an expired lamport incentive. The lamports go
+    /// back to the payer.
+    pub fn refund_sol_incentive(ctx: Context<RefundSolIncentive>, message_index: u32) -> Result<()> {
+        let message = &mut ctx.accounts.message;
+        require!(message.incentive_lamports > 0, MessagingErrorCode::NoLamportIncentive);
+        require!(message.incentive_conditions.expires_at.is_some(), MessagingErrorCode::IncentiveHasNoExpiry);
+        require!(
+            message.incentive_conditions.is_expired(Clock::get()?.unix_timestamp),
+            MessagingErrorCode::IncentiveNotExpired,
+        );
+
+        let incentive_amount = message.incentive_lamports;
+        incentive::release_lamport_incentive(
+            &message.to_account_info(),
+            &ctx.accounts.rent_destination.to_account_info(),
+            incentive_amount,
+        )?;
+        message.incentive_lamports = 0;
+
+        emit!(IncentiveRefunded {
+            version: EVENT_VERSION,
+            sender_pubkey: message.sender,
+            receiver_pubkey: ctx.accounts.receiver.key(),
+            message_index: message_index,
+            mint: Pubkey::default(),
+            amount: incentive_amount,
+            incentive_type: IncentiveType::Lamports,
+            collection: None,
+        });
+
+        Ok(())
+    }
+
+    /// Rewrite a mailbox created before mailboxes recorded their payer in the current layout,
+    /// so messages can be sent to it again. Anyone can migrate a mailbox and pays the rent for
+    /// the larger account.
+    pub fn migrate_mailbox(ctx: Context<MigrateMailbox>) -> Result<()> {
+        migration::migrate_mailbox(&ctx.accounts.mailbox, &ctx.accounts.receiver.key(), &ctx.accounts.payer)?;
+        Ok(())
+    }
+
+    /// Rewrite a message sent before fields were appended to `Message` in the current layout,
+    /// so it can be read, deleted and closed again. Anyone can migrate a message and pays the
+    /// rent for the larger account.
+    pub fn migrate_message(ctx: Context<MigrateMessage>, _message_index: u32) -> Result<()> {
+        migration::migrate_message(&ctx.accounts.message, &ctx.accounts.payer)?;
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(admin: Pubkey, treasury: Pubkey, fees: FeeSchedule)]
+pub struct InitializeConfig<'info> {
+    #[account(init,
+        payer = authority,
+        space = ProtocolConfig { fees: fees.clone(), ..Default::default() }.get_size(),
+        seeds = [PROTOCOL_SEED.as_bytes(), CONFIG_SEED.as_bytes()],
+        bump,
+    )]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub program: Program<'info, crate::program::Messaging>,
+    /// CHECK: only read when the program is upgradeable, checked against its programdata address in the body
+    pub program_data: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(mut,
+        address = config::config_address() @ MessagingErrorCode::InvalidConfigAccount,
+    )]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    #[account(mut, address = config.admin @ MessagingErrorCode::NotConfigAdmin)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(data: String)]
+pub struct SendMessage<'info> {
+    #[account(init_if_needed,
+        payer = payer,
+        space = Mailbox::space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.key().as_ref()],
+        bump,
+    )]
+    pub mailbox: Box<Account<'info, Mailbox>>,
+    /// CHECK: we do not access the data in the receiver other than to transfer postage to it
+    #[account(mut)]
+    pub receiver: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never changed a setting, loaded through MailboxSettings::load
+    #[account(
+        address = settings::settings_address(&mailbox.key()) @ MessagingErrorCode::InvalidSettingsAccount,
+    )]
+    pub mailbox_settings: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never accepted a sender, loaded through Contacts::load
+    #[account(
+        address = contacts::contacts_address(&mailbox.key()) @ MessagingErrorCode::InvalidContactsAccount,
+    )]
+    pub contacts: UncheckedAccount<'info>,
+    /// CHECK: only created once the receiver sets a rate limit, loaded through RateLimitRule::enforce
+    #[account(mut,
+        address = rate_limit::rate_limit_address(&mailbox.key(), &sender.key()) @ MessagingErrorCode::InvalidRateLimitAccount,
+    )]
+    pub rate_limit: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the sender never created an outbox, loaded through outbox::record_sent
+    #[account(mut,
+        address = outbox::outbox_address(&sender.key()) @ MessagingErrorCode::InvalidOutboxAccount,
+    )]
+    pub outbox: UncheckedAccount<'info>,
+
+    #[account(init,
+        payer = payer,
+        space = Message { data: data.clone(), outbox_slot: Some(0), ..Default::default() }.space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MESSAGE_SEED.as_bytes(), mailbox.key().as_ref(), &mailbox.message_count.to_le_bytes()],
+        bump,
+    )]
+    pub message: Box<Account<'info, Message>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub sender: Signer<'info>,
+    #[account(
+        address = config::config_address() @ MessagingErrorCode::InvalidConfigAccount,
+    )]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    /// CHECK: we do not access the data in the fee_receiver other than to transfer lamports to it
+    #[account(mut,
+        address = config.treasury @ MessagingErrorCode::InvalidFeeReceiver,
+    )]
+    pub fee_receiver: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(envelope: MessageEnvelope)]
+pub struct SendEnvelopeMessage<'info> {
+    #[account(init_if_needed,
+        payer = payer,
+        space = Mailbox::space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.key().as_ref()],
+        bump,
+    )]
+    pub mailbox: Box<Account<'info, Mailbox>>,
+    /// CHECK: we do not access the data in the receiver other than to transfer postage to it
+    #[account(mut)]
+    pub receiver: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never changed a setting, loaded through MailboxSettings::load
+    #[account(
+        address = settings::settings_address(&mailbox.key()) @ MessagingErrorCode::InvalidSettingsAccount,
+    )]
+    pub mailbox_settings: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never accepted a sender, loaded through Contacts::load
+    #[account(
+        address = contacts::contacts_address(&mailbox.key()) @ MessagingErrorCode::InvalidContactsAccount,
+    )]
+    pub contacts: UncheckedAccount<'info>,
+    /// CHECK: only created once the receiver sets a rate limit, loaded through RateLimitRule::enforce
+    #[account(mut,
+        address = rate_limit::rate_limit_address(&mailbox.key(), &sender.key()) @ MessagingErrorCode::InvalidRateLimitAccount,
+    )]
+    pub rate_limit: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the sender never created an outbox, loaded through outbox::record_sent
+    #[account(mut,
+        address = outbox::outbox_address(&sender.key()) @ MessagingErrorCode::InvalidOutboxAccount,
+    )]
+    pub outbox: UncheckedAccount<'info>,
+
+    #[account(init,
+        payer = payer,
+        space = Message { envelope: Some(envelope.clone()), outbox_slot: Some(0), ..Default::default() }.space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MESSAGE_SEED.as_bytes(), mailbox.key().as_ref(), &mailbox.message_count.to_le_bytes()],
+        bump,
+    )]
+    pub message: Box<Account<'info, Message>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub sender: Signer<'info>,
+    #[account(
+        address = config::config_address() @ MessagingErrorCode::InvalidConfigAccount,
+    )]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    /// CHECK: we do not access the data in the fee_receiver other than to transfer lamports to it
+    #[account(mut,
+        address = config.treasury @ MessagingErrorCode::InvalidFeeReceiver,
+    )]
+    pub fee_receiver: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreateOutbox<'info> {
+    #[account(init,
+        payer = sender,
+        space = 8 + std::mem::size_of::<Outbox>(),
+        seeds = [PROTOCOL_SEED.as_bytes(), OUTBOX_SEED.as_bytes(), sender.key().as_ref()],
+        bump,
+    )]
+    pub outbox: AccountLoader<'info, Outbox>,
+    #[account(mut)]
+    pub sender: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseOutbox<'info> {
+    #[account(mut,
+        close = sender,
+        address = outbox::outbox_address(&sender.key()) @ MessagingErrorCode::InvalidOutboxAccount,
+    )]
+    pub outbox: AccountLoader<'info, Outbox>,
+    #[account(mut)]
+    pub sender: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(message_index: u32, size: u32)]
+pub struct CreateAttachment<'info> {
+    #[account(
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
+    pub mailbox: Box<Account<'info, Mailbox>>,
+    /// CHECK: we only include receiver for their public key and do not access the account
+    /// and verify it based on the PDA of the mailbox
+    pub receiver: UncheckedAccount<'info>,
+    #[account(mut,
+        constraint = message.sender == sender.key() @ MessagingErrorCode::NotMessageSender,
+        address = message_address(&mailbox.key(), message_index) @ MessagingErrorCode::InvalidMessageAccount,
+    )]
+    pub message: Box<Account<'info, Message>>,
+    #[account(init,
+        payer = payer,
+        space = Attachment::space(size),
+        seeds = [PROTOCOL_SEED.as_bytes(), ATTACHMENT_SEED.as_bytes(), message.key().as_ref(), &[message.attachment_count]],
+        bump,
+    )]
+    pub attachment: Box<Account<'info, Attachment>>,
+
+    pub sender: Signer<'info>,
+    #[account(mut,
+        address = message.payer @ MessagingErrorCode::RentDestinationNotPayer,
+    )]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(message_index: u32, attachment_index: u8)]
+pub struct UpdateAttachment<'info> {
+    #[account(
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
+    pub mailbox: Box<Account<'info, Mailbox>>,
+    /// CHECK: we only include receiver for their public key and do not access the account
+    /// and verify it based on the PDA of the mailbox
+    pub receiver: UncheckedAccount<'info>,
+    #[account(
+        constraint = message.sender == sender.key() @ MessagingErrorCode::NotMessageSender,
+        address = message_address(&mailbox.key(), message_index) @ MessagingErrorCode::InvalidMessageAccount,
+    )]
+    pub message: Box<Account<'info, Message>>,
+    #[account(mut,
+        address = attachment::attachment_address(&message.key(), attachment_index) @ MessagingErrorCode::InvalidAttachmentAccount,
+    )]
+    pub attachment: Box<Account<'info, Attachment>>,
+
+    pub sender: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SendEphemeralMessage<'info> {
+    #[account(init_if_needed,
+        payer = payer,
+        space = Mailbox::space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.key().as_ref()],
+        bump,
+    )]
+    pub mailbox: Box<Account<'info, Mailbox>>,
+    /// CHECK: we do not access the data in the receiver other than to transfer postage to it
+    #[account(mut)]
+    pub receiver: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never changed a setting, loaded through MailboxSettings::load
+    #[account(
+        address = settings::settings_address(&mailbox.key()) @ MessagingErrorCode::InvalidSettingsAccount,
+    )]
+    pub mailbox_settings: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never accepted a sender, loaded through Contacts::load
+    #[account(
+        address = contacts::contacts_address(&mailbox.key()) @ MessagingErrorCode::InvalidContactsAccount,
+    )]
+    pub contacts: UncheckedAccount<'info>,
+    /// CHECK: only created once the receiver sets a rate limit, loaded through RateLimitRule::enforce
+    #[account(mut,
+        address = rate_limit::rate_limit_address(&mailbox.key(), &sender.key()) @ MessagingErrorCode::InvalidRateLimitAccount,
+    )]
+    pub rate_limit: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the sender never created an outbox, loaded through outbox::record_sent
+    #[account(mut,
+        address = outbox::outbox_address(&sender.key()) @ MessagingErrorCode::InvalidOutboxAccount,
+    )]
+    pub outbox: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub sender: Signer<'info>,
+    #[account(
+        address = config::config_address() @ MessagingErrorCode::InvalidConfigAccount,
+    )]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    /// CHECK: we do not access the data in the fee_receiver other than to transfer lamports to it
+    #[account(mut,
+        address = config.treasury @ MessagingErrorCode::InvalidFeeReceiver,
+    )]
+    pub fee_receiver: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreateRingMailbox<'info> {
+    #[account(init,
+        payer = receiver,
+        space = 8 + std::mem::size_of::<RingMailbox>(),
+        seeds = [PROTOCOL_SEED.as_bytes(), RING_MAILBOX_SEED.as_bytes(), receiver.key().as_ref()],
+        bump,
+    )]
+    pub ring_mailbox: AccountLoader<'info, RingMailbox>,
+    #[account(mut)]
+    pub receiver: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SendRingMessage<'info> {
+    #[account(mut,
+        address = ring::ring_mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidRingMailboxAccount,
+    )]
+    pub ring_mailbox: AccountLoader<'info, RingMailbox>,
+    /// CHECK: we do not access the data in the receiver other than to transfer postage to it
+    #[account(mut)]
+    pub receiver: UncheckedAccount<'info>,
+    /// CHECK: we only use the regular mailbox address to derive the receiver's settings PDAs
+    #[account(
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
+    pub mailbox: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never changed a setting, loaded through MailboxSettings::load
+    #[account(
+        address = settings::settings_address(&mailbox.key()) @ MessagingErrorCode::InvalidSettingsAccount,
+    )]
+    pub mailbox_settings: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never accepted a sender, loaded through Contacts::load
+    #[account(
+        address = contacts::contacts_address(&mailbox.key()) @ MessagingErrorCode::InvalidContactsAccount,
+    )]
+    pub contacts: UncheckedAccount<'info>,
+    /// CHECK: only created once the receiver sets a rate limit, loaded through RateLimitRule::enforce
+    #[account(mut,
+        address = rate_limit::rate_limit_address(&ring_mailbox.key(), &sender.key()) @ MessagingErrorCode::InvalidRateLimitAccount,
+    )]
+    pub rate_limit: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the sender never created an outbox, loaded through outbox::record_sent
+    #[account(mut,
+        address = outbox::outbox_address(&sender.key()) @ MessagingErrorCode::InvalidOutboxAccount,
+    )]
+    pub outbox: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub sender: Signer<'info>,
+    #[account(
+        address = config::config_address() @ MessagingErrorCode::InvalidConfigAccount,
+    )]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    /// CHECK: we do not access the data in the fee_receiver other than to transfer lamports to it
+    #[account(mut,
+        address = config.treasury @ MessagingErrorCode::InvalidFeeReceiver,
+    )]
+    pub fee_receiver: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateRingReadMessages<'info> {
+    #[account(mut,
+        address = ring::ring_mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidRingMailboxAccount,
+    )]
+    pub ring_mailbox: AccountLoader<'info, RingMailbox>,
+    /// CHECK: we only include receiver for their public key and do not access the account
+    /// and verify it based on the PDA of the ring mailbox
+    pub receiver: UncheckedAccount<'info>,
+    #[account(
+        constraint = delegates::is_authorized(&receiver, &authority.key(), &delegates, DelegateScope::UpdateReadMessages)
+            @ MessagingErrorCode::NotMailboxAuthority,
+    )]
+    pub authority: Signer<'info>,
+    /// CHECK: we only use the regular mailbox address to derive the delegates PDA
+    #[account(
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
+    pub mailbox: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never delegated, loaded through Delegates::load
+    #[account(
+        address = delegates::delegates_address(&mailbox.key()) @ MessagingErrorCode::InvalidDelegatesAccount,
+    )]
+    pub delegates: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(data: String)]
+pub struct SendExpiringMessage<'info> {
+    #[account(init_if_needed,
+        payer = payer,
+        space = Mailbox::space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.key().as_ref()],
+        bump,
+    )]
+    pub mailbox: Box<Account<'info, Mailbox>>,
+    /// CHECK: we do not access the data in the receiver other than to transfer postage to it
+    #[account(mut)]
+    pub receiver: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never changed a setting, loaded through MailboxSettings::load
+    #[account(
+        address = settings::settings_address(&mailbox.key()) @ MessagingErrorCode::InvalidSettingsAccount,
+    )]
+    pub mailbox_settings: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never accepted a sender, loaded through Contacts::load
+    #[account(
+        address = contacts::contacts_address(&mailbox.key()) @ MessagingErrorCode::InvalidContactsAccount,
+    )]
+    pub contacts: UncheckedAccount<'info>,
+    /// CHECK: only created once the receiver sets a rate limit, loaded through RateLimitRule::enforce
+    #[account(mut,
+        address = rate_limit::rate_limit_address(&mailbox.key(), &sender.key()) @ MessagingErrorCode::InvalidRateLimitAccount,
+    )]
+    pub rate_limit: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the sender never created an outbox, loaded through outbox::record_sent
+    #[account(mut,
+        address = outbox::outbox_address(&sender.key()) @ MessagingErrorCode::InvalidOutboxAccount,
+    )]
+    pub outbox: UncheckedAccount<'info>,
+
+    #[account(init,
+        payer = payer,
+        space = Message { data: data.clone(), expires_at: Some(0), outbox_slot: Some(0), ..Default::default() }.space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MESSAGE_SEED.as_bytes(), mailbox.key().as_ref(), &mailbox.message_count.to_le_bytes()],
+        bump,
+    )]
+    pub message: Box<Account<'info, Message>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub sender: Signer<'info>,
+    #[account(
+        address = config::config_address() @ MessagingErrorCode::InvalidConfigAccount,
+    )]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    /// CHECK: we do not access the data in the fee_receiver other than to transfer lamports to it
+    #[account(mut,
+        address = config.treasury @ MessagingErrorCode::InvalidFeeReceiver,
+    )]
+    pub fee_receiver: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(data: String, incentive_amount: u64, conditions: IncentiveConditions)]
+pub struct SendMessageWithIncentive<'info> {
+    #[account(init_if_needed,
+        payer = payer,
+        space = Mailbox::space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.key().as_ref()],
+        bump,
+    )]
+    pub mailbox: Box<Account<'info, Mailbox>>,
+    /// CHECK: we do not access the data in the receiver other than to transfer postage to it
+    #[account(mut)]
+    pub receiver: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never changed a setting, loaded through MailboxSettings::load
+    #[account(
+        address = settings::settings_address(&mailbox.key()) @ MessagingErrorCode::InvalidSettingsAccount,
+    )]
+    pub mailbox_settings: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never accepted a sender, loaded through Contacts::load
+    #[account(
+        address = contacts::contacts_address(&mailbox.key()) @ MessagingErrorCode::InvalidContactsAccount,
+    )]
+    pub contacts: UncheckedAccount<'info>,
+    /// CHECK: only created once the receiver sets a rate limit, loaded through RateLimitRule::enforce
+    #[account(mut,
+        address = rate_limit::rate_limit_address(&mailbox.key(), &sender.key()) @ MessagingErrorCode::InvalidRateLimitAccount,
+    )]
+    pub rate_limit: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the sender never created an outbox, loaded through outbox::record_sent
+    #[account(mut,
+        address = outbox::outbox_address(&sender.key()) @ MessagingErrorCode::InvalidOutboxAccount,
+    )]
+    pub outbox: UncheckedAccount<'info>,
+
+    #[account(init,
+        payer = payer,
+        space = Message { data: data.clone(), incentive_conditions: conditions.clone(), outbox_slot: Some(0), ..Default::default() }.space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MESSAGE_SEED.as_bytes(), mailbox.key().as_ref(), &mailbox.message_count.to_le_bytes()],
+        bump,
+    )]
+    pub message: Box<Account<'info, Message>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub sender: Signer<'info>,
+    #[account(
+        address = config::config_address() @ MessagingErrorCode::InvalidConfigAccount,
+    )]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    /// CHECK: we do not access the data in the fee_receiver other than to transfer lamports to it
+    #[account(mut,
+        address = config.treasury @ MessagingErrorCode::InvalidFeeReceiver,
+    )]
+    pub fee_receiver: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    pub incentive_mint: Box<Account<'info, token::Mint>>,
+    #[account(mut, associated_token::mint=incentive_mint, associated_token::authority=payer)]
+    pub payer_token_account: Box<Account<'info, token::TokenAccount>>,
+    #[account(init, payer=payer, associated_token::mint=incentive_mint, associated_token::authority=message)]
+    pub incentive_token_account: Box<Account<'info, token::TokenAccount>>,
+
+    pub token_program: Program<'info, token::Token>,
+    pub associated_token_program: Program<'info, associated_token::AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(data: String, conditions: IncentiveConditions)]
+pub struct SendMessageWithNft<'info> {
+    #[account(init_if_needed,
+        payer = payer,
+        space = Mailbox::space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.key().as_ref()],
+        bump,
+    )]
+    pub mailbox: Box<Account<'info, Mailbox>>,
+    /// CHECK: we do not access the data in the receiver other than to transfer postage to it
+    #[account(mut)]
+    pub receiver: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never changed a setting, loaded through MailboxSettings::load
+    #[account(
+        address = settings::settings_address(&mailbox.key()) @ MessagingErrorCode::InvalidSettingsAccount,
+    )]
+    pub mailbox_settings: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never accepted a sender, loaded through Contacts::load
+    #[account(
+        address = contacts::contacts_address(&mailbox.key()) @ MessagingErrorCode::InvalidContactsAccount,
+    )]
+    pub contacts: UncheckedAccount<'info>,
+    /// CHECK: only created once the receiver sets a rate limit, loaded through RateLimitRule::enforce
+    #[account(mut,
+        address = rate_limit::rate_limit_address(&mailbox.key(), &sender.key()) @ MessagingErrorCode::InvalidRateLimitAccount,
+    )]
+    pub rate_limit: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the sender never created an outbox, loaded through outbox::record_sent
+    #[account(mut,
+        address = outbox::outbox_address(&sender.key()) @ MessagingErrorCode::InvalidOutboxAccount,
+    )]
+    pub outbox: UncheckedAccount<'info>,
+
+    #[account(init,
+        payer = payer,
+        space = Message {
+            data: data.clone(),
+            incentive_conditions: conditions.clone(),
+            incentive_nft: Some(NftIncentive { collection: Some(Pubkey::default()) }),
+            outbox_slot: Some(0),
+            ..Default::default()
+        }.space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MESSAGE_SEED.as_bytes(), mailbox.key().as_ref(), &mailbox.message_count.to_le_bytes()],
+        bump,
+    )]
+    pub message: Box<Account<'info, Message>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub sender: Signer<'info>,
+    #[account(
+        address = config::config_address() @ MessagingErrorCode::InvalidConfigAccount,
+    )]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    /// CHECK: we do not access the data in the fee_receiver other than to transfer lamports to it
+    #[account(mut,
+        address = config.treasury @ MessagingErrorCode::InvalidFeeReceiver,
+    )]
+    pub fee_receiver: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    pub incentive_mint: Box<Account<'info, token::Mint>>,
+    #[account(address = nft::metadata_address(&incentive_mint.key()) @ MessagingErrorCode::InvalidNftMetadata)]
+    pub nft_metadata: Box<Account<'info, Metadata>>,
+    #[account(mut, associated_token::mint=incentive_mint, associated_token::authority=payer)]
+    pub payer_token_account: Box<Account<'info, token::TokenAccount>>,
+    #[account(init, payer=payer, associated_token::mint=incentive_mint, associated_token::authority=message)]
+    pub incentive_token_account: Box<Account<'info, token::TokenAccount>>,
+
+    pub token_program: Program<'info, token::Token>,
+    pub associated_token_program: Program<'info, associated_token::AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(data: String, incentive_lamports: u64, conditions: IncentiveConditions)]
+pub struct SendMessageWithSolIncentive<'info> {
+    #[account(init_if_needed,
+        payer = payer,
+        space = Mailbox::space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.key().as_ref()],
+        bump,
+    )]
+    pub mailbox: Box<Account<'info, Mailbox>>,
+    /// CHECK: we do not access the data in the receiver other than to transfer postage to it
+    #[account(mut)]
+    pub receiver: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never changed a setting, loaded through MailboxSettings::load
+    #[account(
+        address = settings::settings_address(&mailbox.key()) @ MessagingErrorCode::InvalidSettingsAccount,
+    )]
+    pub mailbox_settings: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never accepted a sender, loaded through Contacts::load
+    #[account(
+        address = contacts::contacts_address(&mailbox.key()) @ MessagingErrorCode::InvalidContactsAccount,
+    )]
+    pub contacts: UncheckedAccount<'info>,
+    /// CHECK: only created once the receiver sets a rate limit, loaded through RateLimitRule::enforce
+    #[account(mut,
+        address = rate_limit::rate_limit_address(&mailbox.key(), &sender.key()) @ MessagingErrorCode::InvalidRateLimitAccount,
+    )]
+    pub rate_limit: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the sender never created an outbox, loaded through outbox::record_sent
+    #[account(mut,
+        address = outbox::outbox_address(&sender.key()) @ MessagingErrorCode::InvalidOutboxAccount,
+    )]
+    pub outbox: UncheckedAccount<'info>,
+
+    #[account(init,
+        payer = payer,
+        space = Message { data: data.clone(), incentive_conditions: conditions.clone(), outbox_slot: Some(0), ..Default::default() }.space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MESSAGE_SEED.as_bytes(), mailbox.key().as_ref(), &mailbox.message_count.to_le_bytes()],
+        bump,
+    )]
+    pub message: Box<Account<'info, Message>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub sender: Signer<'info>,
+    #[account(
+        address = config::config_address() @ MessagingErrorCode::InvalidConfigAccount,
+    )]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    /// CHECK: we do not access the data in the fee_receiver other than to transfer lamports to it
+    #[account(mut,
+        address = config.treasury @ MessagingErrorCode::InvalidFeeReceiver,
+    )]
+    pub fee_receiver: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(header: EncryptionHeader, ciphertext: Vec<u8>)]
+pub struct SendEncryptedMessage<'info> {
+    #[account(init_if_needed,
+        payer = payer,
+        space = Mailbox::space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.key().as_ref()],
+        bump,
+    )]
+    pub mailbox: Box<Account<'info, Mailbox>>,
+    /// CHECK: we do not access the data in the receiver other than to transfer postage to it
+    #[account(mut)]
+    pub receiver: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never changed a setting, loaded through MailboxSettings::load
+    #[account(
+        address = settings::settings_address(&mailbox.key()) @ MessagingErrorCode::InvalidSettingsAccount,
+    )]
+    pub mailbox_settings: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never accepted a sender, loaded through Contacts::load
+    #[account(
+        address = contacts::contacts_address(&mailbox.key()) @ MessagingErrorCode::InvalidContactsAccount,
+    )]
+    pub contacts: UncheckedAccount<'info>,
+    /// CHECK: only created once the receiver sets a rate limit, loaded through RateLimitRule::enforce
+    #[account(mut,
+        address = rate_limit::rate_limit_address(&mailbox.key(), &sender.key()) @ MessagingErrorCode::InvalidRateLimitAccount,
+    )]
+    pub rate_limit: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the sender never created an outbox, loaded through outbox::record_sent
+    #[account(mut,
+        address = outbox::outbox_address(&sender.key()) @ MessagingErrorCode::InvalidOutboxAccount,
+    )]
+    pub outbox: UncheckedAccount<'info>,
+
+    #[account(init,
+        payer = payer,
+        space = Message { encryption: Some(header.clone()), ciphertext: ciphertext.clone(), outbox_slot: Some(0), ..Default::default() }.space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MESSAGE_SEED.as_bytes(), mailbox.key().as_ref(), &mailbox.message_count.to_le_bytes()],
+        bump,
+    )]
+    pub message: Box<Account<'info, Message>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub sender: Signer<'info>,
+    #[account(
+        address = config::config_address() @ MessagingErrorCode::InvalidConfigAccount,
+    )]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    /// CHECK: we do not access the data in the fee_receiver other than to transfer lamports to it
+    #[account(mut,
+        address = config.treasury @ MessagingErrorCode::InvalidFeeReceiver,
+    )]
+    pub fee_receiver: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetEncryptionKey<'info> {
+    /// CHECK: we only use the mailbox address to derive the key PDA, it does not need to exist yet
+    #[account(
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
+    pub mailbox: UncheckedAccount<'info>,
+    /// CHECK: we only include receiver for their public key and do not access the account
+    /// and verify it based on the PDA of the mailbox
+    pub receiver: UncheckedAccount<'info>,
+    #[account(mut,
+        constraint = delegates::is_mailbox_admin(&receiver, &authority.key()) @ MessagingErrorCode::NotMailboxAuthority,
+    )]
+    pub authority: Signer<'info>,
+
+    #[account(init_if_needed,
+        payer = authority,
+        space = 8 + 1 + 32,
+        seeds = [PROTOCOL_SEED.as_bytes(), ENCRYPTION_KEY_SEED.as_bytes(), mailbox.key().as_ref()],
+        bump,
+    )]
+    pub encryption_key: Box<Account<'info, EncryptionKey>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(data: String)]
+pub struct SendRelayedMessage<'info> {
+    #[account(init_if_needed,
+        payer = payer,
+        space = Mailbox::space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.key().as_ref()],
+        bump,
+    )]
+    pub mailbox: Box<Account<'info, Mailbox>>,
+    /// CHECK: we do not access the data in the receiver other than to transfer postage to it
+    #[account(mut)]
+    pub receiver: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never changed a setting, loaded through MailboxSettings::load
+    #[account(
+        address = settings::settings_address(&mailbox.key()) @ MessagingErrorCode::InvalidSettingsAccount,
+    )]
+    pub mailbox_settings: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never accepted a sender, loaded through Contacts::load
+    #[account(
+        address = contacts::contacts_address(&mailbox.key()) @ MessagingErrorCode::InvalidContactsAccount,
+    )]
+    pub contacts: UncheckedAccount<'info>,
+    /// CHECK: only created once the receiver sets a rate limit, loaded through RateLimitRule::enforce
+    #[account(mut,
+        address = rate_limit::rate_limit_address(&mailbox.key(), &sender.key()) @ MessagingErrorCode::InvalidRateLimitAccount,
+    )]
+    pub rate_limit: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the sender never created an outbox, loaded through outbox::record_sent
+    #[account(mut,
+        address = outbox::outbox_address(&sender.key()) @ MessagingErrorCode::InvalidOutboxAccount,
+    )]
+    pub outbox: UncheckedAccount<'info>,
+
+    #[account(init,
+        payer = payer,
+        space = Message { data: data.clone(), outbox_slot: Some(0), ..Default::default() }.space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MESSAGE_SEED.as_bytes(), mailbox.key().as_ref(), &mailbox.message_count.to_le_bytes()],
+        bump,
+    )]
+    pub message: Box<Account<'info, Message>>,
+
+    /// The relayer, who pays for the accounts and the fee
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: authorized through the ed25519 signature checked in the body instead of signing
+    pub sender: UncheckedAccount<'info>,
+    #[account(init_if_needed,
+        payer = payer,
+        space = 8 + 8,
+        seeds = [PROTOCOL_SEED.as_bytes(), SENDER_NONCE_SEED.as_bytes(), sender.key().as_ref()],
+        bump,
+    )]
+    pub sender_nonce: Box<Account<'info, SenderNonce>>,
+    #[account(
+        address = config::config_address() @ MessagingErrorCode::InvalidConfigAccount,
+    )]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    /// CHECK: we do not access the data in the fee_receiver other than to transfer lamports to it
+    #[account(mut,
+        address = config.treasury @ MessagingErrorCode::InvalidFeeReceiver,
+    )]
+    pub fee_receiver: UncheckedAccount<'info>,
+    /// CHECK: the instructions sysvar, read to find the ed25519 signature check
+    #[account(address = solana_program::sysvar::instructions::ID @ MessagingErrorCode::InvalidInstructionsSysvar)]
+    pub instructions: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(data: String, original_index: u32)]
+pub struct SendReply<'info> {
+    #[account(
+        address = mailbox_address(&sender.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
+    pub original_mailbox: Box<Account<'info, Mailbox>>,
+    #[account(
+        address = message_address(&original_mailbox.key(), original_index) @ MessagingErrorCode::InvalidMessageAccount,
+        constraint = original_message.sender == receiver.key() @ MessagingErrorCode::ReplyReceiverNotOriginalSender,
+    )]
+    pub original_message: Box<Account<'info, Message>>,
+
+    #[account(init_if_needed,
+        payer = payer,
+        space = Mailbox::space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.key().as_ref()],
+        bump,
+    )]
+    pub mailbox: Box<Account<'info, Mailbox>>,
+    /// CHECK: checked against the original message sender, otherwise only used to transfer postage to it
+    #[account(mut)]
+    pub receiver: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never changed a setting, loaded through MailboxSettings::load
+    #[account(
+        address = settings::settings_address(&mailbox.key()) @ MessagingErrorCode::InvalidSettingsAccount,
+    )]
+    pub mailbox_settings: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never accepted a sender, loaded through Contacts::load
+    #[account(
+        address = contacts::contacts_address(&mailbox.key()) @ MessagingErrorCode::InvalidContactsAccount,
+    )]
+    pub contacts: UncheckedAccount<'info>,
+    /// CHECK: only created once the receiver sets a rate limit, loaded through RateLimitRule::enforce
+    #[account(mut,
+        address = rate_limit::rate_limit_address(&mailbox.key(), &sender.key()) @ MessagingErrorCode::InvalidRateLimitAccount,
+    )]
+    pub rate_limit: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the sender never created an outbox, loaded through outbox::record_sent
+    #[account(mut,
+        address = outbox::outbox_address(&sender.key()) @ MessagingErrorCode::InvalidOutboxAccount,
+    )]
+    pub outbox: UncheckedAccount<'info>,
+
+    #[account(init,
+        payer = payer,
+        space = Message { data: data.clone(), reply_to: Some(MessageReference::default()), outbox_slot: Some(0), ..Default::default() }.space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MESSAGE_SEED.as_bytes(), mailbox.key().as_ref(), &mailbox.message_count.to_le_bytes()],
+        bump,
+    )]
+    pub message: Box<Account<'info, Message>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub sender: Signer<'info>,
+    #[account(
+        address = config::config_address() @ MessagingErrorCode::InvalidConfigAccount,
+    )]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    /// CHECK: we do not access the data in the fee_receiver other than to transfer lamports to it
+    #[account(mut,
+        address = config.treasury @ MessagingErrorCode::InvalidFeeReceiver,
+    )]
+    pub fee_receiver: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BroadcastMessage<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub sender: Signer<'info>,
+    /// CHECK: may not exist if the sender never created an outbox, loaded through outbox::record_sent
+    #[account(mut,
+        address = outbox::outbox_address(&sender.key()) @ MessagingErrorCode::InvalidOutboxAccount,
+    )]
+    pub outbox: UncheckedAccount<'info>,
+    #[account(
+        address = config::config_address() @ MessagingErrorCode::InvalidConfigAccount,
+    )]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    /// CHECK: we do not access the data in the fee_receiver other than to transfer lamports to it
+    #[account(mut,
+        address = config.treasury @ MessagingErrorCode::InvalidFeeReceiver,
+    )]
+    pub fee_receiver: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(data: String)]
+pub struct SendMessageRequest<'info> {
+    /// CHECK: we only use the mailbox address to derive the request queue and settings PDAs
+    #[account(
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
+    pub mailbox: UncheckedAccount<'info>,
+    #[account(init_if_needed,
+        payer = payer,
+        space = Mailbox::space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), REQUEST_BOX_SEED.as_bytes(), mailbox.key().as_ref()],
+        bump,
+    )]
+    pub request_box: Box<Account<'info, Mailbox>>,
+    /// CHECK: we do not access the data in the receiver other than to transfer postage to it
+    #[account(mut)]
+    pub receiver: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the receiver never changed a setting, loaded through MailboxSettings::load
+    #[account(
+        address = settings::settings_address(&mailbox.key()) @ MessagingErrorCode::InvalidSettingsAccount,
+    )]
+    pub mailbox_settings: UncheckedAccount<'info>,
+    /// CHECK: only created once the receiver sets a rate limit, loaded through RateLimitRule::enforce
+    #[account(mut,
+        address = rate_limit::rate_limit_address(&request_box.key(), &sender.key()) @ MessagingErrorCode::InvalidRateLimitAccount,
+    )]
+    pub rate_limit: UncheckedAccount<'info>,
+    /// CHECK: may not exist if the sender never created an outbox, loaded through outbox::record_sent
+    #[account(mut,
+        address = outbox::outbox_address(&sender.key()) @ MessagingErrorCode::InvalidOutboxAccount,
+    )]
+    pub outbox: UncheckedAccount<'info>,
+
+    #[account(init,
+        payer = payer,
+        space = Message { data: data.clone(), outbox_slot: Some(0), ..Default::default() }.space(),
+        seeds = [PROTOCOL_SEED.as_bytes(), MESSAGE_SEED.as_bytes(), request_box.key().as_ref(), &request_box.message_count.to_le_bytes()],
+        bump,
+    )]
+    pub message: Box<Account<'info, Message>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub sender: Signer<'info>,
+    #[account(
+        address = config::config_address() @ MessagingErrorCode::InvalidConfigAccount,
+    )]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    /// CHECK: we do not access the data in the fee_receiver other than to transfer lamports to it
+    #[account(mut,
+        address = config.treasury @ MessagingErrorCode::InvalidFeeReceiver,
+    )]
+    pub fee_receiver: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptSender<'info> {
+    /// CHECK: we only use the mailbox address to derive the contacts PDA, it does not need to exist yet
+    #[account(
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
+    pub mailbox: UncheckedAccount<'info>,
+    /// CHECK: we only include receiver for their public key and do not access the account
+    /// and verify it based on the PDA of the mailbox
+    pub receiver: UncheckedAccount<'info>,
+    #[account(mut,
+        constraint = delegates::is_mailbox_admin(&receiver, &authority.key()) @ MessagingErrorCode::NotMailboxAuthority,
+    )]
+    pub authority: Signer<'info>,
+
+    #[account(init_if_needed,
+        payer = authority,
+        space = 8 + 4,
+        seeds = [PROTOCOL_SEED.as_bytes(), CONTACTS_SEED.as_bytes(), mailbox.key().as_ref()],
+        bump,
+    )]
+    pub contacts: Box<Account<'info, Contacts>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetDelegate<'info> {
+    /// CHECK: we only use the mailbox address to derive the delegates PDA, it does not need to exist yet
+    #[account(
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
+    pub mailbox: UncheckedAccount<'info>,
+    /// CHECK: we only include receiver for their public key and do not access the account
+    /// and verify it based on the PDA of the mailbox
+    pub receiver: UncheckedAccount<'info>,
+    #[account(mut,
+        constraint = delegates::is_mailbox_admin(&receiver, &authority.key()) @ MessagingErrorCode::NotMailboxAuthority,
+    )]
+    pub authority: Signer<'info>,
+
+    #[account(init_if_needed,
+        payer = authority,
+        space = 8 + 4,
+        seeds = [PROTOCOL_SEED.as_bytes(), DELEGATES_SEED.as_bytes(), mailbox.key().as_ref()],
+        bump,
+    )]
+    pub delegates: Box<Account<'info, Delegates>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(name: String, members: Vec<Member>)]
+pub struct CreateSharedMailbox<'info> {
+    #[account(init,
+        payer = owner,
+        space = SharedMailbox { name: name.clone(), members: members.clone(), ..Default::default() }.get_size(),
+        seeds = [PROTOCOL_SEED.as_bytes(), SHARED_MAILBOX_SEED.as_bytes(), owner.key().as_ref(), name.as_bytes()],
+        bump,
+    )]
+    pub shared_mailbox: Box<Account<'info, SharedMailbox>>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetSharedMailboxMember<'info> {
+    #[account(mut,
+        address = shared_mailbox::shared_mailbox_address(&shared_mailbox.owner, &shared_mailbox.name) @ MessagingErrorCode::InvalidSharedMailboxAccount,
+    )]
+    pub shared_mailbox: Box<Account<'info, SharedMailbox>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(message_index: u32)]
+pub struct RejectMessageRequest<'info> {
+    /// CHECK: we only use the mailbox address to derive the request queue PDA
+    #[account(
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
+    pub mailbox: UncheckedAccount<'info>,
+    #[account(mut,
+        address = request_box_address(&mailbox.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
+    pub request_box: Box<Account<'info, Mailbox>>,
+    /// CHECK: we only include receiver for their public key and do not access the account
+    /// and verify it based on the PDA of the mailbox
+    pub receiver: UncheckedAccount<'info>,
+    #[account(
+        constraint = delegates::is_mailbox_admin(&receiver, &authority.key()) @ MessagingErrorCode::NotMailboxAuthority,
+    )]
+    pub authority: Signer<'info>,
+
+    #[account(mut,
+        close = rent_destination,
+        address = message_address(&request_box.key(), message_index) @ MessagingErrorCode::InvalidMessageAccount,
+    )]
+    pub message: Box<Account<'info, Message>>,
+    /// CHECK: may not exist if the sender never created an outbox, loaded through outbox::remove_sent
+    #[account(mut,
+        address = outbox::outbox_address(&message.sender) @ MessagingErrorCode::InvalidOutboxAccount,
+    )]
+    pub outbox: UncheckedAccount<'info>,
+
+    /// CHECK: we do not access the data in the rent_destination other than to transfer lamports to it
+    #[account(mut,
+        address = message.payer @ MessagingErrorCode::RentDestinationNotPayer,
+    )]
+    pub rent_destination: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseMailbox<'info> {
+    #[account(mut,
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
+    pub mailbox: Box<Account<'info, Mailbox>>,
+    /// CHECK: we only include receiver for their public key and do not access the account
+    /// and verify it based on the PDA of the mailbox
+    pub receiver: UncheckedAccount<'info>,
+    #[account(
+        constraint = delegates::is_mailbox_admin(&receiver, &authority.key()) @ MessagingErrorCode::NotMailboxAuthority,
+    )]
+    pub authority: Signer<'info>,
+
+    /// CHECK: we do not access the data in the rent_destination other than to transfer lamports to it
+    #[account(mut,
+        address = mailbox.payer @ MessagingErrorCode::RentDestinationNotPayer,
+    )]
+    pub rent_destination: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(data: String)]
-pub struct SendMessage<'info> {
+pub struct AddOrUpdateSetting<'info> {
+    /// CHECK: we only use the mailbox address to derive the settings PDA, it does not need to exist yet
+    #[account(
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
+    pub mailbox: UncheckedAccount<'info>,
+    /// CHECK: we only include receiver for their public key and do not access the account
+    /// and verify it based on the PDA of the mailbox
+    pub receiver: UncheckedAccount<'info>,
+    #[account(mut,
+        constraint = delegates::is_mailbox_admin(&receiver, &authority.key()) @ MessagingErrorCode::NotMailboxAuthority,
+    )]
+    pub authority: Signer<'info>,
+
     #[account(init_if_needed,
-        payer = payer,
-        space = 8 + 4 + 4,
-        seeds = [PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.key().as_ref()],
+        payer = authority,
+        space = 8 + 4,
+        seeds = [PROTOCOL_SEED.as_bytes(), MAILBOX_SETTINGS_SEED.as_bytes(), mailbox.key().as_ref()],
         bump,
     )]
+    pub mailbox_settings: Box<Account<'info, MailboxSettings>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(message_index: u32)]
+pub struct DeleteMessage<'info> {
+    #[account(mut,
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
     pub mailbox: Box<Account<'info, Mailbox>>,
-    /// CHECK: we do not access the data in the receiver
+    /// CHECK: we only include receiver for their public key and do not access the account
+    /// and verify it based on the PDA of the mailbox
     pub receiver: UncheckedAccount<'info>,
 
-    #[account(init,
-        payer = payer,
-        space =
-            8                               // account discriminator
-            + 32                            // sender pubkey
-            + 32                            // payer pubkey
-            + 4 + data.as_bytes().len()     // payload string
-            + 32,                           // incentive pubkey
-        seeds = [PROTOCOL_SEED.as_bytes(), MESSAGE_SEED.as_bytes(), mailbox.key().as_ref(), &mailbox.message_count.to_le_bytes()],
-        bump,
+    #[account(mut,
+        constraint = (authorized_deleter.key() == message.sender || authorized_deleter.key() == message.payer
+            || delegates::is_authorized(&receiver, &authorized_deleter.key(), &delegates, DelegateScope::DeleteMessages))
+            @ MessagingErrorCode::NotAuthorizedToDelete,
+    )]
+    pub authorized_deleter: Signer<'info>,
+    /// CHECK: may not exist if the receiver never delegated, loaded through Delegates::load
+    #[account(
+        address = delegates::delegates_address(&mailbox.key()) @ MessagingErrorCode::InvalidDelegatesAccount,
+    )]
+    pub delegates: UncheckedAccount<'info>,
+
+    #[account(mut,
+        close = rent_destination,
+        address = message_address(&mailbox.key(), message_index) @ MessagingErrorCode::InvalidMessageAccount,
     )]
     pub message: Box<Account<'info, Message>>,
+    /// CHECK: may not exist if the sender never created an outbox, loaded through outbox::remove_sent
+    #[account(mut,
+        address = outbox::outbox_address(&message.sender) @ MessagingErrorCode::InvalidOutboxAccount,
+    )]
+    pub outbox: UncheckedAccount<'info>,
 
-    #[account(mut)]
-    pub payer: Signer<'info>,
-    pub sender: Signer<'info>,
-    /// CHECK: we do not access the data in the fee_receiver other than to transfer lamports to it
+    /// CHECK: we do not access the data in the rent_destination other than to transfer lamports to it
     #[account(mut,
-        address = treasury::TREASURY_ADDRESS,
+        address = message.payer @ MessagingErrorCode::RentDestinationNotPayer,
     )]
-    pub fee_receiver: UncheckedAccount<'info>,
+    pub rent_destination: UncheckedAccount<'info>,
 
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(data: String)]
-pub struct SendMessageWithIncentive<'info> {
-    #[account(init_if_needed,
-        payer = payer,
-        space = 8 + 4 + 4,
-        seeds = [PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.key().as_ref()],
-        bump,
+#[instruction(message_index: u32)]
+pub struct CleanupExpiredMessage<'info> {
+    #[account(mut,
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
     )]
     pub mailbox: Box<Account<'info, Mailbox>>,
-    /// CHECK: we do not access the data in the receiver
+    /// CHECK: we only include receiver for their public key and do not access the account
+    /// and verify it based on the PDA of the mailbox
     pub receiver: UncheckedAccount<'info>,
+    pub cranker: Signer<'info>,
 
-    #[account(init,
-        payer = payer,
-        space =
-            8                               // account discriminator
-            + 32                            // sender pubkey
-            + 32                            // payer pubkey
-            + 4 + data.as_bytes().len()     // payload string
-            + 32,                           // incentive pubkey
-        seeds = [PROTOCOL_SEED.as_bytes(), MESSAGE_SEED.as_bytes(), mailbox.key().as_ref(), &mailbox.message_count.to_le_bytes()],
-        bump,
+    #[account(mut,
+        close = rent_destination,
+        address = message_address(&mailbox.key(), message_index) @ MessagingErrorCode::InvalidMessageAccount,
     )]
     pub message: Box<Account<'info, Message>>,
+    /// CHECK: may not exist if the sender never created an outbox, loaded through outbox::remove_sent
+    #[account(mut,
+        address = outbox::outbox_address(&message.sender) @ MessagingErrorCode::InvalidOutboxAccount,
+    )]
+    pub outbox: UncheckedAccount<'info>,
 
-    #[account(mut)]
-    pub payer: Signer<'info>,
-    pub sender: Signer<'info>,
-    /// CHECK: we do not access the data in the fee_receiver other than to transfer lamports to it
+    /// CHECK: we do not access the data in the rent_destination other than to transfer lamports to it
     #[account(mut,
-        address = treasury::TREASURY_ADDRESS,
+        address = message.payer @ MessagingErrorCode::RentDestinationNotPayer,
     )]
-    pub fee_receiver: UncheckedAccount<'info>,
+    pub rent_destination: UncheckedAccount<'info>,
 
     pub system_program: Program<'info, System>,
+}
 
-    pub incentive_mint: Box<Account<'info, token::Mint>>,
-    #[account(mut, associated_token::mint=incentive_mint, associated_token::authority=payer)]
-    pub payer_token_account: Box<Account<'info, token::TokenAccount>>,
-    #[account(init, payer=payer, associated_token::mint=incentive_mint, associated_token::authority=message)]
-    pub incentive_token_account: Box<Account<'info, token::TokenAccount>>,
+#[derive(Accounts)]
+pub struct CloseRateLimit<'info> {
+    /// CHECK: only used to derive the rate limit address, which can also be kept for a request
+    /// queue or ring mailbox
+    pub mailbox: UncheckedAccount<'info>,
+    /// CHECK: only used to derive the rate limit address
+    pub sender: UncheckedAccount<'info>,
+    #[account(mut,
+        close = rent_destination,
+        address = rate_limit::rate_limit_address(&mailbox.key(), &sender.key()) @ MessagingErrorCode::InvalidRateLimitAccount,
+    )]
+    pub rate_limit: Box<Account<'info, SenderRateLimit>>,
+    /// CHECK: we do not access the data in the rent_destination other than to transfer lamports to it
+    #[account(mut,
+        address = rate_limit.payer @ MessagingErrorCode::RentDestinationNotPayer,
+    )]
+    pub rent_destination: UncheckedAccount<'info>,
+}
 
-    pub token_program: Program<'info, token::Token>,
-    pub associated_token_program: Program<'info, associated_token::AssociatedToken>,
-    pub rent: Sysvar<'info, Rent>,
+#[derive(Accounts)]
+pub struct UpdateReadMessages<'info> {
+    #[account(mut,
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
+    pub mailbox: Box<Account<'info, Mailbox>>,
+    /// CHECK: we only include receiver for their public key and do not access the account
+    /// and verify it based on the PDA of the mailbox
+    pub receiver: UncheckedAccount<'info>,
+    #[account(
+        constraint = delegates::is_authorized(&receiver, &authority.key(), &delegates, DelegateScope::UpdateReadMessages)
+            @ MessagingErrorCode::NotMailboxAuthority,
+    )]
+    pub authority: Signer<'info>,
+    /// CHECK: may not exist if the receiver never delegated, loaded through Delegates::load
+    #[account(
+        address = delegates::delegates_address(&mailbox.key()) @ MessagingErrorCode::InvalidDelegatesAccount,
+    )]
+    pub delegates: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
 #[instruction(message_index: u32)]
-pub struct DeleteMessage<'info> {
+pub struct ClaimIncentive<'info> {
     #[account(mut,
-        seeds = [PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.key().as_ref()],
-        bump,
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
     )]
     pub mailbox: Box<Account<'info, Mailbox>>,
     /// CHECK: we only include receiver for their public key and do not access the account
     /// and verify it based on the PDA of the mailbox
     pub receiver: UncheckedAccount<'info>,
-
     #[account(mut,
-        constraint = (authorized_deleter.key() == receiver.key() || authorized_deleter.key() == message.sender || authorized_deleter.key() == message.payer)
+        constraint = delegates::is_authorized(&receiver, &authority.key(), &delegates, DelegateScope::ClaimIncentives)
+            @ MessagingErrorCode::NotMailboxAuthority,
     )]
-    pub authorized_deleter: Signer<'info>,
+    pub authority: Signer<'info>,
+    /// CHECK: may not exist if the receiver never delegated, loaded through Delegates::load
+    #[account(
+        address = delegates::delegates_address(&mailbox.key()) @ MessagingErrorCode::InvalidDelegatesAccount,
+    )]
+    pub delegates: UncheckedAccount<'info>,
+    /// CHECK: only receives the incentive, checked against the receiver
+    #[account(mut,
+        address = shared_mailbox::incentive_beneficiary(&receiver) @ MessagingErrorCode::IncentiveBeneficiaryMismatch,
+    )]
+    pub beneficiary: UncheckedAccount<'info>,
 
     #[account(mut,
-        close = rent_destination,
-        seeds = [PROTOCOL_SEED.as_bytes(), MESSAGE_SEED.as_bytes(), mailbox.key().as_ref(), &message_index.to_le_bytes()],
-        bump,
+        address = message_address(&mailbox.key(), message_index) @ MessagingErrorCode::InvalidMessageAccount,
     )]
     pub message: Box<Account<'info, Message>>,
 
     /// CHECK: we do not access the data in the rent_destination other than to transfer lamports to it
     #[account(mut,
-        address = message.payer,
+        address = message.payer @ MessagingErrorCode::RentDestinationNotPayer,
     )]
     pub rent_destination: UncheckedAccount<'info>,
 
+    #[account(address = message.incentive_mint @ MessagingErrorCode::IncentiveMintMismatch)]
+    pub incentive_mint: Box<Account<'info, token::Mint>>,
+    #[account(mut, associated_token::mint=incentive_mint, associated_token::authority=message)]
+    pub incentive_token_account: Box<Account<'info, token::TokenAccount>>,
+    #[account(init_if_needed, payer=authority, associated_token::mint=incentive_mint, associated_token::authority=beneficiary)]
+    pub receiver_token_account: Box<Account<'info, token::TokenAccount>>,
+
     pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, token::Token>,
+    pub associated_token_program: Program<'info, associated_token::AssociatedToken>,
+    /// CHECK: the instructions sysvar, read to find the reply when the incentive requires one
+    #[account(address = solana_program::sysvar::instructions::ID @ MessagingErrorCode::InvalidInstructionsSysvar)]
+    pub instructions: UncheckedAccount<'info>,
+    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-pub struct UpdateReadMessages<'info> {
-    #[account(mut,
-        seeds = [PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.key().as_ref()],
-        bump,
+#[instruction(message_index: u32)]
+pub struct ClaimSolIncentive<'info> {
+    #[account(
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
     )]
     pub mailbox: Box<Account<'info, Mailbox>>,
-    pub receiver: Signer<'info>,
+    /// CHECK: we only include receiver for their public key and do not access the account
+    /// and verify it based on the PDA of the mailbox
+    pub receiver: UncheckedAccount<'info>,
+    #[account(
+        constraint = delegates::is_authorized(&receiver, &authority.key(), &delegates, DelegateScope::ClaimIncentives)
+            @ MessagingErrorCode::NotMailboxAuthority,
+    )]
+    pub authority: Signer<'info>,
+    /// CHECK: may not exist if the receiver never delegated, loaded through Delegates::load
+    #[account(
+        address = delegates::delegates_address(&mailbox.key()) @ MessagingErrorCode::InvalidDelegatesAccount,
+    )]
+    pub delegates: UncheckedAccount<'info>,
+    /// CHECK: only receives the incentive, checked against the receiver
+    #[account(mut,
+        address = shared_mailbox::incentive_beneficiary(&receiver) @ MessagingErrorCode::IncentiveBeneficiaryMismatch,
+    )]
+    pub beneficiary: UncheckedAccount<'info>,
+
+    #[account(mut,
+        address = message_address(&mailbox.key(), message_index) @ MessagingErrorCode::InvalidMessageAccount,
+    )]
+    pub message: Box<Account<'info, Message>>,
+    /// CHECK: the instructions sysvar, read to find the reply when the incentive requires one
+    #[account(address = solana_program::sysvar::instructions::ID @ MessagingErrorCode::InvalidInstructionsSysvar)]
+    pub instructions: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
 #[instruction(message_index: u32)]
-pub struct ClaimIncentive<'info> {
-    #[account(mut,
-        seeds = [PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.key().as_ref()],
-        bump,
+pub struct RefundIncentive<'info> {
+    #[account(
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
     )]
     pub mailbox: Box<Account<'info, Mailbox>>,
-    #[account(mut)]
-    pub receiver: Signer<'info>,
+    /// CHECK: we only include receiver for their public key and do not access the account
+    /// and verify it based on the PDA of the mailbox
+    pub receiver: UncheckedAccount<'info>,
 
     #[account(mut,
-        seeds = [PROTOCOL_SEED.as_bytes(), MESSAGE_SEED.as_bytes(), mailbox.key().as_ref(), &message_index.to_le_bytes()],
-        bump,
+        constraint = (authorized_refunder.key() == message.sender || authorized_refunder.key() == message.payer)
+            @ MessagingErrorCode::NotAuthorizedToRefund,
+    )]
+    pub authorized_refunder: Signer<'info>,
+
+    #[account(mut,
+        address = message_address(&mailbox.key(), message_index) @ MessagingErrorCode::InvalidMessageAccount,
     )]
     pub message: Box<Account<'info, Message>>,
 
     /// CHECK: we do not access the data in the rent_destination other than to transfer lamports to it
     #[account(mut,
-        address = message.payer,
+        address = message.payer @ MessagingErrorCode::RentDestinationNotPayer,
     )]
     pub rent_destination: UncheckedAccount<'info>,
 
-    #[account(address=message.incentive_mint)]
+    #[account(address = message.incentive_mint @ MessagingErrorCode::IncentiveMintMismatch)]
     pub incentive_mint: Box<Account<'info, token::Mint>>,
     #[account(mut, associated_token::mint=incentive_mint, associated_token::authority=message)]
     pub incentive_token_account: Box<Account<'info, token::TokenAccount>>,
-    #[account(init_if_needed, payer=receiver, associated_token::mint=incentive_mint, associated_token::authority=receiver)]
-    pub receiver_token_account: Box<Account<'info, token::TokenAccount>>,
+    #[account(init_if_needed, payer=authorized_refunder, associated_token::mint=incentive_mint, associated_token::authority=rent_destination)]
+    pub refund_token_account: Box<Account<'info, token::TokenAccount>>,
 
     pub system_program: Program<'info, System>,
     pub token_program: Program<'info, token::Token>,
@@ -304,11 +2503,130 @@ pub struct ClaimIncentive<'info> {
     pub rent: Sysvar<'info, Rent>,
 }
 
+#[derive(Accounts)]
+#[instruction(message_index: u32)]
+pub struct RefundSolIncentive<'info> {
+    #[account(
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
+    pub mailbox: Box<Account<'info, Mailbox>>,
+    /// CHECK: we only include receiver for their public key and do not access the account
+    /// and verify it based on the PDA of the mailbox
+    pub receiver: UncheckedAccount<'info>,
+
+    #[account(
+        constraint = (authorized_refunder.key() == message.sender || authorized_refunder.key() == message.payer)
+            @ MessagingErrorCode::NotAuthorizedToRefund,
+    )]
+    pub authorized_refunder: Signer<'info>,
+
+    #[account(mut,
+        address = message_address(&mailbox.key(), message_index) @ MessagingErrorCode::InvalidMessageAccount,
+    )]
+    pub message: Box<Account<'info, Message>>,
+
+    /// CHECK: we do not access the data in the rent_destination other than to transfer lamports to it
+    #[account(mut,
+        address = message.payer @ MessagingErrorCode::RentDestinationNotPayer,
+    )]
+    pub rent_destination: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateMailbox<'info> {
+    /// CHECK: checked for the legacy layout in migration::migrate_mailbox, as it cannot be
+    /// loaded as a `Mailbox` yet
+    #[account(mut,
+        owner = crate::ID @ MessagingErrorCode::NotLegacyAccount,
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
+    pub mailbox: UncheckedAccount<'info>,
+    /// CHECK: we only include receiver for their public key and do not access the account
+    pub receiver: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(message_index: u32)]
+pub struct MigrateMessage<'info> {
+    /// CHECK: we only use the mailbox address to derive the message PDA, legacy mailboxes do not
+    /// deserialize as `Mailbox` either
+    #[account(
+        address = mailbox_address(&receiver.key()) @ MessagingErrorCode::InvalidMailboxAccount,
+    )]
+    pub mailbox: UncheckedAccount<'info>,
+    /// CHECK: we only include receiver for their public key and do not access the account
+    pub receiver: UncheckedAccount<'info>,
+
+    /// CHECK: checked for the legacy layout in migration::migrate_message, as it cannot be
+    /// loaded as a `Message` yet
+    #[account(mut,
+        owner = crate::ID @ MessagingErrorCode::NotLegacyAccount,
+        address = message_address(&mailbox.key(), message_index) @ MessagingErrorCode::InvalidMessageAccount,
+    )]
+    pub message: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+pub fn mailbox_address(receiver: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[PROTOCOL_SEED.as_bytes(), MAILBOX_SEED.as_bytes(), receiver.as_ref()], &crate::ID).0
+}
+
+pub fn request_box_address(mailbox: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[PROTOCOL_SEED.as_bytes(), REQUEST_BOX_SEED.as_bytes(), mailbox.as_ref()], &crate::ID).0
+}
+
+/// Address of the message at `message_index` in `mailbox` (or a request box), with its bump
+pub fn find_message_address(mailbox: &Pubkey, message_index: u32) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[PROTOCOL_SEED.as_bytes(), MESSAGE_SEED.as_bytes(), mailbox.as_ref(), &message_index.to_le_bytes()],
+        &crate::ID,
+    )
+}
+
+pub fn message_address(mailbox: &Pubkey, message_index: u32) -> Pubkey {
+    find_message_address(mailbox, message_index).0
+}
+
+/// Borsh-serialized length of a value, for sizing accounts whose fields vary in length
+pub fn borsh_size<T: AnchorSerialize>(value: &T) -> usize {
+    value.try_to_vec().map_or(0, |v| v.len())
+}
+
+pub fn resize_account<'info>(data_account: &dyn ToAccountInfo<'info>, funding_account: &dyn ToAccountInfo<'info>, new_size: usize) -> Result<()> {
+    let rent = Rent::get()?;
+    let new_minimum_balance = rent.minimum_balance(new_size);
+    let data_info = data_account.to_account_info();
+
+    if new_minimum_balance > data_info.lamports() {
+        let lamports_diff = new_minimum_balance.saturating_sub(data_info.lamports());
+        treasury::transfer_lamports(funding_account, data_account, lamports_diff)?;
+    }
+    data_info.realloc(new_size, false)?;
+    Ok(())
+}
+
 #[account]
 #[derive(Default)]
 pub struct Mailbox {
     pub read_message_count: u32,
     pub message_count: u32,
+    pub payer: Pubkey,
+    /// Messages from the first one that `close_mailbox` already found deleted
+    pub close_checked_count: u32,
+}
+
+impl Mailbox {
+    pub fn space() -> usize {
+        // discriminator + read_message_count + message_count + payer + close_checked_count
+        8 + 4 + 4 + 32 + 4
+    }
 }
 
 #[account]
@@ -318,21 +2636,122 @@ pub struct Message {
     pub payer: Pubkey,
     pub data: String,
     pub incentive_mint: Pubkey,
+    pub encryption: Option<EncryptionHeader>,
+    pub ciphertext: Vec<u8>,
+    pub incentive_conditions: IncentiveConditions,
+    pub incentive_lamports: u64,
+    pub reply_to: Option<MessageReference>,
+    pub incentive_nft: Option<NftIncentive>,
+    /// Unix timestamp after which anyone can clean the message up
+    pub expires_at: Option<i64>,
+    /// Attachments are closed with the message, so they must be passed to delete it
+    pub attachment_count: u8,
+    /// Set instead of `data` for messages sent with `send_envelope_message`
+    pub envelope: Option<MessageEnvelope>,
+    /// Where the sender's outbox recorded the message, if they have one
+    pub outbox_slot: Option<u16>,
+}
+
+impl Message {
+    /// Account size for a message holding these contents. Options the send fills in after
+    /// creating the account must already be `Some` here so their space is reserved.
+    pub fn space(&self) -> usize {
+        8 + borsh_size(self)
+    }
+}
+
+#[event]
+pub struct AttachmentFinalized {
+    pub version: u8,
+    pub message_pubkey: Pubkey,
+    pub attachment_pubkey: Pubkey,
+    pub attachment_index: u8,
+    pub size: u32,
+    pub content_hash: [u8; 32],
+}
+
+#[event]
+pub struct ConfigUpdated {
+    pub version: u8,
+    pub admin: Pubkey,
+    pub treasury: Pubkey,
+    pub fees: FeeSchedule,
+}
+
+#[event]
+pub struct MailboxCreated {
+    pub version: u8,
+    pub receiver_pubkey: Pubkey,
+    pub mailbox_pubkey: Pubkey,
+    pub payer_pubkey: Pubkey,
 }
 
 #[event]
 pub struct DispatchMessage {
+    pub version: u8,
     pub sender_pubkey: Pubkey,
     pub receiver_pubkey: Pubkey,
+    pub mailbox_pubkey: Pubkey,
     pub message_index: u32,
     pub message: String,
+    pub encryption: Option<EncryptionHeader>,
+    pub ciphertext: Vec<u8>,
+    pub reply_to: Option<MessageReference>,
+    /// SHA-256 of `message`, set for ephemeral messages that have no account to check against
+    pub content_hash: Option<[u8; 32]>,
+    /// Content type of envelope messages, whose payload is in the message account's envelope
+    pub content_type: Option<String>,
 }
 
 #[event]
 pub struct IncentiveClaimed {
+    pub version: u8,
+    pub sender_pubkey: Pubkey,
+    pub receiver_pubkey: Pubkey,
+    pub message_index: u32,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub incentive_type: IncentiveType,
+    /// Verified collection of an NFT incentive
+    pub collection: Option<Pubkey>,
+    /// The reply sent alongside the claim, when the incentive required one
+    pub reply_message: Option<Pubkey>,
+}
+
+#[event]
+pub struct IncentiveRefunded {
+    pub version: u8,
     pub sender_pubkey: Pubkey,
     pub receiver_pubkey: Pubkey,
     pub message_index: u32,
     pub mint: Pubkey,
     pub amount: u64,
+    pub incentive_type: IncentiveType,
+    /// Verified collection of an NFT incentive
+    pub collection: Option<Pubkey>,
+}
+
+#[event]
+pub struct MessageDeleted {
+    pub version: u8,
+    pub receiver_pubkey: Pubkey,
+    pub mailbox_pubkey: Pubkey,
+    pub message_index: u32,
+    pub deleted_by: Pubkey,
+    pub read_message_count: u32,
+}
+
+#[event]
+pub struct ReadCountUpdated {
+    pub version: u8,
+    pub receiver_pubkey: Pubkey,
+    pub mailbox_pubkey: Pubkey,
+    pub read_message_count: u32,
+}
+
+#[event]
+pub struct MailboxClosed {
+    pub version: u8,
+    pub receiver_pubkey: Pubkey,
+    pub mailbox_pubkey: Pubkey,
 }
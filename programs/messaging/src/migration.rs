@@ -0,0 +1,72 @@
+use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
+use crate::errors::MessagingErrorCode;
+use crate::{Mailbox, Message};
+
+/// Layout of messages sent before fields were appended to `Message`. Such accounts fail to
+/// deserialize as `Message` until they are migrated with `migrate_message`.
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct LegacyMessage {
+    pub sender: Pubkey,
+    pub payer: Pubkey,
+    pub data: String,
+    pub incentive_mint: Pubkey,
+}
+
+/// Layout of mailboxes created before they recorded who paid for them.
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct LegacyMailbox {
+    pub read_message_count: u32,
+    pub message_count: u32,
+}
+
+/// Rewrite a legacy mailbox in the current layout. Nobody knows who paid to create it, so
+/// the receiver is recorded as its payer and gets the rent back when closing it.
+pub fn migrate_mailbox<'info>(mailbox_info: &AccountInfo<'info>, receiver: &Pubkey,
+                              funder: &AccountInfo<'info>) -> Result<Mailbox> {
+    let mailbox = {
+        let data = mailbox_info.try_borrow_data()?;
+        require!(data.len() >= 8 && data[..8] == Mailbox::discriminator(), MessagingErrorCode::NotLegacyAccount);
+        require!(Mailbox::deserialize(&mut &data[8..]).is_err(), MessagingErrorCode::NotLegacyAccount);
+        let legacy = LegacyMailbox::deserialize(&mut &data[8..])
+            .map_err(|_| error!(MessagingErrorCode::NotLegacyAccount))?;
+        Mailbox {
+            read_message_count: legacy.read_message_count,
+            message_count: legacy.message_count,
+            payer: *receiver,
+            close_checked_count: 0,
+        }
+    };
+    write_migrated(mailbox_info, funder, &mailbox)?;
+    Ok(mailbox)
+}
+
+/// Rewrite a legacy message in the current layout, leaving every appended field at its
+/// default. `funder` pays the rent for the larger account.
+pub fn migrate_message<'info>(message_info: &AccountInfo<'info>, funder: &AccountInfo<'info>) -> Result<Message> {
+    let message = {
+        let data = message_info.try_borrow_data()?;
+        require!(data.len() >= 8 && data[..8] == Message::discriminator(), MessagingErrorCode::NotLegacyAccount);
+        require!(Message::deserialize(&mut &data[8..]).is_err(), MessagingErrorCode::NotLegacyAccount);
+        let legacy = LegacyMessage::deserialize(&mut &data[8..])
+            .map_err(|_| error!(MessagingErrorCode::NotLegacyAccount))?;
+        Message {
+            sender: legacy.sender,
+            payer: legacy.payer,
+            data: legacy.data,
+            incentive_mint: legacy.incentive_mint,
+            ..Default::default()
+        }
+    };
+    write_migrated(message_info, funder, &message)?;
+    Ok(message)
+}
+
+fn write_migrated<'info, T: AccountSerialize>(account_info: &AccountInfo<'info>, funder: &AccountInfo<'info>,
+                                              value: &T) -> Result<()> {
+    let mut serialized = Vec::new();
+    value.try_serialize(&mut serialized)?;
+    crate::resize_account(account_info, funder, serialized.len())?;
+    account_info.try_borrow_mut_data()?.copy_from_slice(&serialized);
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use anchor_lang::prelude::*;
+use postbox::nft_metadata::Metadata;
+
+/// Set on messages whose token incentive is a Metaplex NFT sent as a gift
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    Default,
+    PartialEq,
+    Eq
+)]
+pub struct NftIncentive {
+    /// Only a verified collection is recorded, so indexers can trust it
+    pub collection: Option<Pubkey>,
+}
+
+impl NftIncentive {
+    pub fn from_metadata(metadata: &Metadata) -> NftIncentive {
+        let collection = metadata.collection.as_ref().filter(|collection| collection.verified).map(|collection| collection.key);
+        NftIncentive { collection }
+    }
+}
+
+pub fn metadata_address(mint: &Pubkey) -> Pubkey {
+    let metadata_program = Metadata::owner();
+    Pubkey::find_program_address(&[b"metadata", metadata_program.as_ref(), mint.as_ref()], &metadata_program).0
+}
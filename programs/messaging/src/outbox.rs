@@ -0,0 +1,74 @@
+use anchor_lang::prelude::*;
+
+/// Sent messages an outbox holds before the oldest is overwritten
+pub const OUTBOX_CAPACITY: usize = 64;
+
+// Where a sent message went, kept in `SentMessage::kind`
+pub const SENT_TO_MAILBOX: u8 = 0;
+pub const SENT_AS_REQUEST: u8 = 1;
+pub const SENT_TO_RING: u8 = 2;
+pub const SENT_EPHEMERAL: u8 = 3;
+
+#[zero_copy]
+pub struct SentMessage {
+    pub receiver: Pubkey,
+    /// Index in the receiver's mailbox, request queue or ring mailbox, depending on `kind`
+    pub message_index: u32,
+    /// Zero once the message is deleted or rejected, or for slots never used
+    pub in_use: u8,
+    pub kind: u8,
+    /// Keeps entries a multiple of 8 bytes so the layout is the same packed or not
+    pub padding: [u8; 2],
+}
+
+/// The last `OUTBOX_CAPACITY` messages a sender sent, minus those deleted since, so senders
+/// can list their sent mail without scanning every message account. Every send is recorded,
+/// including message requests, ring mailbox and ephemeral messages. Older sends are
+/// overwritten rather than kept, so a longer history has to come from scanning mailboxes or
+/// from indexed `DispatchMessage` events. Send `n` goes in slot `n % OUTBOX_CAPACITY`, which
+/// the message keeps so deleting it clears the slot directly. Only senders who created an
+/// outbox are tracked.
+#[account(zero_copy)]
+pub struct Outbox {
+    pub sent_count: u64,
+    pub entries: [SentMessage; OUTBOX_CAPACITY],
+}
+
+/// Add a message to the sender's outbox over the oldest entry, if they have an outbox, and
+/// return the slot it went in.
+pub fn record_sent(outbox_info: &AccountInfo, receiver: Pubkey, message_index: u32, kind: u8) -> Result<Option<u16>> {
+    if outbox_info.data_is_empty() {
+        return Ok(None);
+    }
+    let outbox_loader = AccountLoader::<Outbox>::try_from(outbox_info)?;
+    let mut outbox = outbox_loader.load_mut()?;
+    let slot = (outbox.sent_count % OUTBOX_CAPACITY as u64) as usize;
+    outbox.entries[slot] = SentMessage { receiver, message_index, in_use: 1, kind, padding: [0; 2] };
+    outbox.sent_count += 1;
+    Ok(Some(slot as u16))
+}
+
+/// Clear a closed message from the slot it was recorded in. Nothing is cleared when the slot
+/// has been reused by a later send, or the sender has no outbox (anymore).
+pub fn remove_sent(outbox_info: &AccountInfo, slot: Option<u16>, receiver: Pubkey, message_index: u32,
+                   kind: u8) -> Result<()> {
+    let slot = match slot {
+        Some(slot) if !outbox_info.data_is_empty() => slot as usize,
+        _ => return Ok(()),
+    };
+    let outbox_loader = AccountLoader::<Outbox>::try_from(outbox_info)?;
+    let mut outbox = outbox_loader.load_mut()?;
+    if let Some(entry) = outbox.entries.get_mut(slot) {
+        if entry.in_use == 1 && entry.kind == kind && entry.receiver == receiver && entry.message_index == message_index {
+            entry.in_use = 0;
+        }
+    }
+    Ok(())
+}
+
+pub fn outbox_address(sender: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[crate::PROTOCOL_SEED.as_bytes(), crate::OUTBOX_SEED.as_bytes(), sender.as_ref()],
+        &crate::ID,
+    ).0
+}
@@ -0,0 +1,77 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token;
+use crate::errors::MessagingErrorCode;
+
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    PartialEq,
+    Eq
+)]
+pub enum PostageRule {
+    Lamports { amount: u64 },
+    Token { mint: Pubkey, amount: u64 },
+}
+
+/// Look through the extra accounts for a token account of the given owner and mint.
+pub fn find_token_account<'a, 'info>(
+    extra_accounts: &'a [AccountInfo<'info>],
+    owner: &Pubkey,
+    mint: &Pubkey,
+) -> Option<&'a AccountInfo<'info>> {
+    extra_accounts.iter().find(|info| {
+        Account::<token::TokenAccount>::try_from(*info).map_or(false,
+            |token_account| token_account.owner == *owner && token_account.mint == *mint
+        )
+    })
+}
+
+impl PostageRule {
+    /// Pay the receiver's postage from the payer. Postage for a shared mailbox goes to its
+    /// owner, like its incentives, so lamport postage then expects the owner among the extra
+    /// accounts. Token postage expects the payer's and beneficiary's token accounts and the
+    /// token program among the extra accounts.
+    pub fn pay<'info>(&self,
+        payer: &AccountInfo<'info>,
+        receiver: &AccountInfo<'info>,
+        extra_accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        let beneficiary = crate::shared_mailbox::incentive_beneficiary(receiver);
+        match self {
+            PostageRule::Lamports { amount } => {
+                if *amount > 0 {
+                    let to = if beneficiary == receiver.key() {
+                        Some(receiver)
+                    } else {
+                        extra_accounts.iter().find(|info| info.key() == beneficiary)
+                    };
+                    match to {
+                        Some(to) => crate::treasury::transfer_lamports(payer, to, *amount)?,
+                        None => return Err(Error::from(MessagingErrorCode::MissingPostageAccounts).with_source(source!())),
+                    }
+                }
+            },
+
+            PostageRule::Token { mint, amount } => {
+                if *amount > 0 {
+                    let from = find_token_account(extra_accounts, payer.key, mint);
+                    let to = find_token_account(extra_accounts, &beneficiary, mint);
+                    let token_program = extra_accounts.iter().find(|info| info.key() == token::ID);
+                    match (from, to, token_program) {
+                        (Some(from), Some(to), Some(token_program)) => {
+                            let transfer_ctx = CpiContext::new(token_program.clone(), token::Transfer {
+                                authority: payer.clone(),
+                                from: from.clone(),
+                                to: to.clone(),
+                            });
+                            token::transfer(transfer_ctx, *amount)?;
+                        },
+                        _ => return Err(Error::from(MessagingErrorCode::MissingPostageAccounts).with_source(source!())),
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+}
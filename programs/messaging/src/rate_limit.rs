@@ -0,0 +1,71 @@
+use anchor_lang::prelude::*;
+use crate::broadcast;
+use crate::errors::MessagingErrorCode;
+
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    PartialEq,
+    Eq
+)]
+pub struct RateLimitRule {
+    /// Messages one sender may send within a window
+    pub max_messages: u16,
+    pub window_seconds: u32,
+}
+
+/// How many messages a sender has sent to one mailbox in the current window. Only created
+/// once the receiver sets a rate limit, paid for by whoever pays for the first message, and
+/// can be closed with `close_rate_limit` once the window is over.
+#[account]
+#[derive(Default)]
+pub struct SenderRateLimit {
+    pub payer: Pubkey,
+    pub window_end: i64,
+    pub message_count: u16,
+}
+
+impl RateLimitRule {
+    pub fn validate(&self) -> Result<()> {
+        require!(self.max_messages > 0 && self.window_seconds > 0, MessagingErrorCode::InvalidRateLimit);
+        Ok(())
+    }
+
+    /// Count a message from `sender` against the limit. Windows are fixed: the count resets
+    /// with the first message sent after the current window has ended, and a new window takes
+    /// the rule's length at that time.
+    pub fn enforce<'info>(&self, mailbox: &Pubkey, sender: &Pubkey, payer: &AccountInfo<'info>,
+                          state_info: &AccountInfo<'info>, system_program: &AccountInfo<'info>) -> Result<()> {
+        let (state_key, state_bump) = find_rate_limit_address(mailbox, sender);
+        require!(state_info.key() == state_key, MessagingErrorCode::InvalidRateLimitAccount);
+
+        let mut state = if state_info.data_is_empty() {
+            let mut state = broadcast::init_pda_account::<SenderRateLimit>(payer, state_info, system_program, 8 + 32 + 8 + 2,
+                &[crate::PROTOCOL_SEED.as_bytes(), crate::RATE_LIMIT_SEED.as_bytes(), mailbox.as_ref(), sender.as_ref(), &[state_bump]])?;
+            state.payer = payer.key();
+            state
+        } else {
+            Account::<SenderRateLimit>::try_from(state_info)?
+        };
+        let now = Clock::get()?.unix_timestamp;
+        if now >= state.window_end {
+            state.window_end = now + i64::from(self.window_seconds);
+            state.message_count = 0;
+        }
+        require!(state.message_count < self.max_messages, MessagingErrorCode::RateLimitExceeded);
+        state.message_count += 1;
+        state.exit(&crate::ID)
+    }
+}
+
+pub fn find_rate_limit_address(mailbox: &Pubkey, sender: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[crate::PROTOCOL_SEED.as_bytes(), crate::RATE_LIMIT_SEED.as_bytes(), mailbox.as_ref(), sender.as_ref()],
+        &crate::ID,
+    )
+}
+
+pub fn rate_limit_address(mailbox: &Pubkey, sender: &Pubkey) -> Pubkey {
+    find_rate_limit_address(mailbox, sender).0
+}
@@ -0,0 +1,65 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{ed25519_program, sysvar::instructions as instructions_sysvar};
+use crate::errors::MessagingErrorCode;
+
+/// Keeps a relay authorization from being valid as any other message the sender signs
+const RELAY_DOMAIN: &[u8] = b"dispatch:relayed_message";
+
+// Layout of the ed25519 program instruction data: a signature count and a padding byte,
+// then one set of seven u16 offsets per signature
+const SIGNATURE_OFFSETS_START: usize = 2;
+const SIGNATURE_OFFSETS_SIZE: usize = 14;
+const PUBKEY_SIZE: usize = 32;
+
+/// Counts the relayed messages of one sender; a relay authorization is only valid for the
+/// current nonce, so each one can be used once.
+#[account]
+#[derive(Default)]
+pub struct SenderNonce {
+    pub nonce: u64,
+}
+
+/// The bytes a sender signs off-chain to let a relayer send `data` to `receiver` for them
+pub fn relay_message_bytes(receiver: &Pubkey, nonce: u64, data: &str) -> Vec<u8> {
+    let mut bytes = RELAY_DOMAIN.to_vec();
+    bytes.extend_from_slice(crate::ID.as_ref());
+    bytes.extend_from_slice(receiver.as_ref());
+    bytes.extend_from_slice(&nonce.to_le_bytes());
+    bytes.extend_from_slice(data.as_bytes());
+    bytes
+}
+
+/// Check that the instruction right before this one had the ed25519 program verify the
+/// signer's signature over the message. The ed25519 program fails the transaction if the
+/// signature itself is invalid, so only what it was asked to verify is checked here.
+pub fn verify_ed25519_signature(instructions: &AccountInfo, signer: &Pubkey, message: &[u8]) -> Result<()> {
+    let current_index = instructions_sysvar::load_current_index_checked(instructions)? as usize;
+    require!(current_index > 0, MessagingErrorCode::MissingRelaySignature);
+    let ed25519_index = current_index - 1;
+    let ix = instructions_sysvar::load_instruction_at_checked(ed25519_index, instructions)?;
+    require!(ix.program_id == ed25519_program::ID && ix.accounts.is_empty(), MessagingErrorCode::MissingRelaySignature);
+
+    let data = &ix.data;
+    require!(
+        data.len() >= SIGNATURE_OFFSETS_START + SIGNATURE_OFFSETS_SIZE && data[0] == 1,
+        MessagingErrorCode::InvalidRelaySignature,
+    );
+    let read_u16 = |at: usize| u16::from_le_bytes([data[SIGNATURE_OFFSETS_START + at], data[SIGNATURE_OFFSETS_START + at + 1]]);
+    // The signature, key and message must all come from the ed25519 instruction itself
+    let is_own_data = |instruction_index: u16| instruction_index == u16::MAX || usize::from(instruction_index) == ed25519_index;
+    require!(
+        is_own_data(read_u16(2)) && is_own_data(read_u16(6)) && is_own_data(read_u16(12)),
+        MessagingErrorCode::InvalidRelaySignature,
+    );
+
+    let pubkey_offset = usize::from(read_u16(4));
+    let message_offset = usize::from(read_u16(8));
+    let message_size = usize::from(read_u16(10));
+    let signed_pubkey = data.get(pubkey_offset..pubkey_offset + PUBKEY_SIZE);
+    let signed_message = data.get(message_offset..message_offset + message_size);
+    require!(
+        signed_pubkey == Some(signer.as_ref()) && signed_message == Some(message),
+        MessagingErrorCode::InvalidRelaySignature,
+    );
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use anchor_lang::prelude::*;
+
+/// Messages kept by a ring mailbox before the oldest is overwritten
+pub const RING_CAPACITY: usize = 32;
+pub const RING_ENTRY_DATA_LEN: usize = 256;
+
+#[zero_copy]
+pub struct RingEntry {
+    pub sender: Pubkey,
+    pub sent_at: i64,
+    pub data_len: u16,
+    /// Keeps entries a multiple of 8 bytes so the layout is the same packed or not
+    pub padding: [u8; 6],
+    pub data: [u8; RING_ENTRY_DATA_LEN],
+}
+
+/// A mailbox holding its last `RING_CAPACITY` messages in one account, for receivers that
+/// get too many messages to pay rent on each. Message `i` lives in slot `i % RING_CAPACITY`
+/// and the counts mean the same as in `Mailbox`, except that the read count is pushed forward
+/// when unread messages are overwritten.
+#[account(zero_copy)]
+pub struct RingMailbox {
+    pub read_message_count: u32,
+    pub message_count: u32,
+    pub payer: Pubkey,
+    pub entries: [RingEntry; RING_CAPACITY],
+}
+
+impl RingMailbox {
+    /// Store a message over the oldest one and return its index.
+    pub fn push(&mut self, sender: Pubkey, sent_at: i64, data: &[u8]) -> u32 {
+        let mut entry_data = [0u8; RING_ENTRY_DATA_LEN];
+        entry_data[..data.len()].copy_from_slice(data);
+        let message_index = self.message_count;
+        self.entries[message_index as usize % RING_CAPACITY] = RingEntry {
+            sender,
+            sent_at,
+            data_len: data.len() as u16,
+            padding: [0; 6],
+            data: entry_data,
+        };
+        self.message_count += 1;
+        let oldest = self.oldest_message_index();
+        if self.read_message_count < oldest {
+            self.read_message_count = oldest;
+        }
+        message_index
+    }
+
+    /// Index of the oldest message still in the ring
+    pub fn oldest_message_index(&self) -> u32 {
+        self.message_count.saturating_sub(RING_CAPACITY as u32)
+    }
+}
+
+pub fn ring_mailbox_address(receiver: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[crate::PROTOCOL_SEED.as_bytes(), crate::RING_MAILBOX_SEED.as_bytes(), receiver.as_ref()],
+        &crate::ID,
+    ).0
+}
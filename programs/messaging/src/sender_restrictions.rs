@@ -0,0 +1,69 @@
+use anchor_lang::prelude::*;
+use anchor_spl::{token, associated_token};
+use crate::errors::MessagingErrorCode;
+
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    PartialEq,
+    Eq
+)]
+pub enum SenderRule {
+    Address { key: Pubkey },
+    TokenOwnership { mint: Pubkey, amount: u64 },
+}
+
+impl SenderRule {
+    /// Allow list matching: the sender may prove token ownership with any of their token
+    /// accounts passed in the extra accounts.
+    fn allows(&self, sender: &Pubkey, extra_accounts: &[AccountInfo]) -> bool {
+        match self {
+            SenderRule::Address { key } => key == sender,
+            SenderRule::TokenOwnership { mint, amount } => extra_accounts.iter().any(|info| {
+                Account::<token::TokenAccount>::try_from(info).map_or(false,
+                    |token_account| token_account.owner == *sender
+                        && token_account.mint == *mint
+                        && token_account.amount >= *amount
+                )
+            }),
+        }
+    }
+
+    /// Block list matching: a sender would never volunteer a token account that gets them
+    /// blocked, so we always look at their associated token account, which must be passed.
+    fn blocks(&self, sender: &Pubkey, extra_accounts: &[AccountInfo]) -> Result<bool> {
+        match self {
+            SenderRule::Address { key } => Ok(key == sender),
+            SenderRule::TokenOwnership { mint, amount } => {
+                let expected_ata = associated_token::get_associated_token_address(sender, mint);
+                let ata_info = extra_accounts.iter().find(|info| info.key() == expected_ata);
+                require!(ata_info.is_some(), MessagingErrorCode::MissingSenderTokenAccount);
+                // An account that was never created holds nothing
+                Ok(Account::<token::TokenAccount>::try_from(ata_info.unwrap()).map_or(false,
+                    |token_account| token_account.amount >= *amount
+                ))
+            },
+        }
+    }
+}
+
+/// Blocked senders are rejected first. If the receiver has an allow list, the sender must also
+/// match one of its rules; an empty allow list closes the mailbox to everyone else.
+pub fn validate_sender_allowed(
+    sender: &Pubkey,
+    allow_list: Option<&Vec<SenderRule>>,
+    block_list: Option<&Vec<SenderRule>>,
+    extra_accounts: &[AccountInfo],
+) -> Result<()> {
+    if let Some(rules) = block_list {
+        for rule in rules {
+            require!(!rule.blocks(sender, extra_accounts)?, MessagingErrorCode::SenderBlocked);
+        }
+    }
+    if let Some(rules) = allow_list {
+        let allowed = rules.iter().any(|rule| rule.allows(sender, extra_accounts));
+        require!(allowed, MessagingErrorCode::SenderNotAllowed);
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use anchor_lang::prelude::*;
+use crate::postage::PostageRule;
+use crate::rate_limit::RateLimitRule;
+use crate::sender_restrictions::SenderRule;
+
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    PartialEq,
+    Eq
+)]
+pub enum SettingsType {
+    Postage,
+    Null,
+    SenderAllowList,
+    SenderBlockList,
+    MessageRequests,
+    RateLimit,
+}
+
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    PartialEq,
+    Eq
+)]
+pub enum SettingsData {
+    Postage { postage: PostageRule },
+    Null,
+    SenderAllowList { rules: Vec<SenderRule> },
+    SenderBlockList { rules: Vec<SenderRule> },
+    MessageRequests { enabled: bool },
+    RateLimit { rule: RateLimitRule },
+}
+
+impl SettingsData {
+    pub fn get_type(&self) -> SettingsType {
+        return match self {
+            SettingsData::Postage { postage: _ } => SettingsType::Postage,
+            SettingsData::Null => SettingsType::Null,
+            SettingsData::SenderAllowList { rules: _ } => SettingsType::SenderAllowList,
+            SettingsData::SenderBlockList { rules: _ } => SettingsType::SenderBlockList,
+            SettingsData::MessageRequests { enabled: _ } => SettingsType::MessageRequests,
+            SettingsData::RateLimit { rule: _ } => SettingsType::RateLimit,
+        };
+    }
+}
+
+#[account]
+#[derive(Default)]
+pub struct MailboxSettings {
+    pub settings: Vec<SettingsData>,
+}
+
+impl MailboxSettings {
+    /// Receivers that never configured anything have no settings account, which is not an error.
+    pub fn load(settings_info: &AccountInfo) -> Result<Option<MailboxSettings>> {
+        if settings_info.data_is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Account::<MailboxSettings>::try_from(settings_info)?.into_inner()))
+    }
+
+    pub fn get_setting(&self, settings_type: SettingsType) -> Option<& SettingsData> {
+        for setting in &self.settings {
+            if setting.get_type() == settings_type {
+                return Some(& setting);
+            }
+        }
+        return None;
+    }
+
+    /// Whether messages from senders outside the contacts list go to the request queue
+    pub fn message_requests_enabled(&self) -> bool {
+        match self.get_setting(SettingsType::MessageRequests) {
+            Some(SettingsData::MessageRequests { enabled }) => *enabled,
+            _ => false,
+        }
+    }
+
+    pub fn get_size(&self) -> usize {
+        // discriminator + settings
+        return 8 + crate::borsh_size(self);
+    }
+}
+
+pub fn settings_address(mailbox: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[crate::PROTOCOL_SEED.as_bytes(), crate::MAILBOX_SETTINGS_SEED.as_bytes(), mailbox.as_ref()],
+        &crate::ID,
+    ).0
+}
@@ -0,0 +1,94 @@
+use anchor_lang::prelude::*;
+use crate::delegates::DelegateScope;
+
+/// Names are used as a PDA seed, which cannot be longer than 32 bytes
+pub const MAX_SHARED_MAILBOX_NAME_LEN: usize = 32;
+
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq
+)]
+pub enum MemberRole {
+    /// Everything, including claiming incentives for the owner and managing members
+    Admin,
+    /// Moving the read pointer
+    Reader,
+    /// Moving the read pointer and deleting handled messages
+    Responder,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub key: Pubkey,
+    pub role: MemberRole,
+}
+
+/// A named inbox like `support` owned by `owner`. Its address is the receiver of the
+/// mailbox, so senders address it like any other key.
+#[account]
+#[derive(Default)]
+pub struct SharedMailbox {
+    pub owner: Pubkey,
+    pub name: String,
+    pub members: Vec<Member>,
+}
+
+impl SharedMailbox {
+    /// Regular receivers are wallets rather than shared mailboxes, which is not an error.
+    pub fn load(receiver_info: &AccountInfo) -> Result<Option<SharedMailbox>> {
+        if receiver_info.owner != &crate::ID || receiver_info.data_is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Account::<SharedMailbox>::try_from(receiver_info)?.into_inner()))
+    }
+
+    /// Add or update a member; no role removes them.
+    pub fn set_member(&mut self, key: Pubkey, role: Option<MemberRole>) {
+        self.members.retain(|member| member.key != key);
+        if let Some(role) = role {
+            self.members.push(Member { key, role });
+        }
+    }
+
+    pub fn is_admin(&self, key: &Pubkey) -> bool {
+        *key == self.owner || self.members.iter().any(|member| member.key == *key && member.role == MemberRole::Admin)
+    }
+
+    pub fn permits(&self, key: &Pubkey, scope: DelegateScope) -> bool {
+        if self.is_admin(key) {
+            return true;
+        }
+        self.members.iter().any(|member| {
+            member.key == *key && match scope {
+                DelegateScope::UpdateReadMessages => true,
+                DelegateScope::DeleteMessages => member.role == MemberRole::Responder,
+                DelegateScope::ClaimIncentives => false,
+            }
+        })
+    }
+
+    pub fn get_size(&self) -> usize {
+        // discriminator + owner + name + members_length + (key + role) per member
+        return 8 + 32 + 4 + self.name.len() + 4 + (32 + 1) * self.members.len();
+    }
+}
+
+/// Incentives claimed for a shared mailbox go to its owner, since nobody can sign for the
+/// mailbox address to spend them. Everyone else is paid directly.
+pub fn incentive_beneficiary(receiver_info: &AccountInfo) -> Pubkey {
+    match SharedMailbox::load(receiver_info) {
+        Ok(Some(shared_mailbox)) => shared_mailbox.owner,
+        _ => receiver_info.key(),
+    }
+}
+
+pub fn shared_mailbox_address(owner: &Pubkey, name: &str) -> Pubkey {
+    Pubkey::find_program_address(
+        &[crate::PROTOCOL_SEED.as_bytes(), crate::SHARED_MAILBOX_SEED.as_bytes(), owner.as_ref(), name.as_bytes()],
+        &crate::ID,
+    ).0
+}
@@ -0,0 +1,61 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hash;
+use anchor_lang::solana_program::sysvar::instructions::load_instruction_at_checked;
+
+/// Points at a message by the mailbox it was sent to and its index there.
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    Default,
+    PartialEq,
+    Eq
+)]
+pub struct MessageReference {
+    pub mailbox: Pubkey,
+    pub message_index: u32,
+}
+
+/// Positions of the original and reply messages among the `SendReply` accounts, read off the
+/// generated account list so they follow the struct's field order. Every field is named here, so
+/// adding one to `SendReply` does not build until it is placed.
+fn send_reply_positions() -> (usize, usize) {
+    let original_message = Pubkey::new_from_array([1; 32]);
+    let message = Pubkey::new_from_array([2; 32]);
+    let accounts = crate::accounts::SendReply {
+        original_mailbox: Pubkey::default(),
+        original_message,
+        mailbox: Pubkey::default(),
+        receiver: Pubkey::default(),
+        mailbox_settings: Pubkey::default(),
+        contacts: Pubkey::default(),
+        rate_limit: Pubkey::default(),
+        outbox: Pubkey::default(),
+        message,
+        payer: Pubkey::default(),
+        sender: Pubkey::default(),
+        config: Pubkey::default(),
+        fee_receiver: Pubkey::default(),
+        system_program: Pubkey::default(),
+    }.to_account_metas(None);
+    let position = |key: Pubkey| accounts.iter().position(|account| account.pubkey == key).unwrap();
+    (position(original_message), position(message))
+}
+
+/// Look through the current transaction for a `send_reply` answering `original_message` and
+/// return the address of the reply. `send_reply` only accepts originals from the replier's own
+/// mailbox, so such a reply always comes from the original message's receiver.
+pub fn find_reply_in_transaction(instructions: &AccountInfo, original_message: &Pubkey) -> Option<Pubkey> {
+    let discriminator = hash(b"global:send_reply").to_bytes();
+    let (original_message_position, message_position) = send_reply_positions();
+    let mut index = 0;
+    while let Ok(ix) = load_instruction_at_checked(index, instructions) {
+        let answers_original = ix.accounts.get(original_message_position)
+            .map_or(false, |account| account.pubkey == *original_message);
+        if ix.program_id == crate::ID && ix.data.starts_with(&discriminator[..8]) && answers_original {
+            return ix.accounts.get(message_position).map(|account| account.pubkey);
+        }
+        index += 1;
+    }
+    None
+}
@@ -0,0 +1,106 @@
+use anchor_lang::prelude::*;
+use crate::post_restrictions::{AdditionalAccountIndices, PostRestrictionRule};
+
+/// The basis points that make up a whole fee
+pub const MAX_PASS_DISCOUNT_BPS: u16 = 10_000;
+
+/// A protocol pass: holding it discounts the protocol fees
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    PartialEq,
+    Eq
+)]
+pub enum FeePass {
+    Token { mint: Pubkey, amount: u64 },
+    NftCollection { collection_id: Pubkey },
+}
+
+impl FeePass {
+    /// The proof is the trailing extra accounts: the holder's token account for a token
+    /// pass, or the token account, metadata and collection mint for an NFT pass.
+    pub fn is_held_by(&self, holder: &Pubkey, extra_accounts: &[AccountInfo]) -> bool {
+        let proof_len = match self {
+            FeePass::Token { mint: _, amount: _ } => 1,
+            FeePass::NftCollection { collection_id: _ } => 3,
+        };
+        if extra_accounts.len() < proof_len {
+            return false;
+        }
+        let start = match u8::try_from(extra_accounts.len() - proof_len) {
+            Ok(start) => start,
+            Err(_) => return false,
+        };
+        return match self {
+            FeePass::Token { mint, amount } => {
+                let rule = PostRestrictionRule::TokenOwnership { mint: *mint, amount: *amount };
+                let indices = vec![AdditionalAccountIndices::TokenOwnership { token_idx: start }];
+                rule.validate_token_ownership(holder, extra_accounts, &indices, mint, amount).is_ok()
+            },
+            FeePass::NftCollection { collection_id } => {
+                let rule = PostRestrictionRule::NftOwnership { collection_id: *collection_id };
+                let indices = vec![AdditionalAccountIndices::NftOwnership {
+                    token_idx: start,
+                    meta_idx: start + 1,
+                    collection_idx: start + 2,
+                }];
+                rule.validate_nft_ownership(holder, extra_accounts, &indices, collection_id).is_ok()
+            },
+        };
+    }
+}
+
+#[derive(
+    AnchorSerialize,
+    AnchorDeserialize,
+    Clone,
+    Default,
+    PartialEq,
+    Eq
+)]
+pub struct FeeSchedule {
+    pub new_postbox: u64,
+    pub new_personal_box: u64,
+    pub post: u64,
+    pub vote: u64,
+    pub pass: Option<FeePass>,
+    pub pass_discount_bps: u16,
+}
+
+impl FeeSchedule {
+    pub fn get_size(&self) -> usize {
+        return match self.try_to_vec() {
+            Ok(v) => v.len(),
+            Err(_) => 0,
+        };
+    }
+}
+
+/// Protocol-wide parameters that used to be compiled in. The admin can change them at any
+/// time without a redeploy.
+#[account]
+#[derive(Default)]
+pub struct ProtocolConfig {
+    pub admin: Pubkey,
+    pub treasury: Pubkey,
+    pub fees: FeeSchedule,
+}
+
+impl ProtocolConfig {
+    /// The given fee, discounted if the holder proved they hold the protocol pass
+    pub fn fee_for(&self, fee: u64, holder: &Pubkey, extra_accounts: &[AccountInfo]) -> u64 {
+        return match &self.fees.pass {
+            Some(pass) if pass.is_held_by(holder, extra_accounts) => {
+                let discount = (fee as u128) * (self.fees.pass_discount_bps as u128) / (MAX_PASS_DISCOUNT_BPS as u128);
+                fee.saturating_sub(discount as u64)
+            },
+            _ => fee,
+        };
+    }
+
+    pub fn get_size(&self) -> usize {
+        // discriminator + admin + treasury + fees
+        return 8 + 32 + 32 + self.fees.get_size();
+    }
+}
@@ -43,4 +43,14 @@ pub enum PostboxErrorCode {
     AlreadyVoted,
     #[msg("Missing a required credential for post restriction")]
     MissingCredentials,
+
+    // Protocol config errors
+    #[msg("Only the config admin can update the protocol config")]
+    NotConfigAdmin = 300,
+    #[msg("Only the program upgrade authority can initialize the protocol config")]
+    NotUpgradeAuthority,
+    #[msg("The treasury does not match the protocol config")]
+    InvalidTreasury,
+    #[msg("The pass discount cannot exceed 10000 basis points")]
+    InvalidPassDiscount,
 }
@@ -1,13 +1,15 @@
 use anchor_lang::prelude::*;
 use anchor_spl::{token, associated_token};
+use config::{FeeSchedule, ProtocolConfig, MAX_PASS_DISCOUNT_BPS};
 use errors::PostboxErrorCode;
 use post_restrictions::AdditionalAccountIndices;
 use settings::{SettingsData, SettingsType};
 use vote_entry::{VoteEntry};
 
+pub mod config;
 mod errors;
-mod nft_metadata;
-mod post_restrictions;
+pub mod nft_metadata;
+pub mod post_restrictions;
 mod settings;
 mod treasury;
 mod vote_entry;
@@ -22,19 +24,11 @@ const POSTBOX_SEED: & str = "postbox";
 const POST_SEED: & str = "post";
 const MODERATOR_SEED: & str = "moderator";
 const VOTE_TRACK_SEED: & str = "votes";
+const CONFIG_SEED: & str = "config";
 
 #[constant]
 const POSTBOX_GROW_CHILDREN_BY: u32 = 1_000;
 
-#[constant]
-const FEE_NEW_POSTBOX: u64 = 100_000;
-#[constant]
-const FEE_NEW_PERSONAL_BOX: u64 = 50_000;
-#[constant]
-const FEE_POST: u64 = 50_000;
-#[constant]
-const FEE_VOTE: u64 = 50_000;
-
 const MAX_VOTE: u16 = 60_000;
 
 // Features to support:
@@ -50,11 +44,54 @@ const MAX_VOTE: u16 = 60_000;
 pub mod postbox {
     use super::*;
 
+    /// Create the protocol config. Only the program's upgrade authority may do this, and only
+    /// once; afterwards the config admin takes over through `update_config`.
+    pub fn initialize_config(ctx: Context<InitializeConfig>, admin: Pubkey, treasury: Pubkey, fees: FeeSchedule) -> Result<()> {
+        require!(fees.pass_discount_bps <= MAX_PASS_DISCOUNT_BPS, PostboxErrorCode::InvalidPassDiscount);
+        if let Some(programdata_address) = ctx.accounts.program.programdata_address()? {
+            require!(ctx.accounts.program_data.key() == programdata_address, PostboxErrorCode::NotUpgradeAuthority);
+            let program_data = Account::<ProgramData>::try_from(&ctx.accounts.program_data)?;
+            require!(
+                program_data.upgrade_authority_address == Some(ctx.accounts.authority.key()),
+                PostboxErrorCode::NotUpgradeAuthority,
+            );
+        }
+        // Programs loaded without an upgrade authority (e.g. by a local test validator) have
+        // nobody else to defer to, so whoever initializes first becomes the admin
+
+        let config = &mut ctx.accounts.config;
+        config.admin = admin;
+        config.treasury = treasury;
+        config.fees = fees;
+        emit!(ConfigUpdatedEvent {
+            admin: config.admin,
+            treasury: config.treasury,
+            fees: config.fees.clone(),
+        });
+        Ok(())
+    }
+
+    /// Allow the config admin to change the fees, the treasury or hand over the admin role.
+    pub fn update_config(ctx: Context<UpdateConfig>, admin: Pubkey, treasury: Pubkey, fees: FeeSchedule) -> Result<()> {
+        require!(fees.pass_discount_bps <= MAX_PASS_DISCOUNT_BPS, PostboxErrorCode::InvalidPassDiscount);
+        let config = &mut ctx.accounts.config;
+        config.admin = admin;
+        config.treasury = treasury;
+        config.fees = fees;
+        resize_account(config.to_account_info().as_ref(), &ctx.accounts.admin, config.get_size())?;
+        emit!(ConfigUpdatedEvent {
+            admin: config.admin,
+            treasury: config.treasury,
+            fees: config.fees.clone(),
+        });
+        Ok(())
+    }
+
     pub fn initialize(ctx: Context<Initialize>, target: String, owners: Vec<Pubkey>, desc: Option<SettingsData>) -> Result<()> {
-        let mut fee = FEE_NEW_POSTBOX;
+        let mut fee = ctx.accounts.config.fees.new_postbox;
         if 0 == target.len() {  // Should be personal
             require!(ctx.accounts.target_account.key() == ctx.accounts.signer.key(), PostboxErrorCode::NotPersonalPostbox);
-            fee = FEE_NEW_PERSONAL_BOX;
+            fee = ctx.accounts.config.fees.new_personal_box;
         }
 
         let postbox_account = &mut ctx.accounts.postbox;
@@ -70,6 +107,7 @@ pub mod postbox {
             _ => return Err(Error::from(PostboxErrorCode::BadDescriptionSetting).with_source(source!())),
         }
 
+        let fee = ctx.accounts.config.fee_for(fee, &ctx.accounts.signer.key(), ctx.remaining_accounts);
         treasury::transfer_lamports(&ctx.accounts.signer, &ctx.accounts.treasury, fee)?;
         Ok(())
     }
@@ -119,7 +157,8 @@ pub mod postbox {
             reply_to: post_account.reply_to,
         });
 
-        treasury::transfer_lamports(&ctx.accounts.poster, &ctx.accounts.treasury, FEE_POST)?;
+        let fee = ctx.accounts.config.fee_for(ctx.accounts.config.fees.post, &ctx.accounts.poster.key(), ctx.remaining_accounts);
+        treasury::transfer_lamports(&ctx.accounts.poster, &ctx.accounts.treasury, fee)?;
         Ok(())
     }
 
@@ -183,7 +222,8 @@ pub mod postbox {
         let vote_count = if up_vote {&mut post_account.up_votes} else {&mut post_account.down_votes};
         *vote_count += if MAX_VOTE == *vote_count {0} else {1};
 
-        treasury::transfer_lamports(&ctx.accounts.voter, &ctx.accounts.treasury, FEE_VOTE)?;
+        let fee = ctx.accounts.config.fee_for(ctx.accounts.config.fees.vote, &ctx.accounts.voter.key(), ctx.remaining_accounts);
+        treasury::transfer_lamports(&ctx.accounts.voter, &ctx.accounts.treasury, fee)?;
         Ok(())
     }
 
@@ -245,6 +285,33 @@ pub mod postbox {
     }
 }
 
+#[derive(Accounts)]
+#[instruction(admin: Pubkey, treasury: Pubkey, fees: FeeSchedule)]
+pub struct InitializeConfig<'info> {
+    #[account(init,
+        payer = authority,
+        space = ProtocolConfig { fees: fees.clone(), ..Default::default() }.get_size(),
+        seeds = [PROTOCOL_SEED.as_bytes(), CONFIG_SEED.as_bytes()],
+        bump,
+    )]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub program: Program<'info, crate::program::Postbox>,
+    /// CHECK: only read when the program is upgradeable, checked against its programdata address in the body
+    pub program_data: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(mut, seeds = [PROTOCOL_SEED.as_bytes(), CONFIG_SEED.as_bytes()], bump)]
+    pub config: Box<Account<'info, ProtocolConfig>>,
+    #[account(mut, address = config.admin @ PostboxErrorCode::NotConfigAdmin)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 #[instruction(target: String, owners: Vec<Pubkey>, desc: Option<SettingsData>)]
 pub struct Initialize<'info> {
@@ -268,8 +335,10 @@ pub struct Initialize<'info> {
     pub target_account: UncheckedAccount<'info>,
     #[account(mut, constraint = owners.contains(signer.key))]
     pub signer: Signer<'info>,
+    #[account(seeds = [PROTOCOL_SEED.as_bytes(), CONFIG_SEED.as_bytes()], bump)]
+    pub config: Box<Account<'info, ProtocolConfig>>,
     /// CHECK: we do not access the data in the treasury other than to transfer lamports to it
-    #[account(mut, address = treasury::TREASURY_ADDRESS)]
+    #[account(mut, address = config.treasury @ PostboxErrorCode::InvalidTreasury)]
     pub treasury: UncheckedAccount<'info>,
     pub system_program: Program<'info, System>,
     pub token_program: Program<'info, token::Token>,
@@ -290,8 +359,10 @@ pub struct CreatePost<'info> {
     pub postbox: Box<Account<'info, Postbox>>,
     #[account(mut)]
     pub poster: Signer<'info>,
+    #[account(seeds = [PROTOCOL_SEED.as_bytes(), CONFIG_SEED.as_bytes()], bump)]
+    pub config: Box<Account<'info, ProtocolConfig>>,
     /// CHECK: we do not access the data in the treasury other than to transfer lamports to it
-    #[account(mut, address = treasury::TREASURY_ADDRESS)]
+    #[account(mut, address = config.treasury @ PostboxErrorCode::InvalidTreasury)]
     pub treasury: UncheckedAccount<'info>,
     /// CHECK: we allow passing default or a post, checked in body
     pub reply_to: UncheckedAccount<'info>,
@@ -351,8 +422,10 @@ pub struct Vote<'info> {
         bump,
     )]
     pub vote_tracker: Box<Account<'info, VoteTracker>>,
+    #[account(seeds = [PROTOCOL_SEED.as_bytes(), CONFIG_SEED.as_bytes()], bump)]
+    pub config: Box<Account<'info, ProtocolConfig>>,
     /// CHECK: we do not access the data in the treasury other than to transfer lamports to it
-    #[account(mut, address = treasury::TREASURY_ADDRESS)]
+    #[account(mut, address = config.treasury @ PostboxErrorCode::InvalidTreasury)]
     pub treasury: UncheckedAccount<'info>,
     pub system_program: Program<'info, System>,
 }
@@ -636,3 +709,10 @@ pub struct EditedEvent {
     old_data: Vec<u8>,
     new_data: Vec<u8>,
 }
+
+#[event]
+pub struct ConfigUpdatedEvent {
+    pub admin: Pubkey,
+    pub treasury: Pubkey,
+    pub fees: FeeSchedule,
+}
@@ -50,7 +50,7 @@ impl PostRestrictionRule {
         };
     }
 
-    fn validate_nft_ownership(&self,
+    pub fn validate_nft_ownership(&self,
         poster: &Pubkey,
         extra_accounts: &[AccountInfo],
         account_indices_vec: &Vec<AdditionalAccountIndices>,
@@ -86,7 +86,7 @@ impl PostRestrictionRule {
         Ok(())
     }
 
-    fn validate_token_ownership(&self,
+    pub fn validate_token_ownership(&self,
         poster: &Pubkey,
         extra_accounts: &[AccountInfo],
         account_indices_vec: &Vec<AdditionalAccountIndices>,
@@ -1,10 +1,14 @@
 import * as splToken from '@solana/spl-token';
 import * as anchor from '@project-serum/anchor';
 import { strict as assert } from 'assert';
+import { createHash } from 'crypto';
+import { decode } from 'bs58';
+import { config } from 'dotenv';
 import { Program } from '@project-serum/anchor';
 import { Messaging } from '../target/types/messaging';
 
 import { Mailbox, clusterAddresses, seeds } from '../usedispatch_client/src';
+import { getMetadataForOwner } from '../usedispatch_client/src/utils';
 
 describe('messaging', () => {
   // Configure the client to use the local cluster.
@@ -14,6 +18,54 @@ describe('messaging', () => {
   const conn = anchor.getProvider().connection;
   const TREASURY = clusterAddresses.get('devnet').treasuryAddress;
 
+  const getMailboxSettingsAddress = async (mailbox: anchor.web3.PublicKey) => {
+    const [mailboxSettings] = await anchor.web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.mailboxSettingsSeed, mailbox.toBuffer()],
+      program.programId,
+    );
+    return mailboxSettings;
+  };
+
+  const getConfigAddress = async () => {
+    const [config] = await anchor.web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.configSeed],
+      program.programId,
+    );
+    return config;
+  };
+
+  const getContactsAddress = async (mailbox: anchor.web3.PublicKey) => {
+    const [contacts] = await anchor.web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.contactsSeed, mailbox.toBuffer()],
+      program.programId,
+    );
+    return contacts;
+  };
+
+  const getDelegatesAddress = async (mailbox: anchor.web3.PublicKey) => {
+    const [delegates] = await anchor.web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.delegatesSeed, mailbox.toBuffer()],
+      program.programId,
+    );
+    return delegates;
+  };
+
+  const getOutboxAddress = async (sender: anchor.web3.PublicKey) => {
+    const [outbox] = await anchor.web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.outboxSeed, sender.toBuffer()],
+      program.programId,
+    );
+    return outbox;
+  };
+
+  const getRateLimitAddress = async (mailbox: anchor.web3.PublicKey, sender: anchor.web3.PublicKey) => {
+    const [rateLimit] = await anchor.web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.rateLimitSeed, mailbox.toBuffer(), sender.toBuffer()],
+      program.programId,
+    );
+    return rateLimit;
+  };
+
   it('Basic test', async () => {
     const receiver = anchor.web3.Keypair.generate();
     const sender = anchor.web3.Keypair.generate();
@@ -40,9 +92,14 @@ describe('messaging', () => {
       accounts: {
         mailbox,
         receiver: receiver.publicKey,
+        mailboxSettings: await getMailboxSettingsAddress(mailbox),
+        contacts: await getContactsAddress(mailbox),
+        outbox: await getOutboxAddress(sender.publicKey),
+        rateLimit: await getRateLimitAddress(mailbox, sender.publicKey),
         message: message0,
         payer: payer.publicKey,
         sender: sender.publicKey,
+        config: await getConfigAddress(),
         feeReceiver: TREASURY,
         systemProgram: anchor.web3.SystemProgram.programId,
       },
@@ -62,9 +119,14 @@ describe('messaging', () => {
       accounts: {
         mailbox,
         receiver: receiver.publicKey,
+        mailboxSettings: await getMailboxSettingsAddress(mailbox),
+        contacts: await getContactsAddress(mailbox),
+        outbox: await getOutboxAddress(sender.publicKey),
+        rateLimit: await getRateLimitAddress(mailbox, sender.publicKey),
         message: message1,
         payer: payer.publicKey,
         sender: sender.publicKey,
+        config: await getConfigAddress(),
         feeReceiver: TREASURY,
         systemProgram: anchor.web3.SystemProgram.programId,
       },
@@ -92,7 +154,9 @@ describe('messaging', () => {
         mailbox,
         receiver: receiver.publicKey,
         authorizedDeleter: receiver.publicKey,
+        delegates: await getDelegatesAddress(mailbox),
         message: message0,
+        outbox: await getOutboxAddress(sender.publicKey),
         rentDestination: payer.publicKey,
         systemProgram: anchor.web3.SystemProgram.programId,
       },
@@ -105,7 +169,9 @@ describe('messaging', () => {
         mailbox,
         receiver: receiver.publicKey,
         authorizedDeleter: receiver.publicKey,
+        delegates: await getDelegatesAddress(mailbox),
         message: message1,
+        outbox: await getOutboxAddress(sender.publicKey),
         rentDestination: payer.publicKey,
         systemProgram: anchor.web3.SystemProgram.programId,
       },
@@ -248,9 +314,14 @@ describe('messaging', () => {
       accounts: {
         mailbox,
         receiver: receiver.publicKey,
+        mailboxSettings: await getMailboxSettingsAddress(mailbox),
+        contacts: await getContactsAddress(mailbox),
+        outbox: await getOutboxAddress(payer.publicKey),
+        rateLimit: await getRateLimitAddress(mailbox, payer.publicKey),
         message: message0,
         payer: payer.publicKey,
         sender: payer.publicKey,
+        config: await getConfigAddress(),
         feeReceiver: TREASURY,
         systemProgram: anchor.web3.SystemProgram.programId,
       },
@@ -269,7 +340,9 @@ describe('messaging', () => {
           mailbox,
           receiver: receiver.publicKey,
           authorizedDeleter: receiver.publicKey,
+          delegates: await getDelegatesAddress(mailbox),
           message: message0,
+          outbox: await getOutboxAddress(payer.publicKey),
           rentDestination: receiver.publicKey, // Intentionally wrong
           systemProgram: anchor.web3.SystemProgram.programId,
         },
@@ -277,7 +350,7 @@ describe('messaging', () => {
       });
     } catch (e) {
       assert.ok(
-        String(e).startsWith('AnchorError caused by account: rent_destination. Error Code: ConstraintAddress.'),
+        String(e).startsWith('AnchorError caused by account: rent_destination. Error Code: RentDestinationNotPayer.'),
       );
     }
     console.log = oldConsoleLog;
@@ -318,9 +391,14 @@ describe('messaging', () => {
       accounts: {
         mailbox,
         receiver: receiver.publicKey,
+        mailboxSettings: await getMailboxSettingsAddress(mailbox),
+        contacts: await getContactsAddress(mailbox),
+        outbox: await getOutboxAddress(payer.publicKey),
+        rateLimit: await getRateLimitAddress(mailbox, payer.publicKey),
         message: message0,
         payer: payer.publicKey,
         sender: payer.publicKey,
+        config: await getConfigAddress(),
         feeReceiver: TREASURY,
         systemProgram: anchor.web3.SystemProgram.programId,
       },
@@ -454,6 +532,25 @@ describe('messaging', () => {
     assert.ok(messageAccount.incentiveMint.equals(mint));
     assert.equal((await receiverMailbox.fetchIncentiveTokenAccount(messageAccount)).amount, BigInt(incentiveAmount));
 
+    try {
+      await program.methods
+        .deleteMessage(0)
+        .accounts({
+          mailbox: await receiverMailbox.getMailboxAddress(),
+          receiver: receiver.publicKey,
+          authorizedDeleter: receiver.publicKey,
+          delegates: await receiverMailbox.getDelegatesAddress(),
+          message: await receiverMailbox.getMessageAddress(0),
+          outbox: await getOutboxAddress(sender.publicKey),
+          rentDestination: sender.publicKey,
+        })
+        .signers([receiver.payer])
+        .rpc();
+      assert.fail('Deleted a message with an unclaimed token incentive');
+    } catch (e) {
+      assert.ok(String(e).includes('TokenIncentiveOutstanding'));
+    }
+
     let eventEmitted = false;
     const subscriptionId = program.addEventListener('IncentiveClaimed', (event: any, _slot: number) => {
       program.removeEventListener(subscriptionId);
@@ -577,4 +674,1270 @@ describe('messaging', () => {
     const sentMessage = (await receiverMailbox.fetch())[0];
     assert.equal(sentMessage.data, message);
   });
+
+  it('Sends an encrypted message and publishes an encryption key', async () => {
+    const receiver = anchor.web3.Keypair.generate();
+    const payer = anchor.web3.Keypair.generate();
+    await conn.confirmTransaction(await conn.requestAirdrop(payer.publicKey, 2 * anchor.web3.LAMPORTS_PER_SOL));
+    await conn.confirmTransaction(await conn.requestAirdrop(receiver.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+
+    const [mailbox] = await anchor.web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.mailboxSeed, receiver.publicKey.toBuffer()],
+      program.programId,
+    );
+    const [encryptionKey] = await anchor.web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.encryptionKeySeed, mailbox.toBuffer()],
+      program.programId,
+    );
+
+    const publicKey = Array(32).fill(7);
+    await program.methods
+      .setEncryptionKey({ x25519XSalsa20Poly1305: {} }, publicKey)
+      .accounts({
+        mailbox,
+        receiver: receiver.publicKey,
+        authority: receiver.publicKey,
+        encryptionKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([receiver])
+      .rpc();
+    const keyAccount = await program.account.encryptionKey.fetch(encryptionKey);
+    assert.deepEqual(keyAccount.publicKey, publicKey);
+
+    const msgCountBuf0 = Buffer.allocUnsafe(4);
+    msgCountBuf0.writeInt32LE(0);
+    const [message0] = await anchor.web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.messageSeed, mailbox.toBuffer(), msgCountBuf0],
+      program.programId,
+    );
+
+    const header = {
+      scheme: { x25519XSalsa20Poly1305: {} },
+      senderEphemeralKey: Array(32).fill(1),
+      nonce: Array(24).fill(2),
+    };
+    const ciphertext = Buffer.from([9, 8, 7, 6, 5]);
+    await program.methods
+      .sendEncryptedMessage(header, ciphertext)
+      .accounts({
+        mailbox,
+        receiver: receiver.publicKey,
+        mailboxSettings: await getMailboxSettingsAddress(mailbox),
+        contacts: await getContactsAddress(mailbox),
+        outbox: await getOutboxAddress(payer.publicKey),
+        rateLimit: await getRateLimitAddress(mailbox, payer.publicKey),
+        message: message0,
+        payer: payer.publicKey,
+        sender: payer.publicKey,
+        config: await getConfigAddress(),
+        feeReceiver: TREASURY,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([payer])
+      .rpc();
+
+    const messageAccount = await program.account.message.fetch(message0);
+    assert.equal(messageAccount.data, '');
+    assert.deepEqual(messageAccount.encryption.nonce, header.nonce);
+    assert.deepEqual(messageAccount.encryption.senderEphemeralKey, header.senderEphemeralKey);
+    assert.ok(Buffer.from(messageAccount.ciphertext).equals(ciphertext));
+  });
+
+  it('Refunds an expired incentive to the payer', async () => {
+    const receiver = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    await conn.confirmTransaction(await conn.requestAirdrop(sender.publicKey, 2 * anchor.web3.LAMPORTS_PER_SOL));
+
+    const mint = await splToken.createMint(conn, sender.payer, sender.publicKey, null, 10);
+    const ata = await splToken.createAssociatedTokenAccount(conn, sender.payer, mint, sender.publicKey);
+    await conn.confirmTransaction(await splToken.mintTo(conn, sender.payer, mint, ata, sender.payer, 1_000_000));
+
+    const receiverMailbox = new Mailbox(conn, receiver);
+    const senderMailbox = new Mailbox(conn, sender);
+
+    const sendOpts = {
+      incentive: {
+        mint,
+        amount: 1_000_000,
+        payerAccount: ata,
+        expiresAt: new Date(new Date().getTime() + 2_000),
+      },
+    };
+    await senderMailbox.send('message with expiring incentive', receiver.publicKey, sendOpts);
+    const messageAccount = await receiverMailbox.fetchMessageById(0);
+    assert.ok(messageAccount.incentiveExpiresAt !== undefined);
+    assert.equal((await splToken.getAccount(conn, ata)).amount, BigInt(0));
+
+    await new Promise((resolve) => setTimeout(resolve, 5_000));
+
+    let eventEmitted = false;
+    const subscriptionId = program.addEventListener('IncentiveRefunded', (event: any, _slot: number) => {
+      program.removeEventListener(subscriptionId);
+      eventEmitted = true;
+      assert.ok(event.senderPubkey.equals(sender.publicKey));
+      assert.ok(event.mint.equals(mint));
+      assert.equal(event.amount.toNumber(), 1_000_000);
+    });
+
+    await conn.confirmTransaction(await senderMailbox.refundIncentive(messageAccount));
+    assert.ok(eventEmitted);
+    assert.equal((await splToken.getAccount(conn, ata)).amount, BigInt(1_000_000));
+
+    const messageAccountAfter = await receiverMailbox.fetchMessageById(0);
+    assert.equal(messageAccountAfter.incentiveMint, undefined);
+  });
+
+  it('Sends a message with a lamport incentive and claims it', async () => {
+    const receiver = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    await conn.confirmTransaction(await conn.requestAirdrop(receiver.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    await conn.confirmTransaction(await conn.requestAirdrop(sender.publicKey, 2 * anchor.web3.LAMPORTS_PER_SOL));
+
+    const receiverMailbox = new Mailbox(conn, receiver);
+    const senderMailbox = new Mailbox(conn, sender);
+
+    const incentiveLamports = 1_000_000;
+    await senderMailbox.send('message with lamports', receiver.publicKey, {
+      solIncentive: { lamports: incentiveLamports },
+    });
+    const messageAccount = await receiverMailbox.fetchMessageById(0);
+    assert.equal(messageAccount.incentiveLamports, incentiveLamports);
+
+    let eventEmitted = false;
+    const subscriptionId = program.addEventListener('IncentiveClaimed', (event: any, _slot: number) => {
+      program.removeEventListener(subscriptionId);
+      eventEmitted = true;
+      assert.ok(event.mint.equals(anchor.web3.PublicKey.default));
+      assert.ok(event.incentiveType.lamports !== undefined);
+      assert.equal(event.amount.toNumber(), incentiveLamports);
+    });
+
+    const balanceBefore = await conn.getBalance(receiver.publicKey);
+    await conn.confirmTransaction(await receiverMailbox.claimIncentive(messageAccount));
+    assert.ok(eventEmitted);
+    const balanceAfter = await conn.getBalance(receiver.publicKey);
+    // The receiver pays the transaction fee out of the incentive
+    assert.ok(balanceAfter > balanceBefore + incentiveLamports - 10_000);
+
+    const messageAccountAfter = await receiverMailbox.fetchMessageById(0);
+    assert.equal(messageAccountAfter.incentiveLamports, undefined);
+  });
+
+  it('Charges receiver-defined postage', async () => {
+    const receiver = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    await conn.confirmTransaction(await conn.requestAirdrop(receiver.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    await conn.confirmTransaction(await conn.requestAirdrop(sender.publicKey, 2 * anchor.web3.LAMPORTS_PER_SOL));
+
+    const receiverMailbox = new Mailbox(conn, receiver);
+    const senderMailbox = new Mailbox(conn, sender);
+
+    const postage = 250_000;
+    await receiverMailbox.updateSetting({ postage: { postage: { lamports: { amount: new anchor.BN(postage) } } } });
+    const settings = await receiverMailbox.fetchMailboxSettings();
+    assert.equal(settings.settings.length, 1);
+
+    const balanceBefore = await conn.getBalance(receiver.publicKey);
+    await senderMailbox.send('paid message', receiver.publicKey);
+    const balanceAfter = await conn.getBalance(receiver.publicKey);
+    assert.equal(balanceAfter, balanceBefore + postage);
+
+    // Token postage
+    const mint = await splToken.createMint(conn, sender.payer, sender.publicKey, null, 0);
+    const senderAta = await splToken.createAssociatedTokenAccount(conn, sender.payer, mint, sender.publicKey);
+    const receiverAta = await splToken.createAssociatedTokenAccount(conn, sender.payer, mint, receiver.publicKey);
+    await conn.confirmTransaction(await splToken.mintTo(conn, sender.payer, mint, senderAta, sender.payer, 10));
+    await receiverMailbox.updateSetting({ postage: { postage: { token: { mint, amount: new anchor.BN(3) } } } });
+
+    await senderMailbox.send('paid in tokens', receiver.publicKey);
+    assert.equal((await splToken.getAccount(conn, receiverAta)).amount, BigInt(3));
+    assert.equal((await splToken.getAccount(conn, senderAta)).amount, BigInt(7));
+  });
+
+  it('Rejects blocked senders and senders missing from the allow list', async () => {
+    const receiver = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const blockedSender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const allowedSender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const otherSender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    for (const wallet of [receiver, blockedSender, allowedSender, otherSender]) {
+      await conn.confirmTransaction(await conn.requestAirdrop(wallet.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    }
+
+    const receiverMailbox = new Mailbox(conn, receiver);
+    await receiverMailbox.updateSetting({
+      senderBlockList: { rules: [{ address: { key: blockedSender.publicKey } }] },
+    });
+
+    try {
+      await new Mailbox(conn, blockedSender).send('spam', receiver.publicKey);
+      assert.fail('Blocked sender was able to send');
+    } catch (e) {
+      assert.ok(String(e).includes('SenderBlocked') || String(e).includes('0x1773'));
+    }
+
+    await new Mailbox(conn, otherSender).send('hello', receiver.publicKey);
+    assert.equal(await receiverMailbox.count(), 1);
+
+    await receiverMailbox.updateSetting({
+      senderAllowList: { rules: [{ address: { key: allowedSender.publicKey } }] },
+    });
+    try {
+      await new Mailbox(conn, otherSender).send('hello again', receiver.publicKey);
+      assert.fail('Sender missing from the allow list was able to send');
+    } catch (e) {
+      assert.ok(String(e).includes('SenderNotAllowed') || String(e).includes('0x1774'));
+    }
+
+    await new Mailbox(conn, allowedSender).send('allowed', receiver.publicKey);
+    assert.equal(await receiverMailbox.count(), 2);
+  });
+
+  it('Routes unknown senders to message requests', async () => {
+    const receiver = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const spammer = new anchor.Wallet(anchor.web3.Keypair.generate());
+    for (const wallet of [receiver, sender, spammer]) {
+      await conn.confirmTransaction(await conn.requestAirdrop(wallet.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    }
+
+    const receiverMailbox = new Mailbox(conn, receiver);
+    const senderMailbox = new Mailbox(conn, sender);
+    const spammerMailbox = new Mailbox(conn, spammer);
+    await receiverMailbox.updateSetting({ messageRequests: { enabled: true } });
+
+    await senderMailbox.send('can we talk?', receiver.publicKey);
+    await spammerMailbox.send('buy now', receiver.publicKey);
+    assert.equal(await receiverMailbox.count(), 0);
+    let requests = await receiverMailbox.fetchMessageRequests();
+    assert.equal(requests.length, 2);
+    assert.ok(requests[0].sender.equals(sender.publicKey));
+
+    // The request queue cannot be skipped by sending to the main mailbox directly
+    const mailbox = await receiverMailbox.getMailboxAddress();
+    try {
+      await program.methods
+        .sendMessage('sneaking in')
+        .accounts({
+          mailbox,
+          receiver: receiver.publicKey,
+          mailboxSettings: await getMailboxSettingsAddress(mailbox),
+          contacts: await getContactsAddress(mailbox),
+          outbox: await getOutboxAddress(spammer.publicKey),
+          rateLimit: await getRateLimitAddress(mailbox, spammer.publicKey),
+          message: await receiverMailbox.getMessageAddress(0),
+          payer: spammer.publicKey,
+          sender: spammer.publicKey,
+          config: await getConfigAddress(),
+          feeReceiver: TREASURY,
+        })
+        .signers([spammer.payer])
+        .rpc();
+      assert.fail('Sender outside contacts reached the main mailbox');
+    } catch (e) {
+      assert.ok(String(e).includes('SenderNotInContacts'));
+    }
+
+    const spammerBalance = await conn.getBalance(spammer.publicKey);
+    await receiverMailbox.rejectMessageRequest(1);
+    assert.ok((await conn.getBalance(spammer.publicKey)) > spammerBalance);
+
+    await receiverMailbox.acceptSender(sender.publicKey);
+    const contacts = await receiverMailbox.fetchContacts();
+    assert.equal(contacts.length, 1);
+    await senderMailbox.send('thanks for accepting', receiver.publicKey);
+    assert.equal(await receiverMailbox.count(), 1);
+    requests = await receiverMailbox.fetchMessageRequests();
+    assert.equal(requests.length, 1);
+  });
+
+  it('Threads replies back to the original sender', async () => {
+    const alice = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const bob = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const eve = new anchor.Wallet(anchor.web3.Keypair.generate());
+    for (const wallet of [alice, bob, eve]) {
+      await conn.confirmTransaction(await conn.requestAirdrop(wallet.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    }
+
+    const aliceMailbox = new Mailbox(conn, alice);
+    const bobMailbox = new Mailbox(conn, bob);
+    await aliceMailbox.sendMessage('lunch?', 'are you free today', bob.publicKey);
+    const [original] = await bobMailbox.fetchMessages();
+
+    await bobMailbox.reply(original, 're: lunch?', 'sure, noon works');
+    const [reply] = await aliceMailbox.fetchMessages();
+    assert.ok(reply.sender.equals(bob.publicKey));
+    assert.ok(reply.replyTo.mailbox.equals(await bobMailbox.getMailboxAddress()));
+    assert.equal(reply.replyTo.messageId, original.messageId);
+
+    // Replies can only go to whoever sent the original message
+    try {
+      await bobMailbox.sendTransaction(await bobMailbox.makeReplyTx('not for you', original.messageId, eve.publicKey));
+      assert.fail('Reply was delivered to someone other than the original sender');
+    } catch (e) {
+      assert.ok(String(e).includes('ReplyReceiverNotOriginalSender'));
+    }
+  });
+
+  it('Broadcasts a message to several mailboxes', async () => {
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    await conn.confirmTransaction(await conn.requestAirdrop(sender.publicKey, 2 * anchor.web3.LAMPORTS_PER_SOL));
+    const receivers = [0, 1, 2].map(() => anchor.web3.Keypair.generate().publicKey);
+
+    const senderMailbox = new Mailbox(conn, sender);
+    // One receiver already has a mailbox, the others are created by the broadcast
+    await senderMailbox.send('hi', receivers[0]);
+
+    const treasuryBalance = await conn.getBalance(TREASURY);
+    await senderMailbox.broadcast('announcement', receivers);
+    assert.equal(await conn.getBalance(TREASURY), treasuryBalance + 3 * 50000);
+    for (const receiver of receivers) {
+      const receiverMailbox = new Mailbox(conn, sender, { mailboxOwner: receiver });
+      const messages = await receiverMailbox.fetchMessages();
+      assert.equal(messages[messages.length - 1].data.body, 'announcement');
+      assert.ok(messages[messages.length - 1].sender.equals(sender.publicKey));
+    }
+
+    try {
+      await program.methods
+        .broadcastMessage('too many', 6)
+        .accounts({
+          outbox: await getOutboxAddress(sender.publicKey),
+          payer: sender.publicKey,
+          sender: sender.publicKey,
+          config: await getConfigAddress(),
+          feeReceiver: TREASURY,
+        })
+        .signers([sender.payer])
+        .rpc();
+      assert.fail('Broadcast accepted too many recipients');
+    } catch (e) {
+      assert.ok(String(e).includes('TooManyRecipients'));
+    }
+  });
+
+  it('Closes an empty mailbox and returns rent to its payer', async () => {
+    const receiver = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    for (const wallet of [receiver, sender]) {
+      await conn.confirmTransaction(await conn.requestAirdrop(wallet.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    }
+
+    const receiverMailbox = new Mailbox(conn, receiver);
+    const senderMailbox = new Mailbox(conn, sender);
+    await senderMailbox.send('one', receiver.publicKey);
+    await senderMailbox.send('two', receiver.publicKey);
+    await senderMailbox.send('three', receiver.publicKey);
+
+    try {
+      await receiverMailbox.closeMailbox();
+      assert.fail('Closed a mailbox with unread messages');
+    } catch (e) {
+      assert.ok(String(e).includes('MailboxNotEmpty'));
+    }
+
+    // Deleting out of order leaves the read pointer behind the deleted message
+    await receiverMailbox.delete(1);
+    await receiverMailbox.delete(0);
+    assert.equal((await receiverMailbox.countEx()).readMessageCount, 1);
+
+    // Messages skipped over by the read pointer still keep the mailbox open
+    await receiverMailbox.updateReadMessages(3);
+    try {
+      await receiverMailbox.closeMailbox();
+      assert.fail('Closed a mailbox with a message behind the read pointer');
+    } catch (e) {
+      assert.ok(String(e).includes('MailboxNotEmpty'));
+    }
+    await receiverMailbox.delete(2);
+
+    // Funding a deleted message's address does not bring the message back
+    const deletedAddress = await receiverMailbox.getMessageAddress(1);
+    const fundTx = new anchor.web3.Transaction().add(
+      anchor.web3.SystemProgram.transfer({
+        fromPubkey: sender.publicKey,
+        toPubkey: deletedAddress,
+        lamports: await conn.getMinimumBalanceForRentExemption(0),
+      }),
+    );
+    await anchor.web3.sendAndConfirmTransaction(conn, fundTx, [sender.payer]);
+
+    // Checks can be spread over several transactions; the mailbox stays open until the last one
+    const mailboxAddress = await receiverMailbox.getMailboxAddress();
+    const firstTwo = await Promise.all([0, 1].map((id) => receiverMailbox.getMessageAddress(id)));
+    await program.methods
+      .closeMailbox()
+      .accounts({
+        mailbox: mailboxAddress,
+        receiver: receiver.publicKey,
+        authority: receiver.publicKey,
+        rentDestination: sender.publicKey,
+      })
+      .remainingAccounts(firstTwo.map((pubkey) => ({ pubkey, isWritable: false, isSigner: false })))
+      .signers([receiver.payer])
+      .rpc();
+    assert.equal((await program.account.mailbox.fetch(mailboxAddress)).closeCheckedCount, 2);
+
+    const mailboxRent = await conn.getBalance(mailboxAddress);
+    const senderBalance = await conn.getBalance(sender.publicKey);
+    await receiverMailbox.closeMailbox();
+    assert.equal(await conn.getBalance(mailboxAddress), 0);
+    assert.equal(await conn.getBalance(sender.publicKey), senderBalance + mailboxRent);
+  });
+
+  it('Reports messaging error codes', async () => {
+    const receiver = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const stranger = anchor.web3.Keypair.generate();
+    for (const wallet of [receiver, sender]) {
+      await conn.confirmTransaction(await conn.requestAirdrop(wallet.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    }
+    const receiverMailbox = new Mailbox(conn, receiver);
+    await new Mailbox(conn, sender).send('hello', receiver.publicKey);
+
+    try {
+      await program.methods
+        .updateReadMessages(2)
+        .accounts({
+          mailbox: await receiverMailbox.getMailboxAddress(),
+          receiver: receiver.publicKey,
+          authority: receiver.publicKey,
+          delegates: await receiverMailbox.getDelegatesAddress(),
+        })
+        .signers([receiver.payer])
+        .rpc();
+      assert.fail('Read count moved past the message count');
+    } catch (e) {
+      assert.ok(String(e).includes('ReadCountExceedsMessageCount'));
+    }
+
+    try {
+      await program.methods
+        .deleteMessage(0)
+        .accounts({
+          mailbox: await receiverMailbox.getMailboxAddress(),
+          receiver: receiver.publicKey,
+          authorizedDeleter: stranger.publicKey,
+          delegates: await receiverMailbox.getDelegatesAddress(),
+          message: await receiverMailbox.getMessageAddress(0),
+          outbox: await getOutboxAddress(sender.publicKey),
+          rentDestination: sender.publicKey,
+        })
+        .signers([stranger])
+        .rpc();
+      assert.fail('A stranger deleted the message');
+    } catch (e) {
+      assert.ok(String(e).includes('NotAuthorizedToDelete'));
+    }
+  });
+
+  it('Emits mailbox lifecycle events', async () => {
+    const receiver = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    for (const wallet of [receiver, sender]) {
+      await conn.confirmTransaction(await conn.requestAirdrop(wallet.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    }
+    const receiverMailbox = new Mailbox(conn, receiver);
+    const senderMailbox = new Mailbox(conn, sender);
+    const mailboxAddress = await receiverMailbox.getMailboxAddress();
+
+    const events: { name: string; event: any }[] = [];
+    const listeners = ['MailboxCreated', 'DispatchMessage', 'MessageDeleted', 'ReadCountUpdated', 'MailboxClosed'].map(
+      (name) =>
+        program.addEventListener(name, (event: any) => {
+          if (event.mailboxPubkey.equals(mailboxAddress)) {
+            events.push({ name, event });
+          }
+        }),
+    );
+
+    await conn.confirmTransaction(await senderMailbox.send('one', receiver.publicKey));
+    await conn.confirmTransaction(await senderMailbox.send('two', receiver.publicKey));
+    await conn.confirmTransaction(await receiverMailbox.delete(0));
+    await program.methods
+      .updateReadMessages(2)
+      .accounts({
+        mailbox: await receiverMailbox.getMailboxAddress(),
+        receiver: receiver.publicKey,
+        authority: receiver.publicKey,
+        delegates: await receiverMailbox.getDelegatesAddress(),
+      })
+      .signers([receiver.payer])
+      .rpc();
+    await conn.confirmTransaction(await receiverMailbox.delete(1));
+    await conn.confirmTransaction(await receiverMailbox.closeMailbox());
+    await new Promise((resolve) => setTimeout(resolve, 1000));
+    for (const listener of listeners) {
+      await program.removeEventListener(listener);
+    }
+
+    assert.deepEqual(
+      events.map((e) => e.name),
+      ['MailboxCreated', 'DispatchMessage', 'DispatchMessage', 'MessageDeleted', 'ReadCountUpdated', 'MessageDeleted', 'MailboxClosed'],
+    );
+    assert.ok(events.every((e) => e.event.version === 5));
+    assert.ok(events[0].event.payerPubkey.equals(sender.publicKey));
+    assert.equal(events[3].event.readMessageCount, 1);
+    assert.equal(events[4].event.readMessageCount, 2);
+  });
+
+  it('Reads fees and treasury from the protocol config', async () => {
+    const provider = anchor.getProvider() as anchor.AnchorProvider;
+    const admin = provider.wallet.publicKey;
+    const config = await program.account.protocolConfig.fetch(await getConfigAddress());
+    assert.ok(config.admin.equals(admin));
+    assert.ok(config.treasury.equals(TREASURY));
+
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    await conn.confirmTransaction(await conn.requestAirdrop(sender.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    const senderMailbox = new Mailbox(conn, sender);
+
+    let eventAdmin: anchor.web3.PublicKey | undefined;
+    const listener = program.addEventListener('ConfigUpdated', (event: any) => {
+      eventAdmin = event.admin;
+    });
+    await program.methods
+      .updateConfig(admin, TREASURY, { ...config.fees, messageFee: new anchor.BN(75_000) })
+      .accounts({ config: await getConfigAddress() })
+      .rpc();
+    try {
+      const treasuryBalance = await conn.getBalance(TREASURY);
+      await senderMailbox.send('pricier', anchor.web3.Keypair.generate().publicKey);
+      assert.equal(await conn.getBalance(TREASURY), treasuryBalance + 75_000);
+    } finally {
+      await program.methods
+        .updateConfig(admin, TREASURY, config.fees)
+        .accounts({ config: await getConfigAddress() })
+        .rpc();
+    }
+    await new Promise((resolve) => setTimeout(resolve, 1000));
+    await program.removeEventListener(listener);
+    assert.ok(eventAdmin.equals(admin));
+
+    try {
+      await program.methods
+        .updateConfig(sender.publicKey, sender.publicKey, { ...config.fees, messageFee: new anchor.BN(0) })
+        .accounts({ config: await getConfigAddress(), admin: sender.publicKey })
+        .signers([sender.payer])
+        .rpc();
+      assert.fail('Someone other than the admin updated the config');
+    } catch (e) {
+      assert.ok(String(e).includes('NotConfigAdmin'));
+    }
+  });
+
+  it('Waives the message fee for protocol pass holders', async () => {
+    const provider = anchor.getProvider() as anchor.AnchorProvider;
+    const admin = provider.wallet.publicKey;
+    const config = await program.account.protocolConfig.fetch(await getConfigAddress());
+
+    const holder = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const other = new anchor.Wallet(anchor.web3.Keypair.generate());
+    for (const wallet of [holder, other]) {
+      await conn.confirmTransaction(await conn.requestAirdrop(wallet.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    }
+    const passMint = await splToken.createMint(conn, holder.payer, holder.publicKey, null, 0);
+    const holderAta = await splToken.createAssociatedTokenAccount(conn, holder.payer, passMint, holder.publicKey);
+    await conn.confirmTransaction(await splToken.mintTo(conn, holder.payer, passMint, holderAta, holder.payer, 1));
+
+    await program.methods
+      .updateConfig(admin, TREASURY, {
+        ...config.fees,
+        pass: { token: { mint: passMint, amount: new anchor.BN(1) } },
+        passDiscountBps: 10_000,
+      })
+      .accounts({ config: await getConfigAddress() })
+      .rpc();
+    try {
+      const receiver = anchor.web3.Keypair.generate().publicKey;
+      let treasuryBalance = await conn.getBalance(TREASURY);
+      await new Mailbox(conn, holder).send('free for pass holders', receiver);
+      assert.equal(await conn.getBalance(TREASURY), treasuryBalance);
+
+      treasuryBalance = await conn.getBalance(TREASURY);
+      await new Mailbox(conn, other).send('full price', receiver);
+      assert.equal(await conn.getBalance(TREASURY), treasuryBalance + config.fees.messageFee.toNumber());
+    } finally {
+      await program.methods
+        .updateConfig(admin, TREASURY, config.fees)
+        .accounts({ config: await getConfigAddress() })
+        .rpc();
+    }
+  });
+
+  it('Sends a relayed message paid for by the relayer', async () => {
+    const sender = anchor.web3.Keypair.generate();
+    const relayer = new anchor.Wallet(anchor.web3.Keypair.generate());
+    await conn.confirmTransaction(await conn.requestAirdrop(relayer.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    const receiver = anchor.web3.Keypair.generate().publicKey;
+    const relayerMailbox = new Mailbox(conn, relayer);
+
+    // Keypairs cannot sign arbitrary messages without another dependency, but the ed25519
+    // helper signs with a private key: its data is a 16 byte header, the public key, then the signature
+    const signRelayed = (signer: anchor.web3.Keypair, data: string, nonce: number) => {
+      const ix = anchor.web3.Ed25519Program.createInstructionWithPrivateKey({
+        privateKey: signer.secretKey,
+        message: relayerMailbox.getRelayMessageBytes(receiver, nonce, data),
+      });
+      return { sender: sender.publicKey, receiver, data, nonce, signature: ix.data.slice(48, 112) };
+    };
+
+    const relayed = signRelayed(sender, 'relayed hello', await relayerMailbox.fetchSenderNonce(sender.publicKey));
+    await relayerMailbox.sendRelayed(relayed);
+    assert.equal(await conn.getBalance(sender.publicKey), 0);
+    assert.equal(await relayerMailbox.fetchSenderNonce(sender.publicKey), 1);
+
+    const receiverMailbox = new Mailbox(conn, new anchor.Wallet(anchor.web3.Keypair.generate()), {
+      mailboxOwner: receiver,
+    });
+    const messages = await receiverMailbox.fetchMessages();
+    assert.equal(messages.length, 1);
+    assert.ok(messages[0].sender.equals(sender.publicKey));
+    assert.equal(messages[0].data.body, 'relayed hello');
+
+    try {
+      await relayerMailbox.sendRelayed(relayed);
+      assert.fail('A relayed message was replayed');
+    } catch (e) {
+      assert.ok(String(e).includes('InvalidRelayNonce'));
+    }
+
+    try {
+      await relayerMailbox.sendRelayed(signRelayed(anchor.web3.Keypair.generate(), 'forged', 1));
+      assert.fail('A message was relayed without the sender signature');
+    } catch (e) {
+      // Rejected by the ed25519 program before the messaging program runs
+      assert.ok(!(e instanceof assert.AssertionError));
+    }
+    assert.equal(await relayerMailbox.fetchSenderNonce(sender.publicKey), 1);
+  });
+
+  it('Lets a delegate manage the mailbox within its permissions', async () => {
+    const receiver = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const delegate = new anchor.Wallet(anchor.web3.Keypair.generate());
+    for (const wallet of [receiver, sender, delegate]) {
+      await conn.confirmTransaction(await conn.requestAirdrop(wallet.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    }
+    const receiverMailbox = new Mailbox(conn, receiver);
+    const delegateMailbox = new Mailbox(conn, delegate, { mailboxOwner: receiver.publicKey });
+
+    const incentiveLamports = 1_000_000;
+    await new Mailbox(conn, sender).send('for the support team', receiver.publicKey, {
+      solIncentive: { lamports: incentiveLamports },
+    });
+    await new Mailbox(conn, sender).send('second', receiver.publicKey);
+
+    await receiverMailbox.setDelegate(delegate.publicKey, {
+      updateReadMessages: true,
+      deleteMessages: false,
+      claimIncentives: true,
+    });
+    const delegates = await receiverMailbox.fetchDelegates();
+    assert.equal(delegates.length, 1);
+    assert.ok(delegates[0].key.equals(delegate.publicKey));
+
+    // Claimed by the delegate, paid to the receiver
+    const receiverBalance = await conn.getBalance(receiver.publicKey);
+    await conn.confirmTransaction(await delegateMailbox.claimIncentive(await delegateMailbox.fetchMessageById(0)));
+    assert.equal(await conn.getBalance(receiver.publicKey), receiverBalance + incentiveLamports);
+
+    await conn.confirmTransaction(await delegateMailbox.updateReadMessages(1));
+    assert.equal((await receiverMailbox.countEx()).readMessageCount, 1);
+
+    try {
+      await delegateMailbox.delete(1);
+      assert.fail('A delegate without the delete permission deleted a message');
+    } catch (e) {
+      assert.ok(String(e).includes('NotAuthorizedToDelete'));
+    }
+
+    await receiverMailbox.removeDelegate(delegate.publicKey);
+    assert.equal((await receiverMailbox.fetchDelegates()).length, 0);
+    try {
+      await delegateMailbox.updateReadMessages(2);
+      assert.fail('A removed delegate updated the read pointer');
+    } catch (e) {
+      assert.ok(String(e).includes('NotMailboxAuthority'));
+    }
+  });
+
+  it('Shares a named mailbox between members with roles', async () => {
+    const owner = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const admin = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const responder = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const reader = new anchor.Wallet(anchor.web3.Keypair.generate());
+    for (const wallet of [owner, sender, admin, responder, reader]) {
+      await conn.confirmTransaction(await conn.requestAirdrop(wallet.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    }
+    const ownerMailbox = new Mailbox(conn, owner);
+    await conn.confirmTransaction(
+      await ownerMailbox.createSharedMailbox('support', [
+        { key: admin.publicKey, role: { admin: {} } },
+        { key: responder.publicKey, role: { responder: {} } },
+        { key: reader.publicKey, role: { reader: {} } },
+      ]),
+    );
+    const support = await ownerMailbox.getSharedMailboxAddress('support');
+    const asMember = (wallet: anchor.Wallet) => new Mailbox(conn, wallet, { mailboxOwner: support });
+
+    const incentiveLamports = 1_000_000;
+    const senderMailbox = new Mailbox(conn, sender);
+    await senderMailbox.send('help please', support, { solIncentive: { lamports: incentiveLamports } });
+    await senderMailbox.send('still need help', support);
+
+    try {
+      await asMember(responder).claimIncentive(await asMember(responder).fetchMessageById(0));
+      assert.fail('A responder claimed an incentive');
+    } catch (e) {
+      assert.ok(String(e).includes('NotMailboxAuthority'));
+    }
+
+    // Claimed by an admin, paid to the owner since nobody can sign for the shared address
+    const ownerBalance = await conn.getBalance(owner.publicKey);
+    await asMember(admin).claimIncentive(await asMember(admin).fetchMessageById(0));
+    assert.equal(await conn.getBalance(owner.publicKey), ownerBalance + incentiveLamports);
+
+    await conn.confirmTransaction(await asMember(reader).updateReadMessages(1));
+    try {
+      await asMember(reader).delete(1);
+      assert.fail('A reader deleted a message');
+    } catch (e) {
+      assert.ok(String(e).includes('NotAuthorizedToDelete'));
+    }
+    await conn.confirmTransaction(await asMember(responder).delete(1));
+    assert.equal((await asMember(reader).countEx()).readMessageCount, 2);
+
+    await conn.confirmTransaction(await new Mailbox(conn, admin).setSharedMailboxMember(support, reader.publicKey, null));
+    const sharedMailbox = await ownerMailbox.fetchSharedMailbox(support);
+    assert.equal(sharedMailbox.members.length, 2);
+    try {
+      await new Mailbox(conn, responder).setSharedMailboxMember(support, responder.publicKey, { admin: {} });
+      assert.fail('A responder promoted themselves');
+    } catch (e) {
+      assert.ok(String(e).includes('NotSharedMailboxAdmin'));
+    }
+  });
+
+  it('Lets shared mailbox admins configure and close it', async () => {
+    const owner = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const admin = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const responder = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const stranger = new anchor.Wallet(anchor.web3.Keypair.generate());
+    for (const wallet of [owner, sender, admin, responder, stranger]) {
+      await conn.confirmTransaction(await conn.requestAirdrop(wallet.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    }
+    const ownerMailbox = new Mailbox(conn, owner);
+    await conn.confirmTransaction(
+      await ownerMailbox.createSharedMailbox('sales', [
+        { key: admin.publicKey, role: { admin: {} } },
+        { key: responder.publicKey, role: { responder: {} } },
+      ]),
+    );
+    const sales = await ownerMailbox.getSharedMailboxAddress('sales');
+    const adminMailbox = new Mailbox(conn, admin, { mailboxOwner: sales });
+
+    await conn.confirmTransaction(await adminMailbox.updateSetting({ messageRequests: { enabled: true } }));
+    await conn.confirmTransaction(await adminMailbox.acceptSender(sender.publicKey));
+    await conn.confirmTransaction(
+      await adminMailbox.setDelegate(responder.publicKey, {
+        updateReadMessages: true,
+        deleteMessages: false,
+        claimIncentives: false,
+      }),
+    );
+    assert.equal((await adminMailbox.fetchMailboxSettings()).settings.length, 1);
+    assert.ok((await adminMailbox.fetchContacts())[0].equals(sender.publicKey));
+    assert.equal((await adminMailbox.fetchDelegates()).length, 1);
+
+    try {
+      await new Mailbox(conn, responder, { mailboxOwner: sales }).acceptSender(responder.publicKey);
+      assert.fail('A responder changed the contacts');
+    } catch (e) {
+      assert.ok(String(e).includes('NotMailboxAuthority'));
+    }
+
+    // Senders outside the contacts land in the request queue, which admins can clear
+    await conn.confirmTransaction(await new Mailbox(conn, stranger).send('unsolicited', sales));
+    assert.equal((await adminMailbox.fetchMessageRequests()).length, 1);
+    await conn.confirmTransaction(await adminMailbox.rejectMessageRequest(0));
+    assert.equal((await adminMailbox.fetchMessageRequests()).length, 0);
+
+    // Postage to a shared mailbox goes to its owner, as nobody can spend from the mailbox address
+    const postage = 100_000;
+    await conn.confirmTransaction(
+      await adminMailbox.updateSetting({ postage: { postage: { lamports: { amount: new anchor.BN(postage) } } } }),
+    );
+    const ownerBalance = await conn.getBalance(owner.publicKey);
+    const salesBalance = await conn.getBalance(sales);
+    await conn.confirmTransaction(await new Mailbox(conn, sender).send('accepted sender', sales));
+    assert.equal(await conn.getBalance(owner.publicKey), ownerBalance + postage);
+    assert.equal(await conn.getBalance(sales), salesBalance);
+
+    // Nobody can sign a reply for the shared mailbox, so such an incentive could never be claimed
+    try {
+      await new Mailbox(conn, sender).send('worth a reply?', sales, {
+        solIncentive: { lamports: 1_000_000, requiresReply: true },
+      });
+      assert.fail('Sent a shared mailbox an incentive that requires a reply');
+    } catch (e) {
+      assert.ok(String(e).includes('SharedMailboxCannotReply'));
+    }
+    await conn.confirmTransaction(await adminMailbox.delete(0));
+    await conn.confirmTransaction(await adminMailbox.closeMailbox());
+    assert.equal(await conn.getBalance(await adminMailbox.getMailboxAddress()), 0);
+  });
+
+  it('Sends an NFT as a gift and reclaims it after expiry', async () => {
+    // The cloned collection NFT belongs to the token gating test user, so it is handed back
+    // through a refund rather than claimed
+    config();
+    if (!process.env.USER_KEY) {
+      assert.fail('Secret keys not found. Write them to a file called .env in the project root');
+    }
+    const user = new anchor.Wallet(anchor.web3.Keypair.fromSecretKey(decode(process.env.USER_KEY)));
+    const collectionId = new anchor.web3.PublicKey('GcMPukzjZWfY4y4KVM3HNdqtZTf5WyTWPvL4YXznoS9c');
+    const nft = (await getMetadataForOwner(conn, user.publicKey)).find((m) => m.collection?.key.equals(collectionId));
+    const userAta = await splToken.getAssociatedTokenAddress(nft.mint, user.publicKey);
+    const receiver = anchor.web3.Keypair.generate().publicKey;
+
+    await new Mailbox(conn, user).send('a gift', receiver, {
+      nftIncentive: { mint: nft.mint, payerAccount: userAta, expiresAt: new Date(Date.now() + 2000) },
+    });
+    const receiverMailbox = new Mailbox(conn, new anchor.Wallet(anchor.web3.Keypair.generate()), {
+      mailboxOwner: receiver,
+    });
+    const message = await receiverMailbox.fetchMessageById(0);
+    assert.ok(message.incentiveMint.equals(nft.mint));
+    assert.ok(message.incentiveNft.collection.equals(collectionId));
+    assert.equal(Number((await splToken.getAccount(conn, userAta)).amount), 0);
+
+    let refundEvent: any;
+    const listener = program.addEventListener('IncentiveRefunded', (event: any) => {
+      refundEvent = event;
+    });
+    await new Promise((resolve) => setTimeout(resolve, 5000));
+    await conn.confirmTransaction(await new Mailbox(conn, user).refundIncentive(message));
+    await new Promise((resolve) => setTimeout(resolve, 1000));
+    await program.removeEventListener(listener);
+
+    assert.equal(Number((await splToken.getAccount(conn, userAta)).amount), 1);
+    assert.ok(refundEvent.incentiveType.nft !== undefined);
+    assert.ok(refundEvent.mint.equals(nft.mint));
+    assert.ok(refundEvent.collection.equals(collectionId));
+  });
+
+  it('Only pays a reply-conditional incentive together with a reply', async () => {
+    const receiver = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    for (const wallet of [receiver, sender]) {
+      await conn.confirmTransaction(await conn.requestAirdrop(wallet.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    }
+    const receiverMailbox = new Mailbox(conn, receiver);
+    const senderMailbox = new Mailbox(conn, sender);
+
+    const incentiveLamports = 1_000_000;
+    await senderMailbox.send('worth a reply?', receiver.publicKey, {
+      solIncentive: { lamports: incentiveLamports, requiresReply: true },
+    });
+    const message = await receiverMailbox.fetchMessageById(0);
+    assert.ok(message.incentiveRequiresReply);
+
+    try {
+      await receiverMailbox.sendTransaction(await receiverMailbox.makeClaimSolIncentiveTx(0));
+      assert.fail('Claimed without replying');
+    } catch (e) {
+      assert.ok(String(e).includes('ReplyRequiredToClaim'));
+    }
+
+    let claimEvent: any;
+    const listener = program.addEventListener('IncentiveClaimed', (event: any) => {
+      claimEvent = event;
+    });
+    await conn.confirmTransaction(await receiverMailbox.replyAndClaim(message, 'Re: worth a reply?', 'yes'));
+    await new Promise((resolve) => setTimeout(resolve, 1000));
+    await program.removeEventListener(listener);
+
+    const reply = await senderMailbox.fetchMessageById(0);
+    assert.equal(reply.data.body, 'yes');
+    assert.ok(claimEvent.replyMessage.equals(await senderMailbox.getMessageAddress(0)));
+    assert.equal(claimEvent.amount.toNumber(), incentiveLamports);
+    assert.equal((await receiverMailbox.fetchMessageById(0)).incentiveLamports, undefined);
+  });
+
+  it('Lets anyone clean up an expired message', async () => {
+    const receiver = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const cranker = new anchor.Wallet(anchor.web3.Keypair.generate());
+    for (const wallet of [sender, cranker]) {
+      await conn.confirmTransaction(await conn.requestAirdrop(wallet.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    }
+    const senderMailbox = new Mailbox(conn, sender);
+    const crankerMailbox = new Mailbox(conn, cranker);
+
+    await senderMailbox.send('short lived', receiver.publicKey, { ttlSeconds: 2 });
+    await senderMailbox.send('lasts forever', receiver.publicKey);
+    const message = await new Mailbox(conn, receiver).fetchMessageById(0);
+    assert.ok(message.expiresAt > new Date());
+
+    try {
+      await crankerMailbox.cleanupExpiredMessage(0, receiver.publicKey);
+      assert.fail('Cleaned up a message before it expired');
+    } catch (e) {
+      assert.ok(String(e).includes('MessageNotExpired'));
+    }
+    try {
+      await crankerMailbox.cleanupExpiredMessage(1, receiver.publicKey);
+      assert.fail('Cleaned up a message without an expiry');
+    } catch (e) {
+      assert.ok(String(e).includes('MessageHasNoExpiry'));
+    }
+
+    await new Promise((resolve) => setTimeout(resolve, 4000));
+    const senderBalance = await conn.getBalance(sender.publicKey);
+    await conn.confirmTransaction(await crankerMailbox.cleanupExpiredMessage(0, receiver.publicKey));
+    assert.ok((await conn.getBalance(sender.publicKey)) > senderBalance);
+    assert.equal(await conn.getAccountInfo(await senderMailbox.getMessageAddress(0, receiver.publicKey)), null);
+    assert.equal((await new Mailbox(conn, receiver).countEx()).readMessageCount, 1);
+  });
+
+  it('Keeps the latest messages in a ring mailbox', async () => {
+    const receiver = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    for (const wallet of [receiver, sender]) {
+      await conn.confirmTransaction(await conn.requestAirdrop(wallet.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    }
+    const receiverMailbox = new Mailbox(conn, receiver);
+    const senderMailbox = new Mailbox(conn, sender);
+    await conn.confirmTransaction(await receiverMailbox.createRingMailbox());
+
+    const ringMailbox = await receiverMailbox.fetchRingMailbox();
+    const capacity = ringMailbox.entries.length;
+    for (let i = 0; i < capacity + 2; i++) {
+      await conn.confirmTransaction(await senderMailbox.sendToRing(`ring message ${i}`, receiver.publicKey));
+    }
+    try {
+      await senderMailbox.sendToRing('x'.repeat(257), receiver.publicKey);
+      assert.fail('Sent a message too long for the ring');
+    } catch (e) {
+      assert.ok(String(e).includes('RingMessageTooLong'));
+    }
+
+    // The two oldest messages were overwritten and are no longer unread
+    let messages = await receiverMailbox.fetchRingMessages();
+    assert.equal(messages.length, capacity);
+    assert.equal(messages[0].messageId, 2);
+    assert.equal(messages[0].data.body, 'ring message 2');
+    assert.ok(messages[0].sender.equals(sender.publicKey));
+    assert.equal(messages[capacity - 1].data.body, `ring message ${capacity + 1}`);
+
+    await conn.confirmTransaction(await receiverMailbox.updateRingReadMessages(capacity));
+    messages = await receiverMailbox.fetchRingMessages();
+    assert.equal(messages.length, 2);
+    assert.equal(messages[0].messageId, capacity);
+    // Ring sends never create message accounts
+    assert.equal(await conn.getAccountInfo(await senderMailbox.getMessageAddress(0, receiver.publicKey)), null);
+  });
+
+  it('Sends an ephemeral message as an event only', async () => {
+    const receiver = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    await conn.confirmTransaction(await conn.requestAirdrop(sender.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    const receiverMailbox = new Mailbox(conn, receiver);
+    const senderMailbox = new Mailbox(conn, sender);
+
+    let received: any;
+    const listener = receiverMailbox.addMessageListener((message) => {
+      received = message;
+    });
+    await conn.confirmTransaction(await senderMailbox.sendEphemeral('ping', receiver.publicKey));
+    await new Promise((resolve) => setTimeout(resolve, 1000));
+    receiverMailbox.removeMessageListener(listener);
+
+    assert.equal(received.messageId, 0);
+    assert.equal(received.data.body, 'ping');
+    assert.ok(received.contentHash.equals(createHash('sha256').update('ping').digest()));
+    assert.equal(await conn.getAccountInfo(await senderMailbox.getMessageAddress(0, receiver.publicKey)), null);
+    assert.deepEqual(await receiverMailbox.countEx(), { messageCount: 1, readMessageCount: 0 });
+    assert.equal((await receiverMailbox.fetchMessages()).length, 0);
+
+    await senderMailbox.send('stored', receiver.publicKey);
+    const messages = await receiverMailbox.fetchMessages();
+    assert.equal(messages.length, 1);
+    assert.equal(messages[0].messageId, 1);
+    try {
+      await receiverMailbox.fetchMessageById(0);
+      assert.fail('Fetched an ephemeral message by id');
+    } catch (e) {
+      assert.ok(String(e).includes('ephemeral'));
+    }
+
+    // Popping skips the ephemeral index under the read pointer
+    await conn.confirmTransaction(await conn.requestAirdrop(receiver.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    await conn.confirmTransaction(await receiverMailbox.pop());
+    assert.equal(await receiverMailbox.count(), 0);
+    assert.deepEqual(await receiverMailbox.countEx(), { messageCount: 2, readMessageCount: 2 });
+  });
+
+  it('Attaches chunked content to a message and closes it on delete', async () => {
+    const receiver = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    for (const wallet of [receiver, sender]) {
+      await conn.confirmTransaction(await conn.requestAirdrop(wallet.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    }
+    const receiverMailbox = new Mailbox(conn, receiver);
+    const senderMailbox = new Mailbox(conn, sender);
+
+    await senderMailbox.send('see attached', receiver.publicKey);
+    const content = Buffer.from('long form content '.repeat(150));
+    const signatures = await senderMailbox.attach(0, receiver.publicKey, content);
+    assert.equal(signatures.length, 2 + Math.ceil(content.length / 800));
+    try {
+      await receiverMailbox.attach(0, receiver.publicKey, Buffer.from('not mine'));
+      assert.fail('Attached to a message sent by someone else');
+    } catch (e) {
+      assert.ok(String(e).includes('NotMessageSender'));
+    }
+
+    const message = await receiverMailbox.fetchMessageById(0);
+    assert.equal(message.attachmentCount, 1);
+    const [attachment] = await receiverMailbox.fetchAttachments(message);
+    assert.ok(attachment.data.equals(content));
+    assert.ok(attachment.contentHash.equals(createHash('sha256').update(content).digest()));
+
+    const mailbox = await receiverMailbox.getMailboxAddress();
+    const messageAddress = await receiverMailbox.getMessageAddress(0);
+    try {
+      await program.methods
+        .deleteMessage(0)
+        .accounts({
+          mailbox,
+          receiver: receiver.publicKey,
+          authorizedDeleter: receiver.publicKey,
+          delegates: await getDelegatesAddress(mailbox),
+          message: messageAddress,
+          outbox: await getOutboxAddress(sender.publicKey),
+          rentDestination: sender.publicKey,
+        })
+        .signers([receiver.payer])
+        .rpc();
+      assert.fail('Deleted a message without closing its attachments');
+    } catch (e) {
+      assert.ok(String(e).includes('MissingAttachmentAccounts'));
+    }
+
+    const attachmentAddress = await receiverMailbox.getAttachmentAddress(messageAddress, 0);
+    const senderBalance = await conn.getBalance(sender.publicKey);
+    const attachmentRent = await conn.getBalance(attachmentAddress);
+    await receiverMailbox.deleteMessage(message);
+    assert.equal(await conn.getAccountInfo(attachmentAddress), null);
+    assert.ok((await conn.getBalance(sender.publicKey)) > senderBalance + attachmentRent);
+  });
+
+  it('Sends a typed message envelope', async () => {
+    const receiver = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    await conn.confirmTransaction(await conn.requestAirdrop(sender.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    const receiverMailbox = new Mailbox(conn, receiver);
+    const senderMailbox = new Mailbox(conn, sender);
+
+    let received: any;
+    const listener = receiverMailbox.addMessageListener((message) => {
+      received = message;
+    });
+    await senderMailbox.sendEnvelope(
+      { contentType: 'text/markdown', subj: 'Release notes', body: '# v2', meta: { lang: 'en' } },
+      receiver.publicKey,
+    );
+    await new Promise((resolve) => setTimeout(resolve, 1000));
+    receiverMailbox.removeMessageListener(listener);
+    assert.equal(received.contentType, 'text/markdown');
+
+    const message = await receiverMailbox.fetchMessageById(0);
+    assert.equal(message.contentType, 'text/markdown');
+    assert.equal(message.data.subj, 'Release notes');
+    assert.equal(message.data.body, '# v2');
+    assert.deepEqual(message.data.meta, { lang: 'en' });
+
+    try {
+      await senderMailbox.sendEnvelope({ contentType: '', body: 'untyped' }, receiver.publicKey);
+      assert.fail('Sent an envelope without a content type');
+    } catch (e) {
+      assert.ok(String(e).includes('InvalidEnvelopeContentType'));
+    }
+    try {
+      const tx = await program.methods
+        .sendEnvelopeMessage({ schemaVersion: 2, contentType: 'text/plain', subject: null, body: 'from the future', metadata: [] })
+        .accounts({
+          mailboxSettings: await senderMailbox.getMailboxSettingsAddress(receiver.publicKey),
+          contacts: await senderMailbox.getContactsAddress(receiver.publicKey),
+          rateLimit: await senderMailbox.getRateLimitAddress(await senderMailbox.getMailboxAddress(receiver.publicKey)),
+          outbox: await senderMailbox.getOutboxAddress(),
+          config: await getConfigAddress(),
+          receiver: receiver.publicKey,
+          message: await senderMailbox.getMessageAddress(1, receiver.publicKey),
+          payer: sender.publicKey,
+          sender: sender.publicKey,
+          feeReceiver: (await senderMailbox.fetchMessagingConfig()).treasury,
+        })
+        .transaction();
+      await senderMailbox.sendTransaction(tx);
+      assert.fail('Sent an envelope with an unknown schema version');
+    } catch (e) {
+      assert.ok(String(e).includes('UnsupportedEnvelopeSchema'));
+    }
+  });
+
+  it('Keeps an outbox of sent messages in step with deletes', async () => {
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const receiverA = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const receiverB = new anchor.Wallet(anchor.web3.Keypair.generate());
+    await conn.confirmTransaction(await conn.requestAirdrop(sender.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    const senderMailbox = new Mailbox(conn, sender);
+    assert.equal(await senderMailbox.fetchSentMessages(), null);
+    await senderMailbox.createOutbox();
+
+    await senderMailbox.send('first to a', receiverA.publicKey);
+    await senderMailbox.send('to b', receiverB.publicKey);
+    await senderMailbox.send('second to a', receiverA.publicKey);
+    const secondToA = await program.account.message.fetch(await senderMailbox.getMessageAddress(1, receiverA.publicKey));
+    assert.equal(secondToA.outboxSlot, 2);
+    let sent = await senderMailbox.fetchSentMessages();
+    assert.deepEqual(
+      sent.map((m) => [m.receiver.toBase58(), m.messageId]),
+      [
+        [receiverA.publicKey.toBase58(), 0],
+        [receiverB.publicKey.toBase58(), 0],
+        [receiverA.publicKey.toBase58(), 1],
+      ],
+    );
+
+    await senderMailbox.sendEphemeral('ephemeral to a', receiverA.publicKey);
+    sent = await senderMailbox.fetchSentMessages();
+    assert.deepEqual(
+      sent.map((m) => m.kind),
+      ['mailbox', 'mailbox', 'mailbox', 'ephemeral'],
+    );
+
+    await senderMailbox.delete(0, receiverA.publicKey);
+    sent = await senderMailbox.fetchSentMessages();
+    assert.equal(sent.length, 3);
+    const toA = await senderMailbox.fetchSentMessagesTo(receiverA.publicKey);
+    assert.equal(toA.length, 1);
+    assert.equal(toA[0].data.body, 'second to a');
+
+    await senderMailbox.closeOutbox();
+    assert.equal(await senderMailbox.fetchSentMessages(), null);
+    await senderMailbox.send('untracked', receiverB.publicKey);
+  });
+
+  it('Rate limits each sender per time window', async () => {
+    const receiver = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const sender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    const otherSender = new anchor.Wallet(anchor.web3.Keypair.generate());
+    for (const wallet of [receiver, sender, otherSender]) {
+      await conn.confirmTransaction(await conn.requestAirdrop(wallet.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    }
+    const receiverMailbox = new Mailbox(conn, receiver);
+    const senderMailbox = new Mailbox(conn, sender);
+
+    try {
+      await receiverMailbox.updateSetting({ rateLimit: { rule: { maxMessages: 0, windowSeconds: 60 } } });
+      assert.fail('Set a rate limit that allows no messages');
+    } catch (e) {
+      assert.ok(String(e).includes('InvalidRateLimit'));
+    }
+    await receiverMailbox.updateSetting({ rateLimit: { rule: { maxMessages: 2, windowSeconds: 3 } } });
+
+    await senderMailbox.send('one', receiver.publicKey);
+    await senderMailbox.send('two', receiver.publicKey);
+    try {
+      await senderMailbox.send('three', receiver.publicKey);
+      assert.fail('Sent past the rate limit');
+    } catch (e) {
+      assert.ok(String(e).includes('RateLimitExceeded'));
+    }
+    await new Mailbox(conn, otherSender).send('someone else', receiver.publicKey);
+
+    await new Promise((resolve) => setTimeout(resolve, 4000));
+    await senderMailbox.send('next window', receiver.publicKey);
+    const messages = await receiverMailbox.fetchMessages();
+    assert.deepEqual(
+      messages.map((m) => m.data.body),
+      ['one', 'two', 'someone else', 'next window'],
+    );
+
+    // Anyone can close the rate limit account once its window is over, refunding the payer
+    const otherMailbox = new Mailbox(conn, otherSender);
+    try {
+      await otherMailbox.closeRateLimit(receiver.publicKey, sender.publicKey);
+      assert.fail('Closed a rate limit during its window');
+    } catch (e) {
+      assert.ok(String(e).includes('RateLimitWindowOpen'));
+    }
+    await new Promise((resolve) => setTimeout(resolve, 4000));
+    const rateLimitAddress = await senderMailbox.getRateLimitAddress(await receiverMailbox.getMailboxAddress());
+    const rent = await conn.getBalance(rateLimitAddress);
+    const senderBalance = await conn.getBalance(sender.publicKey);
+    await conn.confirmTransaction(await otherMailbox.closeRateLimit(receiver.publicKey, sender.publicKey));
+    assert.equal(await conn.getBalance(rateLimitAddress), 0);
+    assert.equal(await conn.getBalance(sender.publicKey), senderBalance + rent);
+  });
+
+  it('Migrates a message stored in the legacy layout', async () => {
+    // Loaded from tests/fixtures/legacy-message.json, see Anchor.toml
+    const legacyReceiver = anchor.web3.Keypair.fromSeed(new Uint8Array(32).fill(7));
+    const cranker = new anchor.Wallet(anchor.web3.Keypair.generate());
+    await conn.confirmTransaction(await conn.requestAirdrop(cranker.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    const mailbox = new Mailbox(conn, cranker, { mailboxOwner: legacyReceiver.publicKey });
+    const messageAddress = await mailbox.getMessageAddress(0);
+    assert.ok(await conn.getAccountInfo(messageAddress));
+    try {
+      await program.account.message.fetch(messageAddress);
+      assert.fail('Loaded a legacy message as the current layout');
+    } catch (e) {
+      assert.ok(!String(e).includes('Loaded a legacy message'));
+    }
+
+    await mailbox.migrateMessage(0);
+    const message = await mailbox.fetchMessageById(0);
+    assert.equal(message.data.body, 'Sent before the message layout changed');
+    assert.ok(message.sender.equals(legacyReceiver.publicKey));
+    assert.equal(message.incentiveMint, undefined);
+    assert.equal(message.attachmentCount, undefined);
+
+    try {
+      await mailbox.migrateMessage(0);
+      assert.fail('Migrated a message twice');
+    } catch (e) {
+      assert.ok(String(e).includes('NotLegacyAccount'));
+    }
+  });
+
+  it('Migrates a legacy mailbox and closes it', async () => {
+    // Loaded from tests/fixtures/legacy-mailbox.json, holding the message migrated above
+    const legacyReceiver = new anchor.Wallet(anchor.web3.Keypair.fromSeed(new Uint8Array(32).fill(7)));
+    await conn.confirmTransaction(await conn.requestAirdrop(legacyReceiver.publicKey, 1 * anchor.web3.LAMPORTS_PER_SOL));
+    const receiverMailbox = new Mailbox(conn, legacyReceiver);
+    const mailboxAddress = await receiverMailbox.getMailboxAddress();
+    try {
+      await program.account.mailbox.fetch(mailboxAddress);
+      assert.fail('Loaded a legacy mailbox as the current layout');
+    } catch (e) {
+      assert.ok(!String(e).includes('Loaded a legacy mailbox'));
+    }
+
+    await receiverMailbox.migrateMailbox();
+    assert.deepEqual(await receiverMailbox.countEx(), { messageCount: 1, readMessageCount: 0 });
+    const mailbox = await program.account.mailbox.fetch(mailboxAddress);
+    assert.ok(mailbox.payer.equals(legacyReceiver.publicKey));
+
+    const [message] = await receiverMailbox.fetchMessages();
+    assert.equal(message.data.body, 'Sent before the message layout changed');
+    await receiverMailbox.deleteMessage(message);
+    await receiverMailbox.closeMailbox();
+    assert.equal(await conn.getBalance(mailboxAddress), 0);
+  });
 });
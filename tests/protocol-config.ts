@@ -0,0 +1,49 @@
+import * as anchor from '@project-serum/anchor';
+import { Program } from '@project-serum/anchor';
+import { Messaging } from '../target/types/messaging';
+import { Postbox } from '../target/types/postbox';
+
+import { clusterAddresses, seeds } from '../usedispatch_client/src';
+
+const BPF_LOADER_UPGRADEABLE = new anchor.web3.PublicKey('BPFLoaderUpgradeab1e11111111111111111111111');
+
+// The fees the test suites expect, matching the values both programs used to compile in
+export const MESSAGING_FEES = { messageFee: new anchor.BN(50_000), pass: null, passDiscountBps: 0 };
+export const POSTBOX_FEES = {
+  newPostbox: new anchor.BN(100_000),
+  newPersonalBox: new anchor.BN(50_000),
+  post: new anchor.BN(50_000),
+  vote: new anchor.BN(50_000),
+  pass: null,
+  passDiscountBps: 0,
+};
+
+const initializeConfig = async (program: Program<Messaging> | Program<Postbox>, fees: object) => {
+  const [config] = await anchor.web3.PublicKey.findProgramAddress(
+    [seeds.protocolSeed, seeds.configSeed],
+    program.programId,
+  );
+  if (await program.provider.connection.getAccountInfo(config)) {
+    return;
+  }
+  const [programData] = await anchor.web3.PublicKey.findProgramAddress(
+    [program.programId.toBuffer()],
+    BPF_LOADER_UPGRADEABLE,
+  );
+  const admin = anchor.getProvider().wallet.publicKey;
+  await (program.methods as any)
+    .initializeConfig(admin, clusterAddresses.get('devnet').treasuryAddress, fees)
+    .accounts({
+      authority: admin,
+      program: program.programId,
+      programData,
+    })
+    .rpc();
+};
+
+// Root hook: every suite needs the protocol configs before it can pay any fee
+before(async () => {
+  anchor.setProvider(anchor.AnchorProvider.env());
+  await initializeConfig(anchor.workspace.Messaging as Program<Messaging>, MESSAGING_FEES);
+  await initializeConfig(anchor.workspace.Postbox as Program<Postbox>, POSTBOX_FEES);
+});
@@ -1,5 +1,6 @@
 import * as web3 from '@solana/web3.js';
 import * as anchor from '@project-serum/anchor';
+import * as splToken from '@solana/spl-token';
 import { Messaging } from '../../target/types/messaging';
 import messagingProgramIdl from '../../target/idl/messaging.json';
 import { Postbox } from '../../target/types/postbox';
@@ -10,8 +11,10 @@ import {
   DispatchAddresses,
   TXN_COMMITMENT,
   SOLANA_CONNECTION_MAX_RETRIES,
+  seeds,
 } from './constants';
 import { WalletInterface, AnchorExpectedWalletInterface, AnchorNodeWalletInterface } from './wallets';
+import { getMetadataForOwner, deriveMetadataAccount } from './utils';
 
 export type DispatchConnectionOpts = {
   skipAnchorProvider?: boolean;
@@ -43,6 +46,58 @@ export class DispatchConnection {
     this.postboxProgram = new anchor.Program<Postbox>(postboxProgramIdl as any, this.addresses.postboxAddress);
   }
 
+  public async getMessagingConfigAddress() {
+    const [configAddress] = await web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.configSeed],
+      this.messagingProgram.programId,
+    );
+    return configAddress;
+  }
+
+  /// Fees and treasury for the messaging program
+  public async fetchMessagingConfig() {
+    return this.messagingProgram.account.protocolConfig.fetch(await this.getMessagingConfigAddress());
+  }
+
+  public async getPostboxConfigAddress() {
+    const [configAddress] = await web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.configSeed],
+      this.postboxProgram.programId,
+    );
+    return configAddress;
+  }
+
+  /// Fees and treasury for the postbox program
+  public async fetchPostboxConfig() {
+    return this.postboxProgram.account.protocolConfig.fetch(await this.getPostboxConfigAddress());
+  }
+
+  /// Accounts proving `holder` owns the protocol pass of either program's config. They must
+  /// be appended after every other remaining account. Empty if the holder has no pass.
+  public async getFeePassAccounts(pass: any | null, holder: web3.PublicKey): Promise<web3.AccountMeta[]> {
+    if (pass?.token) {
+      const ata = await splToken.getAssociatedTokenAddress(pass.token.mint, holder, true);
+      const info = await this.conn.getAccountInfo(ata);
+      const balance = info?.data ? splToken.AccountLayout.decode(info.data).amount : BigInt(0);
+      if (balance >= BigInt(pass.token.amount.toString())) {
+        return [{ pubkey: ata, isWritable: false, isSigner: false }];
+      }
+    }
+    if (pass?.nftCollection) {
+      const collectionId: web3.PublicKey = pass.nftCollection.collectionId;
+      const nftsOwned = await getMetadataForOwner(this.conn, holder);
+      const nft = nftsOwned.find((n) => n.collection?.verified && n.collection.key.equals(collectionId));
+      if (nft) {
+        return [
+          { pubkey: await splToken.getAssociatedTokenAddress(nft.mint, holder, true), isWritable: false, isSigner: false },
+          { pubkey: await deriveMetadataAccount(nft.mint), isWritable: false, isSigner: false },
+          { pubkey: collectionId, isWritable: false, isSigner: false },
+        ];
+      }
+    }
+    return [];
+  }
+
   public async sendTransaction(tx: web3.Transaction, commitment: web3.Commitment = TXN_COMMITMENT) {
     let sig: string;
     if ('sendTransaction' in this.wallet && this.wallet.sendTransaction) {
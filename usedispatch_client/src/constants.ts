@@ -10,14 +10,43 @@ export const seeds = {
   protocolSeed: Buffer.from('dispatch'),
   mailboxSeed: Buffer.from('mailbox'),
   messageSeed: Buffer.from('message'),
+  encryptionKeySeed: Buffer.from('encryption_key'),
+  mailboxSettingsSeed: Buffer.from('mailbox_settings'),
+  contactsSeed: Buffer.from('contacts'),
+  delegatesSeed: Buffer.from('delegates'),
+  sharedMailboxSeed: Buffer.from('shared_mailbox'),
+  requestBoxSeed: Buffer.from('requests'),
+  ringMailboxSeed: Buffer.from('ring_mailbox'),
+  attachmentSeed: Buffer.from('attachment'),
+  outboxSeed: Buffer.from('outbox'),
+  rateLimitSeed: Buffer.from('rate_limit'),
+  senderNonceSeed: Buffer.from('sender_nonce'),
   postboxSeed: Buffer.from('postbox'),
   postSeed: Buffer.from('post'),
   moderatorSeed: Buffer.from('moderator'),
   voteTrackerSeed: Buffer.from('votes'),
+  configSeed: Buffer.from('config'),
 };
 
 export const eventName = 'DispatchMessage';
 
+/**
+ * Bytes of an attachment written per transaction, leaving room
+ * for the accounts and signatures within the transaction size limit
+ */
+export const ATTACHMENT_CHUNK_SIZE = 800;
+
+/**
+ * Message addresses checked per `close_mailbox` transaction, leaving room
+ * for the other accounts and signatures within the transaction size limit
+ */
+export const CLOSE_MAILBOX_BATCH_SIZE = 25;
+
+/**
+ * The message envelope layout the program accepts
+ */
+export const ENVELOPE_SCHEMA_VERSION = 1;
+
 export const clusterAddresses = new Map<web3.Cluster, DispatchAddresses>();
 
 clusterAddresses.set('devnet', {
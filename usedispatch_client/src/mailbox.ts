@@ -2,14 +2,16 @@ import * as splToken from '@solana/spl-token';
 import * as web3 from '@solana/web3.js';
 import * as anchor from '@project-serum/anchor';
 import * as CryptoJS from 'crypto-js';
-import { seeds, eventName } from './constants';
+import { seeds, eventName, ATTACHMENT_CHUNK_SIZE, CLOSE_MAILBOX_BATCH_SIZE, ENVELOPE_SCHEMA_VERSION } from './constants';
 import { WalletInterface } from './wallets';
 import { convertSolanartToDispatchMessage } from './solanart';
 import { DispatchConnection, DispatchConnectionOpts } from './connection';
+import { deriveMetadataAccount } from './utils';
 
 export type MailboxAccount = {
   messageCount: number;
   readMessageCount: number;
+  payer: web3.PublicKey;
 };
 
 export type ParsedMessageData = {
@@ -28,12 +30,48 @@ export type MessageData = {
   meta?: object;
 };
 
+/// A message sent with `sendEnvelope`, validated by the program instead of packed into a string
+export type MessageEnvelope = {
+  /// MIME type of the body, like `text/plain` or `text/markdown`
+  contentType: string;
+  subj?: string;
+  body: string;
+  meta?: Record<string, string>;
+};
+
 export type MessageAccount = {
   sender: web3.PublicKey;
   receiver: web3.PublicKey;
   data: MessageData;
   messageId: number;
   incentiveMint?: web3.PublicKey;
+  incentiveLamports?: number;
+  incentiveExpiresAt?: Date;
+  /// The incentive can only be claimed together with a reply
+  incentiveRequiresReply?: boolean;
+  /// After this anyone can clean the message up with `cleanupExpiredMessage`
+  expiresAt?: Date;
+  /// Set when this message is a reply to the message at `messageId` in `mailbox`
+  replyTo?: MessageReference;
+  /// Set when `incentiveMint` is an NFT sent as a gift
+  incentiveNft?: { collection?: web3.PublicKey };
+  /// SHA-256 of the raw payload, only reported by listeners for ephemeral messages
+  contentHash?: Buffer;
+  /// Number of attachment accounts, read them with `fetchAttachments`
+  attachmentCount?: number;
+  /// Set for messages sent as an envelope, whose fields are also unpacked into `data`
+  contentType?: string;
+};
+
+export type AttachmentAccount = {
+  data: Buffer;
+  /// Unset until the sender has written every chunk and finalized the attachment
+  contentHash?: Buffer;
+};
+
+export type MessageReference = {
+  mailbox: web3.PublicKey;
+  messageId: number;
 };
 
 /** @deprecated Use MessageAccount instead */
@@ -44,9 +82,16 @@ export type DeprecatedMessageAccount = {
   messageId: number;
 };
 
+/// Where a sent message went, in the order the program numbers them
+export const sentMessageKinds = ['mailbox', 'request', 'ring', 'ephemeral'] as const;
+export type SentMessageKind = typeof sentMessageKinds[number];
+
 export type SentMessageAccount = {
   receiver: web3.PublicKey;
+  /// Index in the receiver's mailbox, request queue or ring mailbox, depending on `kind`
   messageId: number;
+  /// Only known when read from the outbox
+  kind?: SentMessageKind;
 };
 
 export type MailboxOpts = DispatchConnectionOpts & {
@@ -59,10 +104,70 @@ export type IncentiveArgs = {
   mint: web3.PublicKey;
   amount: number;
   payerAccount: web3.PublicKey;
+  /// After this the receiver can no longer claim and the sender can refund the incentive
+  expiresAt?: Date;
+  /// The receiver can only claim by replying, see `replyAndClaim`
+  requiresReply?: boolean;
+};
+
+export type NftIncentiveArgs = {
+  mint: web3.PublicKey;
+  payerAccount: web3.PublicKey;
+  /// After this the receiver can no longer claim and the sender can reclaim the NFT
+  expiresAt?: Date;
+  /// The receiver can only claim by replying, see `replyAndClaim`
+  requiresReply?: boolean;
+};
+
+export type SolIncentiveArgs = {
+  lamports: number;
+  /// After this the receiver can no longer claim and the sender can refund the incentive
+  expiresAt?: Date;
+  /// The receiver can only claim by replying, see `replyAndClaim`
+  requiresReply?: boolean;
+};
+
+export type PostageRule = { lamports: { amount: anchor.BN } } | { token: { mint: web3.PublicKey; amount: anchor.BN } };
+
+export type SenderRule = { address: { key: web3.PublicKey } } | { tokenOwnership: { mint: web3.PublicKey; amount: anchor.BN } };
+
+export type MailboxSetting =
+  | { postage: { postage: PostageRule } }
+  | { senderAllowList: { rules: SenderRule[] } }
+  | { senderBlockList: { rules: SenderRule[] } }
+  | { messageRequests: { enabled: boolean } }
+  /// At most `maxMessages` from each sender within every `windowSeconds`
+  | { rateLimit: { rule: { maxMessages: number; windowSeconds: number } } };
+
+/// Scopes a receiver can grant to a delegate managing their mailbox
+export type DelegatePermissions = {
+  updateReadMessages: boolean;
+  deleteMessages: boolean;
+  claimIncentives: boolean;
+};
+
+export type MemberRole = { admin: {} } | { reader: {} } | { responder: {} };
+
+export type SharedMailboxMember = {
+  key: web3.PublicKey;
+  role: MemberRole;
+};
+
+/// A message signed by its sender for a relayer to submit and pay for
+export type RelayedMessage = {
+  sender: web3.PublicKey;
+  receiver: web3.PublicKey;
+  data: string;
+  nonce: number;
+  signature: Uint8Array;
 };
 
 export type SendOpts = {
   incentive?: IncentiveArgs;
+  solIncentive?: SolIncentiveArgs;
+  nftIncentive?: NftIncentiveArgs;
+  /// After this many seconds anyone can clean the message up. Cannot be combined with an incentive
+  ttlSeconds?: number;
 };
 
 export class Mailbox extends DispatchConnection {
@@ -99,6 +204,153 @@ export class Mailbox extends DispatchConnection {
     return this.sendTransaction(tx);
   }
 
+  /// Reply to a message in this mailbox; the reply is delivered to the original sender
+  async reply(message: MessageAccount, subj: string, body: string, meta?: object): Promise<string> {
+    this.validateWallet();
+    const tx = await this.makeReplyTx(this.getMessageString(subj, body, meta), message.messageId, message.sender);
+    return this.sendTransaction(tx);
+  }
+
+  /// Send the same message to several receivers in one transaction
+  async broadcast(data: string, receiverAddresses: web3.PublicKey[]): Promise<string> {
+    this.validateWallet();
+    const tx = await this.makeBroadcastTx(data, receiverAddresses);
+    return this.sendTransaction(tx);
+  }
+
+  /// Sign a message for a relayer to send with `sendRelayed`; nothing is sent and nothing is paid
+  async signRelayedMessage(data: string, receiverAddress: web3.PublicKey): Promise<RelayedMessage> {
+    this.validateWallet();
+    if (!this.wallet.signMessage) {
+      throw new Error('The provided wallet is unable to sign messages');
+    }
+    const message = this.obfuscate ? this.obfuscateMessage(data, receiverAddress) : data;
+    const nonce = await this.fetchSenderNonce();
+    const signature = await this.wallet.signMessage(this.getRelayMessageBytes(receiverAddress, nonce, message));
+    return { sender: this.mailboxOwner, receiver: receiverAddress, data: message, nonce, signature };
+  }
+
+  /// Send a message signed by someone else, paying for it from this wallet
+  async sendRelayed(relayed: RelayedMessage): Promise<string> {
+    const tx = await this.makeRelayedSendTx(relayed);
+    return this.sendTransaction(tx);
+  }
+
+  async updateSetting(setting: MailboxSetting): Promise<string> {
+    this.validateAuthority();
+    const tx = await this.makeUpdateSettingTx(setting);
+    return this.sendTransaction(tx);
+  }
+
+  async acceptSender(sender: web3.PublicKey): Promise<string> {
+    this.validateAuthority();
+    const tx = await this.makeAcceptSenderTx(sender);
+    return this.sendTransaction(tx);
+  }
+
+  /// Let another key manage this mailbox. Delegates act through a Mailbox created with their
+  /// own wallet and this mailbox's owner as `mailboxOwner`.
+  async setDelegate(delegate: web3.PublicKey, permissions: DelegatePermissions): Promise<string> {
+    this.validateAuthority();
+    const tx = await this.makeSetDelegateTx(delegate, permissions);
+    return this.sendTransaction(tx);
+  }
+
+  async removeDelegate(delegate: web3.PublicKey): Promise<string> {
+    return this.setDelegate(delegate, { updateReadMessages: false, deleteMessages: false, claimIncentives: false });
+  }
+
+  /// Create a named inbox owned by this wallet. Members act on it through a Mailbox created
+  /// with their own wallet and the shared mailbox address as `mailboxOwner`, and admins
+  /// configure and close it the same way.
+  async createSharedMailbox(name: string, members: SharedMailboxMember[]): Promise<string> {
+    this.validateWallet();
+    const tx = await this.makeCreateSharedMailboxTx(name, members);
+    return this.sendTransaction(tx);
+  }
+
+  /// Add or change a member of a shared mailbox, or remove them by passing a null role
+  async setSharedMailboxMember(
+    sharedMailboxAddress: web3.PublicKey,
+    member: web3.PublicKey,
+    role: MemberRole | null,
+  ): Promise<string> {
+    const tx = await this.makeSetSharedMailboxMemberTx(sharedMailboxAddress, member, role);
+    return this.sendTransaction(tx);
+  }
+
+  async updateReadMessages(readMessageCount: number): Promise<string> {
+    this.validateAuthority();
+    const tx = await this.makeUpdateReadMessagesTx(readMessageCount);
+    return this.sendTransaction(tx);
+  }
+
+  /// Keep an index of the messages this wallet sends, read with `fetchSentMessages`
+  async createOutbox(): Promise<string> {
+    this.validateWallet();
+    const tx = await this.makeCreateOutboxTx();
+    return this.sendTransaction(tx);
+  }
+
+  async closeOutbox(): Promise<string> {
+    this.validateWallet();
+    const tx = await this.makeCloseOutboxTx();
+    return this.sendTransaction(tx);
+  }
+
+  /// Send a structured message. Envelopes are stored as is, so they are never obfuscated.
+  async sendEnvelope(envelope: MessageEnvelope, receiverAddress: web3.PublicKey): Promise<string> {
+    this.validateWallet();
+    const tx = await this.makeSendEnvelopeTx(envelope, receiverAddress);
+    return this.sendTransaction(tx);
+  }
+
+  /// Send a message that is only emitted as an event and never stored in an account. Only
+  /// message listeners and indexers see it; the receiver's message count still advances.
+  async sendEphemeral(data: string, receiverAddress: web3.PublicKey): Promise<string> {
+    this.validateWallet();
+    const tx = await this.makeSendEphemeralTx(data, receiverAddress);
+    return this.sendTransaction(tx);
+  }
+
+  /// Create a ring mailbox for this wallet: senders pay only the fee and the oldest
+  /// messages are overwritten once it holds its capacity
+  async createRingMailbox(): Promise<string> {
+    this.validateWallet();
+    const tx = await this.makeCreateRingMailboxTx();
+    return this.sendTransaction(tx);
+  }
+
+  async sendToRing(data: string, receiverAddress: web3.PublicKey): Promise<string> {
+    this.validateWallet();
+    const tx = await this.makeSendRingMessageTx(data, receiverAddress);
+    return this.sendTransaction(tx);
+  }
+
+  async updateRingReadMessages(readMessageCount: number): Promise<string> {
+    this.validateAuthority();
+    const tx = await this.makeUpdateRingReadMessagesTx(readMessageCount);
+    return this.sendTransaction(tx);
+  }
+
+  async rejectMessageRequest(messageId: number): Promise<string> {
+    this.validateAuthority();
+    const tx = await this.makeRejectMessageRequestTx(messageId);
+    return this.sendTransaction(tx);
+  }
+
+  /// Close this mailbox once every message ever sent to it has been deleted, returning its rent.
+  /// Messages are checked `CLOSE_MAILBOX_BATCH_SIZE` at a time, and the last transaction closes it.
+  async closeMailbox(): Promise<string> {
+    this.validateAuthority();
+    let signature: string;
+    do {
+      const tx = await this.makeCloseMailboxTx();
+      signature = await this.sendTransaction(tx);
+    } while (await this.fetchMailbox());
+    return signature;
+  }
+
   /** @deprecated use delete instead  */
   async pop(): Promise<string> {
     this.validateWallet();
@@ -106,23 +358,87 @@ export class Mailbox extends DispatchConnection {
     return this.sendTransaction(tx);
   }
 
+  /// Close someone's expired message, returning its rent to whoever paid for it
+  async cleanupExpiredMessage(messageId: number, receiverAddress?: web3.PublicKey): Promise<string> {
+    const tx = await this.makeCleanupExpiredMessageTx(messageId, receiverAddress);
+    return this.sendTransaction(tx);
+  }
+
+  /// Close a sender's rate limit account for the receiver's mailbox once its window has ended,
+  /// returning its rent to whoever paid for it
+  async closeRateLimit(receiverAddress: web3.PublicKey, sender?: web3.PublicKey): Promise<string> {
+    const tx = await this.makeCloseRateLimitTx(receiverAddress, sender);
+    return this.sendTransaction(tx);
+  }
+
   /** @deprecated use delete instead  */
   async delete(messageId: number, receiverAddress?: web3.PublicKey): Promise<string> {
-    this.validateWallet();
+    this.validateAuthority();
     const tx = await this.makeDeleteTx(messageId, receiverAddress);
     return this.sendTransaction(tx);
   }
 
   async deleteMessage(message: MessageAccount): Promise<string> {
-    this.validateWallet();
+    this.validateAuthority();
     const tx = await this.makeDeleteTx(message.messageId, message.receiver);
     return this.sendTransaction(tx);
   }
 
-  async claimIncentive(message: MessageAccount): Promise<string> {
+  /// Rewrite a mailbox created before mailboxes recorded their payer, which cannot receive
+  /// messages until it is migrated. Anyone can migrate a mailbox and pays for the larger account.
+  async migrateMailbox(mailboxOwner?: web3.PublicKey): Promise<string> {
+    const tx = await this.makeMigrateMailboxTx(mailboxOwner);
+    return this.sendTransaction(tx);
+  }
+
+  /// Rewrite a message sent before the message layout changed, which cannot be fetched or
+  /// deleted until it is migrated. Anyone can migrate a message and pays for the larger account.
+  async migrateMessage(messageId: number, receiverAddress?: web3.PublicKey): Promise<string> {
+    const tx = await this.makeMigrateMessageTx(messageId, receiverAddress);
+    return this.sendTransaction(tx);
+  }
+
+  /// Attach content too large for a message to one this wallet sent. Takes a transaction
+  /// per chunk plus one to create and one to finalize; returns every signature.
+  async attach(messageId: number, receiverAddress: web3.PublicKey, content: Buffer): Promise<string[]> {
     this.validateWallet();
+    const txs = await this.makeAttachTxs(messageId, receiverAddress, content);
+    const signatures: string[] = [];
+    for (const tx of txs) {
+      signatures.push(await this.sendTransaction(tx));
+    }
+    return signatures;
+  }
+
+  async claimIncentive(message: MessageAccount): Promise<string> {
+    this.validateAuthority();
     if (!message.receiver.equals(this.mailboxOwner)) throw new Error('Receiver does not match mailboxOwner');
-    const tx = await this.makeClaimIncentiveTx(message.messageId);
+    if (message.incentiveRequiresReply) throw new Error('This incentive can only be claimed with replyAndClaim');
+    const tx = message.incentiveLamports
+      ? await this.makeClaimSolIncentiveTx(message.messageId)
+      : await this.makeClaimIncentiveTx(message.messageId);
+    return this.sendTransaction(tx);
+  }
+
+  /// Reply to a message and claim its incentive in the same transaction, as incentives that
+  /// require a reply demand
+  async replyAndClaim(message: MessageAccount, subj: string, body: string, meta?: object): Promise<string> {
+    this.validateWallet();
+    const tx = await this.makeReplyTx(this.getMessageString(subj, body, meta), message.messageId, message.sender);
+    tx.add(
+      ...(message.incentiveLamports
+        ? await this.makeClaimSolIncentiveTx(message.messageId)
+        : await this.makeClaimIncentiveTx(message.messageId)
+      ).instructions,
+    );
+    return this.sendTransaction(tx);
+  }
+
+  async refundIncentive(message: MessageAccount): Promise<string> {
+    this.validateWallet();
+    const tx = message.incentiveLamports
+      ? await this.makeRefundSolIncentiveTx(message.messageId, message.receiver)
+      : await this.makeRefundIncentiveTx(message.messageId, message.receiver);
     return this.sendTransaction(tx);
   }
 
@@ -147,6 +463,9 @@ export class Mailbox extends DispatchConnection {
     return messages.map(normalize).filter((m): m is DeprecatedMessageAccount => m !== null);
   }
 
+  /// Unread messages, oldest first. Not every index has a message account: deleted messages
+  /// and those sent with `sendEphemeral`, which take an index but only exist as an event, are
+  /// skipped, so message ids can have gaps.
   async fetchMessages(): Promise<MessageAccount[]> {
     const mailbox = await this.fetchMailbox();
     if (!mailbox) {
@@ -167,14 +486,69 @@ export class Mailbox extends DispatchConnection {
     return messages.map(normalize).filter((m): m is MessageAccount => m !== null);
   }
 
+  /// Unread messages still held by the ring mailbox, oldest first
+  async fetchRingMessages(): Promise<MessageAccount[]> {
+    const ringMailbox = await this.fetchRingMailbox();
+    if (!ringMailbox) {
+      return [];
+    }
+    const capacity = ringMailbox.entries.length;
+    const messageIds = Array(ringMailbox.messageCount - ringMailbox.readMessageCount)
+      .fill(0)
+      .map((_element, index) => index + ringMailbox.readMessageCount);
+    return messageIds.map((messageId) => {
+      const entry = ringMailbox.entries[messageId % capacity];
+      const data = Buffer.from(entry.data.slice(0, entry.dataLen)).toString();
+      return {
+        sender: entry.sender,
+        receiver: this.mailboxOwner,
+        data: this.unpackMessageData(data, entry.sender, this.mailboxOwner),
+        messageId,
+      } as MessageAccount;
+    });
+  }
+
+  async fetchAttachments(message: MessageAccount): Promise<AttachmentAccount[]> {
+    const messageAddress = await this.getMessageAddress(message.messageId, message.receiver);
+    const addresses = await this.getAttachmentAddresses(messageAddress, message.attachmentCount ?? 0);
+    const attachments = await this.messagingProgram.account.attachment.fetchMultiple(addresses);
+    return attachments.map((attachment: any) => ({
+      data: Buffer.from(attachment.data),
+      contentHash: attachment.contentHash ? Buffer.from(attachment.contentHash) : undefined,
+    }));
+  }
+
   async fetchMessageById(messageId: number): Promise<MessageAccount> {
     const messageAddress = await this.getMessageAddress(messageId);
-    const messageAccount = await this.messagingProgram.account.message.fetch(messageAddress);
+    const messageAccount = await this.messagingProgram.account.message.fetchNullable(messageAddress);
+    if (!messageAccount) {
+      throw new Error(`Message ${messageId} was deleted or only sent as an ephemeral event`);
+    }
     return this.normalizeMessageAccount(messageAccount, messageId)!;
   }
 
+  /// Messages this wallet sent that have not been deleted or rejected, oldest first, read from
+  /// its outbox. Every kind of send is listed, but the outbox only holds the last 64 sends and
+  /// older ones are dropped, see `fetchSentMessagesTo`. Returns null when the wallet never
+  /// created an outbox with `createOutbox`.
+  async fetchSentMessages(): Promise<SentMessageAccount[] | null> {
+    const outbox = await this.messagingProgram.account.outbox.fetchNullable(await this.getOutboxAddress());
+    if (!outbox) {
+      return null;
+    }
+    return this.listOutbox(outbox).map((sent) => ({ receiver: sent.receiver, messageId: sent.messageIndex, kind: sent.kind }));
+  }
+
   async fetchSentMessagesTo(receiverAddress: web3.PublicKey): Promise<MessageAccount[]> {
     const receiverMailbox = new Mailbox(this.conn, this.wallet, { mailboxOwner: receiverAddress });
+    const outbox = await this.messagingProgram.account.outbox.fetchNullable(await this.getOutboxAddress());
+    // Once the outbox wrapped around, older sends are only found by scanning the mailbox
+    if (outbox && outbox.sentCount.toNumber() <= outbox.entries.length) {
+      const messageIds = this.listOutbox(outbox)
+        .filter((sent) => sent.kind === 'mailbox' && sent.receiver.equals(receiverAddress))
+        .map((sent) => sent.messageIndex);
+      return Promise.all(messageIds.map((messageId) => receiverMailbox.fetchMessageById(messageId)));
+    }
     const sentToReceiver = (await receiverMailbox.fetchMessages()).filter((m) => {
       return m.sender.equals(this.mailboxOwner);
     });
@@ -193,6 +567,8 @@ export class Mailbox extends DispatchConnection {
     return (await this.fetchMessages()).length;
   }
 
+  /// Raw counters of the mailbox. `messageCount` includes ephemeral messages, which never
+  /// had an account, so use `count` for the number of messages that can be fetched.
   async countEx() {
     const mailbox = await this.fetchMailbox();
     if (!mailbox) {
@@ -224,18 +600,79 @@ export class Mailbox extends DispatchConnection {
 
     const message = this.obfuscate ? this.obfuscateMessage(data, receiverAddress) : data;
 
+    const mailboxSettingsAddress = await this.getMailboxSettingsAddress(receiverAddress);
+    const remainingAccounts = await this.appendFeePassAccounts(
+      await this.getSettingsAccounts(mailboxSettingsAddress, receiverAddress),
+    );
+
+    if (await this.mustSendAsRequest(receiverAddress)) {
+      if (opts?.incentive || opts?.solIncentive || opts?.nftIncentive) {
+        throw new Error('Message requests cannot carry an incentive');
+      }
+      if (opts?.ttlSeconds) {
+        throw new Error('Message requests cannot expire');
+      }
+      return this.makeSendRequestTx(message, receiverAddress, remainingAccounts);
+    }
+
     const accounts = {
+      ...(await this.getSendAccounts(receiverAddress)),
       mailbox: toMailboxAddress,
       receiver: receiverAddress,
       message: messageAddress,
       payer: this.payer ?? this.mailboxOwner,
       sender: this.mailboxOwner,
-      feeReceiver: this.addresses.treasuryAddress,
+      feeReceiver: (await this.fetchMessagingConfig()).treasury,
       systemProgram: web3.SystemProgram.programId,
     };
 
     let tx: web3.Transaction;
-    if (opts?.incentive) {
+    if (opts?.ttlSeconds && (opts.incentive || opts.solIncentive || opts.nftIncentive)) {
+      throw new Error('Expiring messages cannot carry an incentive');
+    }
+    if (opts?.ttlSeconds) {
+      tx = this.messagingProgram.transaction.sendExpiringMessage(message, opts.ttlSeconds, {
+        accounts,
+        remainingAccounts,
+      });
+    } else if (opts?.solIncentive) {
+      const conditions = {
+        expiresAt: opts.solIncentive.expiresAt
+          ? new anchor.BN(Math.floor(opts.solIncentive.expiresAt.getTime() / 1000))
+          : null,
+        requiresReply: opts.solIncentive.requiresReply ?? false,
+      };
+      tx = this.messagingProgram.transaction.sendMessageWithSolIncentive(
+        message,
+        new anchor.BN(opts.solIncentive.lamports),
+        conditions,
+        {
+          accounts,
+          remainingAccounts,
+        },
+      );
+    } else if (opts?.nftIncentive) {
+      const ata = await splToken.getAssociatedTokenAddress(opts.nftIncentive.mint, messageAddress, true);
+      const conditions = {
+        expiresAt: opts.nftIncentive.expiresAt
+          ? new anchor.BN(Math.floor(opts.nftIncentive.expiresAt.getTime() / 1000))
+          : null,
+        requiresReply: opts.nftIncentive.requiresReply ?? false,
+      };
+      tx = this.messagingProgram.transaction.sendMessageWithNft(message, conditions, {
+        accounts: {
+          ...accounts,
+          incentiveMint: opts.nftIncentive.mint,
+          nftMetadata: await deriveMetadataAccount(opts.nftIncentive.mint),
+          payerTokenAccount: opts.nftIncentive.payerAccount,
+          incentiveTokenAccount: ata,
+          tokenProgram: splToken.TOKEN_PROGRAM_ID,
+          associatedTokenProgram: splToken.ASSOCIATED_TOKEN_PROGRAM_ID,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+        },
+        remainingAccounts,
+      });
+    } else if (opts?.incentive) {
       const ata = await splToken.getAssociatedTokenAddress(opts.incentive.mint, messageAddress, true);
       const incentiveAccounts = {
         ...accounts,
@@ -246,16 +683,393 @@ export class Mailbox extends DispatchConnection {
         associatedTokenProgram: splToken.ASSOCIATED_TOKEN_PROGRAM_ID,
         rent: web3.SYSVAR_RENT_PUBKEY,
       };
-      tx = this.messagingProgram.transaction.sendMessageWithIncentive(message, new anchor.BN(opts.incentive.amount), {
-        accounts: incentiveAccounts,
-      });
+      const conditions = {
+        expiresAt: opts.incentive.expiresAt ? new anchor.BN(Math.floor(opts.incentive.expiresAt.getTime() / 1000)) : null,
+        requiresReply: opts.incentive.requiresReply ?? false,
+      };
+      tx = this.messagingProgram.transaction.sendMessageWithIncentive(
+        message,
+        new anchor.BN(opts.incentive.amount),
+        conditions,
+        {
+          accounts: incentiveAccounts,
+          remainingAccounts,
+        },
+      );
     } else {
-      tx = this.messagingProgram.transaction.sendMessage(message, { accounts });
+      tx = this.messagingProgram.transaction.sendMessage(message, { accounts, remainingAccounts });
     }
 
     return this.setTransactionPayer(tx);
   }
 
+  async makeReplyTx(data: string, originalMessageId: number, originalSender: web3.PublicKey): Promise<web3.Transaction> {
+    const toMailbox = await this.fetchMailbox(await this.getMailboxAddress(originalSender));
+    const messageIndex = toMailbox ? toMailbox.messageCount : 0;
+    const message = this.obfuscate ? this.obfuscateMessage(data, originalSender) : data;
+    const mailboxSettingsAddress = await this.getMailboxSettingsAddress(originalSender);
+    const remainingAccounts = await this.appendFeePassAccounts(
+      await this.getSettingsAccounts(mailboxSettingsAddress, originalSender),
+    );
+    const tx = await this.messagingProgram.methods
+      .sendReply(message, originalMessageId)
+      .accounts({
+        ...(await this.getSendAccounts(originalSender)),
+        originalMailbox: await this.getMailboxAddress(),
+        originalMessage: await this.getMessageAddress(originalMessageId),
+        receiver: originalSender,
+        message: await this.getMessageAddress(messageIndex, originalSender),
+        payer: this.payer ?? this.mailboxOwner,
+        sender: this.mailboxOwner,
+        feeReceiver: (await this.fetchMessagingConfig()).treasury,
+      })
+      .remainingAccounts(remainingAccounts)
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeBroadcastTx(data: string, receiverAddresses: web3.PublicKey[]): Promise<web3.Transaction> {
+    const recipientAccounts: web3.AccountMeta[] = [];
+    const extraAccounts: web3.AccountMeta[] = [];
+    for (const receiverAddress of receiverAddresses) {
+      const mailboxAddress = await this.getMailboxAddress(receiverAddress);
+      const mailbox = await this.fetchMailbox(mailboxAddress);
+      const mailboxSettingsAddress = await this.getMailboxSettingsAddress(receiverAddress);
+      recipientAccounts.push(
+        { pubkey: receiverAddress, isWritable: true, isSigner: false },
+        { pubkey: mailboxAddress, isWritable: true, isSigner: false },
+        { pubkey: mailboxSettingsAddress, isWritable: false, isSigner: false },
+        { pubkey: await this.getContactsAddress(receiverAddress), isWritable: false, isSigner: false },
+        { pubkey: await this.getRateLimitAddress(mailboxAddress), isWritable: true, isSigner: false },
+        {
+          pubkey: await this.getMessageAddress(mailbox ? mailbox.messageCount : 0, receiverAddress),
+          isWritable: true,
+          isSigner: false,
+        },
+      );
+      for (const account of await this.getSettingsAccounts(mailboxSettingsAddress, receiverAddress)) {
+        if (!extraAccounts.some((a) => a.pubkey.equals(account.pubkey))) {
+          extraAccounts.push(account);
+        }
+      }
+    }
+    const tx = await this.messagingProgram.methods
+      .broadcastMessage(data, receiverAddresses.length)
+      .accounts({
+        outbox: await this.getOutboxAddress(),
+        config: await this.getMessagingConfigAddress(),
+        payer: this.payer ?? this.mailboxOwner,
+        sender: this.mailboxOwner,
+        feeReceiver: (await this.fetchMessagingConfig()).treasury,
+      })
+      .remainingAccounts(await this.appendFeePassAccounts([...recipientAccounts, ...extraAccounts]))
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeRelayedSendTx(relayed: RelayedMessage): Promise<web3.Transaction> {
+    const toMailbox = await this.fetchMailbox(await this.getMailboxAddress(relayed.receiver));
+    const messageIndex = toMailbox ? toMailbox.messageCount : 0;
+    const mailboxSettingsAddress = await this.getMailboxSettingsAddress(relayed.receiver);
+    const remainingAccounts = await this.appendFeePassAccounts(
+      await this.getSettingsAccounts(mailboxSettingsAddress, relayed.receiver, relayed.sender),
+      relayed.sender,
+    );
+    // The program checks the signature through this instruction, which must come right before the send
+    const signatureIx = web3.Ed25519Program.createInstructionWithPublicKey({
+      publicKey: relayed.sender.toBytes(),
+      message: this.getRelayMessageBytes(relayed.receiver, relayed.nonce, relayed.data),
+      signature: relayed.signature,
+    });
+    const sendIx = await this.messagingProgram.methods
+      .sendRelayedMessage(relayed.data, new anchor.BN(relayed.nonce))
+      .accounts({
+        ...(await this.getSendAccounts(relayed.receiver, relayed.sender)),
+        receiver: relayed.receiver,
+        message: await this.getMessageAddress(messageIndex, relayed.receiver),
+        payer: this.payer ?? this.mailboxOwner,
+        sender: relayed.sender,
+        feeReceiver: (await this.fetchMessagingConfig()).treasury,
+        instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+      })
+      .remainingAccounts(remainingAccounts)
+      .instruction();
+    return this.setTransactionPayer(new web3.Transaction().add(signatureIx, sendIx));
+  }
+
+  async makeUpdateSettingTx(setting: MailboxSetting): Promise<web3.Transaction> {
+    const tx = await this.messagingProgram.methods
+      .addOrUpdateSetting(setting)
+      .accounts({
+        mailbox: await this.getMailboxAddress(),
+        receiver: this.mailboxOwner,
+        authority: this.wallet.publicKey!,
+      })
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeAcceptSenderTx(sender: web3.PublicKey): Promise<web3.Transaction> {
+    const tx = await this.messagingProgram.methods
+      .acceptSender(sender)
+      .accounts({
+        mailbox: await this.getMailboxAddress(),
+        receiver: this.mailboxOwner,
+        authority: this.wallet.publicKey!,
+      })
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeSetDelegateTx(delegate: web3.PublicKey, permissions: DelegatePermissions): Promise<web3.Transaction> {
+    const tx = await this.messagingProgram.methods
+      .setDelegate(delegate, permissions)
+      .accounts({
+        mailbox: await this.getMailboxAddress(),
+        receiver: this.mailboxOwner,
+        authority: this.wallet.publicKey!,
+      })
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeCreateSharedMailboxTx(name: string, members: SharedMailboxMember[]): Promise<web3.Transaction> {
+    const tx = await this.messagingProgram.methods
+      .createSharedMailbox(name, members)
+      .accounts({
+        sharedMailbox: await this.getSharedMailboxAddress(name),
+        owner: this.mailboxOwner,
+      })
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeSetSharedMailboxMemberTx(
+    sharedMailboxAddress: web3.PublicKey,
+    member: web3.PublicKey,
+    role: MemberRole | null,
+  ): Promise<web3.Transaction> {
+    const tx = await this.messagingProgram.methods
+      .setSharedMailboxMember(member, role)
+      .accounts({
+        sharedMailbox: sharedMailboxAddress,
+        authority: this.wallet.publicKey!,
+      })
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeUpdateReadMessagesTx(readMessageCount: number): Promise<web3.Transaction> {
+    const tx = await this.messagingProgram.methods
+      .updateReadMessages(readMessageCount)
+      .accounts({
+        mailbox: await this.getMailboxAddress(),
+        delegates: await this.getDelegatesAddress(),
+        receiver: this.mailboxOwner,
+        authority: this.wallet.publicKey!,
+      })
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeCreateOutboxTx(): Promise<web3.Transaction> {
+    const tx = await this.messagingProgram.methods
+      .createOutbox()
+      .accounts({
+        sender: this.mailboxOwner,
+      })
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeCloseOutboxTx(): Promise<web3.Transaction> {
+    const tx = await this.messagingProgram.methods
+      .closeOutbox()
+      .accounts({
+        outbox: await this.getOutboxAddress(),
+        sender: this.mailboxOwner,
+      })
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeSendEnvelopeTx(envelope: MessageEnvelope, receiverAddress: web3.PublicKey): Promise<web3.Transaction> {
+    const toMailbox = await this.fetchMailbox(await this.getMailboxAddress(receiverAddress));
+    const messageIndex = toMailbox ? toMailbox.messageCount : 0;
+    const mailboxSettingsAddress = await this.getMailboxSettingsAddress(receiverAddress);
+    const tx = await this.messagingProgram.methods
+      .sendEnvelopeMessage({
+        schemaVersion: ENVELOPE_SCHEMA_VERSION,
+        contentType: envelope.contentType,
+        subject: envelope.subj ?? null,
+        body: envelope.body,
+        metadata: Object.keys(envelope.meta ?? {}).map((key) => ({ key, value: envelope.meta![key] })),
+      })
+      .accounts({
+        ...(await this.getSendAccounts(receiverAddress)),
+        receiver: receiverAddress,
+        message: await this.getMessageAddress(messageIndex, receiverAddress),
+        payer: this.payer ?? this.mailboxOwner,
+        sender: this.mailboxOwner,
+        feeReceiver: (await this.fetchMessagingConfig()).treasury,
+      })
+      .remainingAccounts(
+        await this.appendFeePassAccounts(await this.getSettingsAccounts(mailboxSettingsAddress, receiverAddress)),
+      )
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeSendEphemeralTx(data: string, receiverAddress: web3.PublicKey): Promise<web3.Transaction> {
+    const message = this.obfuscate ? this.obfuscateMessage(data, receiverAddress) : data;
+    const mailboxSettingsAddress = await this.getMailboxSettingsAddress(receiverAddress);
+    const tx = await this.messagingProgram.methods
+      .sendEphemeralMessage(message)
+      .accounts({
+        ...(await this.getSendAccounts(receiverAddress)),
+        receiver: receiverAddress,
+        payer: this.payer ?? this.mailboxOwner,
+        sender: this.mailboxOwner,
+        feeReceiver: (await this.fetchMessagingConfig()).treasury,
+      })
+      .remainingAccounts(
+        await this.appendFeePassAccounts(await this.getSettingsAccounts(mailboxSettingsAddress, receiverAddress)),
+      )
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeAttachTxs(messageId: number, receiverAddress: web3.PublicKey, content: Buffer): Promise<web3.Transaction[]> {
+    const messageAddress = await this.getMessageAddress(messageId, receiverAddress);
+    const messageAccount = await this.messagingProgram.account.message.fetch(messageAddress);
+    const attachmentIndex = messageAccount.attachmentCount;
+    const accounts = {
+      mailbox: await this.getMailboxAddress(receiverAddress),
+      receiver: receiverAddress,
+      message: messageAddress,
+      attachment: await this.getAttachmentAddress(messageAddress, attachmentIndex),
+      sender: this.mailboxOwner,
+    };
+    const txs = [
+      await this.messagingProgram.methods
+        .createAttachment(messageId, content.length)
+        .accounts({ ...accounts, payer: messageAccount.payer })
+        .transaction(),
+    ];
+    for (let offset = 0; offset < content.length; offset += ATTACHMENT_CHUNK_SIZE) {
+      const chunk = content.subarray(offset, offset + ATTACHMENT_CHUNK_SIZE);
+      txs.push(
+        await this.messagingProgram.methods
+          .writeAttachment(messageId, attachmentIndex, offset, chunk)
+          .accounts(accounts)
+          .transaction(),
+      );
+    }
+    const contentHash = Buffer.from(
+      CryptoJS.SHA256(CryptoJS.lib.WordArray.create(content as any)).toString(CryptoJS.enc.Hex),
+      'hex',
+    );
+    txs.push(
+      await this.messagingProgram.methods
+        .finalizeAttachment(messageId, attachmentIndex, [...contentHash])
+        .accounts(accounts)
+        .transaction(),
+    );
+    return txs.map((tx) => this.setTransactionPayer(tx));
+  }
+
+  async makeCreateRingMailboxTx(): Promise<web3.Transaction> {
+    const tx = await this.messagingProgram.methods
+      .createRingMailbox()
+      .accounts({
+        receiver: this.mailboxOwner,
+      })
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeSendRingMessageTx(data: string, receiverAddress: web3.PublicKey): Promise<web3.Transaction> {
+    const ringMailboxAddress = await this.getRingMailboxAddress(receiverAddress);
+    const message = this.obfuscate ? this.obfuscateMessage(data, receiverAddress) : data;
+    const mailboxSettingsAddress = await this.getMailboxSettingsAddress(receiverAddress);
+    const tx = await this.messagingProgram.methods
+      .sendRingMessage(message)
+      .accounts({
+        ...(await this.getSendAccounts(receiverAddress)),
+        ringMailbox: ringMailboxAddress,
+        mailbox: await this.getMailboxAddress(receiverAddress),
+        rateLimit: await this.getRateLimitAddress(ringMailboxAddress),
+        receiver: receiverAddress,
+        payer: this.payer ?? this.mailboxOwner,
+        sender: this.mailboxOwner,
+        feeReceiver: (await this.fetchMessagingConfig()).treasury,
+      })
+      .remainingAccounts(
+        await this.appendFeePassAccounts(await this.getSettingsAccounts(mailboxSettingsAddress, receiverAddress)),
+      )
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeUpdateRingReadMessagesTx(readMessageCount: number): Promise<web3.Transaction> {
+    const tx = await this.messagingProgram.methods
+      .updateRingReadMessages(readMessageCount)
+      .accounts({
+        ringMailbox: await this.getRingMailboxAddress(),
+        mailbox: await this.getMailboxAddress(),
+        delegates: await this.getDelegatesAddress(),
+        receiver: this.mailboxOwner,
+        authority: this.wallet.publicKey!,
+      })
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeRejectMessageRequestTx(messageId: number): Promise<web3.Transaction> {
+    const messageAddress = await this.getRequestMessageAddress(messageId);
+    const messageAccount = await this.messagingProgram.account.message.fetch(messageAddress);
+    const tx = await this.messagingProgram.methods
+      .rejectMessageRequest(messageId)
+      .accounts({
+        mailbox: await this.getMailboxAddress(),
+        requestBox: await this.getRequestBoxAddress(),
+        message: messageAddress,
+        outbox: await this.getOutboxAddress(messageAccount.sender),
+        receiver: this.mailboxOwner,
+        authority: this.wallet.publicKey!,
+        rentDestination: messageAccount.payer,
+      })
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeCloseMailboxTx(): Promise<web3.Transaction> {
+    const mailboxAddress = await this.getMailboxAddress();
+    const mailbox = await this.fetchMailbox(mailboxAddress);
+    if (!mailbox) {
+      throw new Error(`Mailbox ${mailboxAddress.toBase58()} not found`);
+    }
+    // The program checks that every message ever sent is gone, including those behind the read
+    // pointer, continuing from where the last transaction stopped
+    const unchecked = mailbox.messageCount - mailbox.closeCheckedCount;
+    const messageIds = Array.from(
+      { length: Math.min(unchecked, CLOSE_MAILBOX_BATCH_SIZE) },
+      (_element, index) => mailbox.closeCheckedCount + index,
+    );
+    const messageAddresses = await Promise.all(messageIds.map((id) => this.getMessageAddress(id)));
+    const tx = await this.messagingProgram.methods
+      .closeMailbox()
+      .accounts({
+        mailbox: mailboxAddress,
+        receiver: this.mailboxOwner,
+        authority: this.wallet.publicKey!,
+        rentDestination: mailbox.payer,
+      })
+      .remainingAccounts(messageAddresses.map((pubkey) => ({ pubkey, isWritable: false, isSigner: false })))
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
   /** @deprecated use makeDeleteTx instead */
   async makePopTx(): Promise<web3.Transaction> {
     const mailboxAddress = await this.getMailboxAddress();
@@ -263,7 +1077,52 @@ export class Mailbox extends DispatchConnection {
     if (!mailbox) {
       throw new Error(`Mailbox ${mailboxAddress.toBase58()} not found`);
     }
-    return this.makeDeleteTx(mailbox.readMessageCount, this.mailboxOwner);
+    const [oldest] = await this.fetchMessages();
+    if (!oldest) {
+      throw new Error(`Mailbox ${mailboxAddress.toBase58()} has no messages to pop`);
+    }
+    const deleteTx = await this.makeDeleteTx(oldest.messageId, this.mailboxOwner);
+    if (oldest.messageId === mailbox.readMessageCount) {
+      return deleteTx;
+    }
+    // The read pointer sits on indices without an account, such as ephemeral messages. Move it
+    // to the oldest message first so deleting that message advances it.
+    const tx = await this.makeUpdateReadMessagesTx(oldest.messageId);
+    return tx.add(...deleteTx.instructions);
+  }
+
+  async makeCleanupExpiredMessageTx(messageId: number, receiverAddress?: web3.PublicKey): Promise<web3.Transaction> {
+    const messageAddress = await this.getMessageAddress(messageId, receiverAddress ?? this.mailboxOwner);
+    const messageAccount = await this.messagingProgram.account.message.fetch(messageAddress);
+    const tx = await this.messagingProgram.methods
+      .cleanupExpiredMessage(messageId)
+      .accounts({
+        mailbox: await this.getMailboxAddress(receiverAddress),
+        message: messageAddress,
+        receiver: receiverAddress ?? this.mailboxOwner,
+        cranker: this.wallet.publicKey!,
+        outbox: await this.getOutboxAddress(messageAccount.sender),
+        rentDestination: messageAccount.payer,
+      })
+      .remainingAccounts(await this.getAttachmentAccounts(messageAddress, messageAccount.attachmentCount))
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeCloseRateLimitTx(receiverAddress: web3.PublicKey, sender?: web3.PublicKey): Promise<web3.Transaction> {
+    const mailboxAddress = await this.getMailboxAddress(receiverAddress);
+    const rateLimitAddress = await this.getRateLimitAddress(mailboxAddress, sender);
+    const rateLimit = await this.messagingProgram.account.senderRateLimit.fetch(rateLimitAddress);
+    const tx = await this.messagingProgram.methods
+      .closeRateLimit()
+      .accounts({
+        mailbox: mailboxAddress,
+        sender: sender ?? this.mailboxOwner,
+        rateLimit: rateLimitAddress,
+        rentDestination: rateLimit.payer,
+      })
+      .transaction();
+    return this.setTransactionPayer(tx);
   }
 
   /// Returns null if message account doesn't exist, the transaction otherwise
@@ -273,10 +1132,41 @@ export class Mailbox extends DispatchConnection {
     const tx = await this.messagingProgram.methods
       .deleteMessage(messageId)
       .accounts({
+        mailbox: await this.getMailboxAddress(receiverAddress),
+        delegates: await this.getDelegatesAddress(receiverAddress),
+        message: messageAddress,
         receiver: receiverAddress ?? this.mailboxOwner,
-        authorizedDeleter: this.mailboxOwner,
+        authorizedDeleter: this.wallet.publicKey!,
+        outbox: await this.getOutboxAddress(messageAccount.sender),
         rentDestination: messageAccount.payer,
       })
+      .remainingAccounts(await this.getAttachmentAccounts(messageAddress, messageAccount.attachmentCount))
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeMigrateMailboxTx(mailboxOwner?: web3.PublicKey): Promise<web3.Transaction> {
+    const tx = await this.messagingProgram.methods
+      .migrateMailbox()
+      .accounts({
+        mailbox: await this.getMailboxAddress(mailboxOwner),
+        receiver: mailboxOwner ?? this.mailboxOwner,
+        payer: this.payer ?? this.wallet.publicKey!,
+      })
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  async makeMigrateMessageTx(messageId: number, receiverAddress?: web3.PublicKey): Promise<web3.Transaction> {
+    const receiver = receiverAddress ?? this.mailboxOwner;
+    const tx = await this.messagingProgram.methods
+      .migrateMessage(messageId)
+      .accounts({
+        mailbox: await this.getMailboxAddress(receiver),
+        receiver,
+        message: await this.getMessageAddress(messageId, receiver),
+        payer: this.payer ?? this.wallet.publicKey!,
+      })
       .transaction();
     return this.setTransactionPayer(tx);
   }
@@ -288,11 +1178,18 @@ export class Mailbox extends DispatchConnection {
     const messageAccount = await this.messagingProgram.account.message.fetch(messageAddress);
     const mint = messageAccount.incentiveMint;
     const ata = await splToken.getAssociatedTokenAddress(mint, messageAddress, true);
-    const receiverAta = await splToken.getAssociatedTokenAddress(mint, receiver, true);
+    const beneficiary = await this.getIncentiveBeneficiary(receiver);
+    const receiverAta = await splToken.getAssociatedTokenAddress(mint, beneficiary, true);
     const tx = await this.messagingProgram.methods
       .claimIncentive(messageId)
       .accounts({
+        mailbox: await this.getMailboxAddress(receiver),
+        delegates: await this.getDelegatesAddress(receiver),
+        message: messageAddress,
         receiver,
+        authority: this.wallet.publicKey!,
+        instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+        beneficiary,
         rentDestination: messageAccount.payer,
         incentiveMint: mint,
         incentiveTokenAccount: ata,
@@ -302,6 +1199,64 @@ export class Mailbox extends DispatchConnection {
     return this.setTransactionPayer(tx);
   }
 
+  /// Returns null if message account doesn't exist, the transaction otherwise
+  async makeClaimSolIncentiveTx(messageId: number, receiverAddress?: web3.PublicKey): Promise<web3.Transaction> {
+    const receiver = receiverAddress ?? this.mailboxOwner;
+    const tx = await this.messagingProgram.methods
+      .claimSolIncentive(messageId)
+      .accounts({
+        mailbox: await this.getMailboxAddress(receiver),
+        delegates: await this.getDelegatesAddress(receiver),
+        message: await this.getMessageAddress(messageId, receiver),
+        receiver,
+        authority: this.wallet.publicKey!,
+        instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+        beneficiary: await this.getIncentiveBeneficiary(receiver),
+      })
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  /// Returns null if message account doesn't exist, the transaction otherwise
+  async makeRefundIncentiveTx(messageId: number, receiverAddress: web3.PublicKey): Promise<web3.Transaction> {
+    const messageAddress = await this.getMessageAddress(messageId, receiverAddress);
+    const messageAccount = await this.messagingProgram.account.message.fetch(messageAddress);
+    const mint = messageAccount.incentiveMint;
+    const ata = await splToken.getAssociatedTokenAddress(mint, messageAddress, true);
+    const refundAta = await splToken.getAssociatedTokenAddress(mint, messageAccount.payer, true);
+    const tx = await this.messagingProgram.methods
+      .refundIncentive(messageId)
+      .accounts({
+        mailbox: await this.getMailboxAddress(receiverAddress),
+        message: messageAddress,
+        receiver: receiverAddress,
+        authorizedRefunder: this.mailboxOwner,
+        rentDestination: messageAccount.payer,
+        incentiveMint: mint,
+        incentiveTokenAccount: ata,
+        refundTokenAccount: refundAta,
+      })
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  /// Returns null if message account doesn't exist, the transaction otherwise
+  async makeRefundSolIncentiveTx(messageId: number, receiverAddress: web3.PublicKey): Promise<web3.Transaction> {
+    const messageAddress = await this.getMessageAddress(messageId, receiverAddress);
+    const messageAccount = await this.messagingProgram.account.message.fetch(messageAddress);
+    const tx = await this.messagingProgram.methods
+      .refundSolIncentive(messageId)
+      .accounts({
+        mailbox: await this.getMailboxAddress(receiverAddress),
+        message: messageAddress,
+        receiver: receiverAddress,
+        authorizedRefunder: this.mailboxOwner,
+        rentDestination: messageAccount.payer,
+      })
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
   /*
     Subscriptions
   */
@@ -315,6 +1270,8 @@ export class Mailbox extends DispatchConnection {
           receiver: event.receiverPubkey,
           data: this.unpackMessageData(event.message, event.senderPubkey, event.receiverPubkey),
           messageId: event.messageIndex,
+          contentHash: event.contentHash ? Buffer.from(event.contentHash) : undefined,
+          contentType: event.contentType ?? undefined,
         });
       }
     });
@@ -349,6 +1306,177 @@ export class Mailbox extends DispatchConnection {
     return mailboxAddress;
   }
 
+  async getMailboxSettingsAddress(mailboxOwner?: web3.PublicKey) {
+    const mailboxAddress = await this.getMailboxAddress(mailboxOwner);
+    const [settingsAddress] = await web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.mailboxSettingsSeed, mailboxAddress.toBuffer()],
+      this.messagingProgram.programId,
+    );
+
+    return settingsAddress;
+  }
+
+  async fetchMailboxSettings(mailboxOwner?: web3.PublicKey) {
+    const address = await this.getMailboxSettingsAddress(mailboxOwner);
+    return this.messagingProgram.account.mailboxSettings.fetchNullable(address);
+  }
+
+  async getContactsAddress(mailboxOwner?: web3.PublicKey) {
+    const mailboxAddress = await this.getMailboxAddress(mailboxOwner);
+    const [contactsAddress] = await web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.contactsSeed, mailboxAddress.toBuffer()],
+      this.messagingProgram.programId,
+    );
+
+    return contactsAddress;
+  }
+
+  async fetchContacts(mailboxOwner?: web3.PublicKey): Promise<web3.PublicKey[]> {
+    const address = await this.getContactsAddress(mailboxOwner);
+    const contacts = await this.messagingProgram.account.contacts.fetchNullable(address);
+    return contacts?.contacts ?? [];
+  }
+
+  async getDelegatesAddress(mailboxOwner?: web3.PublicKey) {
+    const mailboxAddress = await this.getMailboxAddress(mailboxOwner);
+    const [delegatesAddress] = await web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.delegatesSeed, mailboxAddress.toBuffer()],
+      this.messagingProgram.programId,
+    );
+
+    return delegatesAddress;
+  }
+
+  async fetchDelegates(mailboxOwner?: web3.PublicKey): Promise<{ key: web3.PublicKey; permissions: DelegatePermissions }[]> {
+    const address = await this.getDelegatesAddress(mailboxOwner);
+    const delegates = await this.messagingProgram.account.delegates.fetchNullable(address);
+    return delegates?.delegates ?? [];
+  }
+
+  async getSharedMailboxAddress(name: string, owner?: web3.PublicKey) {
+    const [sharedMailboxAddress] = await web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.sharedMailboxSeed, (owner ?? this.mailboxOwner).toBuffer(), Buffer.from(name)],
+      this.messagingProgram.programId,
+    );
+
+    return sharedMailboxAddress;
+  }
+
+  /// Null for regular receivers, whose wallet accounts are not owned by the program
+  async fetchSharedMailbox(sharedMailboxAddress: web3.PublicKey) {
+    const info = await this.conn.getAccountInfo(sharedMailboxAddress);
+    if (!info || !info.owner.equals(this.messagingProgram.programId)) {
+      return null;
+    }
+    return this.messagingProgram.account.sharedMailbox.fetch(sharedMailboxAddress);
+  }
+
+  /// Incentives for a shared mailbox are paid to its owner, everyone else is paid directly
+  async getIncentiveBeneficiary(receiverAddress: web3.PublicKey) {
+    const sharedMailbox = await this.fetchSharedMailbox(receiverAddress);
+    return sharedMailbox ? sharedMailbox.owner : receiverAddress;
+  }
+
+  async getRequestBoxAddress(mailboxOwner?: web3.PublicKey) {
+    const mailboxAddress = await this.getMailboxAddress(mailboxOwner);
+    const [requestBoxAddress] = await web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.requestBoxSeed, mailboxAddress.toBuffer()],
+      this.messagingProgram.programId,
+    );
+
+    return requestBoxAddress;
+  }
+
+  async getRequestMessageAddress(index: number, receiverAddress?: web3.PublicKey) {
+    const requestBoxAddress = await this.getRequestBoxAddress(receiverAddress);
+    const msgCountBuf = Buffer.allocUnsafe(4);
+    msgCountBuf.writeInt32LE(index);
+    const [messageAddress] = await web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.messageSeed, requestBoxAddress.toBuffer(), msgCountBuf],
+      this.messagingProgram.programId,
+    );
+
+    return messageAddress;
+  }
+
+  /// Pending message requests, which are not part of the regular mailbox
+  async fetchMessageRequests(): Promise<MessageAccount[]> {
+    const requestBox = await this.fetchMailbox(await this.getRequestBoxAddress());
+    if (!requestBox) {
+      return [];
+    }
+    const numMessages = requestBox.messageCount - requestBox.readMessageCount;
+    const messageIds = Array(numMessages)
+      .fill(0)
+      .map((_element, index) => index + requestBox.readMessageCount);
+    const addresses = await Promise.all(messageIds.map((id) => this.getRequestMessageAddress(id)));
+    const messages = await this.messagingProgram.account.message.fetchMultiple(addresses);
+    const normalize = (messageAccount: any | null, index: number) => {
+      return this.normalizeMessageAccount(messageAccount, index + requestBox.readMessageCount);
+    };
+    return messages.map(normalize).filter((m): m is MessageAccount => m !== null);
+  }
+
+  async getRingMailboxAddress(mailboxOwner?: web3.PublicKey) {
+    const [ringMailboxAddress] = await web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.ringMailboxSeed, (mailboxOwner ?? this.mailboxOwner).toBuffer()],
+      this.messagingProgram.programId,
+    );
+
+    return ringMailboxAddress;
+  }
+
+  async fetchRingMailbox(mailboxOwner?: web3.PublicKey) {
+    const address = await this.getRingMailboxAddress(mailboxOwner);
+    return this.messagingProgram.account.ringMailbox.fetchNullable(address);
+  }
+
+  /// Where the receiver's rate limit counts messages from `sender` to the given mailbox
+  async getRateLimitAddress(mailboxAddress: web3.PublicKey, sender?: web3.PublicKey) {
+    const [rateLimitAddress] = await web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.rateLimitSeed, mailboxAddress.toBuffer(), (sender ?? this.mailboxOwner).toBuffer()],
+      this.messagingProgram.programId,
+    );
+
+    return rateLimitAddress;
+  }
+
+  async getOutboxAddress(sender?: web3.PublicKey) {
+    const [outboxAddress] = await web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.outboxSeed, (sender ?? this.mailboxOwner).toBuffer()],
+      this.messagingProgram.programId,
+    );
+
+    return outboxAddress;
+  }
+
+  async getSenderNonceAddress(sender?: web3.PublicKey) {
+    const [nonceAddress] = await web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.senderNonceSeed, (sender ?? this.mailboxOwner).toBuffer()],
+      this.messagingProgram.programId,
+    );
+
+    return nonceAddress;
+  }
+
+  /// The nonce the sender's next relayed message has to be signed with
+  async fetchSenderNonce(sender?: web3.PublicKey): Promise<number> {
+    const address = await this.getSenderNonceAddress(sender);
+    const senderNonce = await this.messagingProgram.account.senderNonce.fetchNullable(address);
+    return senderNonce ? senderNonce.nonce.toNumber() : 0;
+  }
+
+  /// The bytes a sender signs to authorize a relayed message, matching relay_message_bytes in the program
+  getRelayMessageBytes(receiverAddress: web3.PublicKey, nonce: number, data: string): Uint8Array {
+    return Buffer.concat([
+      Buffer.from('dispatch:relayed_message'),
+      this.messagingProgram.programId.toBuffer(),
+      receiverAddress.toBuffer(),
+      new anchor.BN(nonce).toArrayLike(Buffer, 'le', 8),
+      Buffer.from(data),
+    ]);
+  }
+
   async getMessageAddress(index: number, receiverAddress?: web3.PublicKey) {
     const receiver = receiverAddress ?? this.mailboxOwner;
     const mailboxAddress = await this.getMailboxAddress(receiver);
@@ -362,12 +1490,140 @@ export class Mailbox extends DispatchConnection {
     return messageAddress;
   }
 
+  async getAttachmentAddress(messageAddress: web3.PublicKey, index: number) {
+    const [attachmentAddress] = await web3.PublicKey.findProgramAddress(
+      [seeds.protocolSeed, seeds.attachmentSeed, messageAddress.toBuffer(), Buffer.from([index])],
+      this.messagingProgram.programId,
+    );
+
+    return attachmentAddress;
+  }
+
+  async getAttachmentAddresses(messageAddress: web3.PublicKey, attachmentCount: number) {
+    return Promise.all(
+      Array.from({ length: attachmentCount }, (_element, index) => this.getAttachmentAddress(messageAddress, index)),
+    );
+  }
+
+  /// Closing a message also closes its attachments, which have to be passed in order
+  private async getAttachmentAccounts(messageAddress: web3.PublicKey, attachmentCount: number) {
+    const addresses = await this.getAttachmentAddresses(messageAddress, attachmentCount);
+    return addresses.map((pubkey) => ({ pubkey, isWritable: true, isSigner: false }));
+  }
+
+  /// Entries of an outbox still in use, oldest first. Send `n` is kept in slot `n % capacity`.
+  private listOutbox(outbox: any): { receiver: web3.PublicKey; messageIndex: number; kind: SentMessageKind }[] {
+    const capacity = outbox.entries.length;
+    const sentCount = outbox.sentCount.toNumber();
+    const oldest = Math.max(0, sentCount - capacity);
+    return Array.from({ length: sentCount - oldest }, (_element, index) => outbox.entries[(oldest + index) % capacity])
+      .filter((sent) => sent.inUse !== 0)
+      .map((sent) => ({ receiver: sent.receiver, messageIndex: sent.messageIndex, kind: sentMessageKinds[sent.kind] }));
+  }
+
   private async fetchMailbox(mailboxAddress?: web3.PublicKey) {
     const address = mailboxAddress ?? (await this.getMailboxAddress());
     const mailboxAccount = await this.messagingProgram.account.mailbox.fetchNullable(address);
     return mailboxAccount;
   }
 
+  /// Accounts a send reads from the receiver's mailbox and the sender's outbox, which the
+  /// program checks by address rather than deriving them
+  private async getSendAccounts(receiverAddress: web3.PublicKey, sender: web3.PublicKey = this.mailboxOwner) {
+    return {
+      mailboxSettings: await this.getMailboxSettingsAddress(receiverAddress),
+      contacts: await this.getContactsAddress(receiverAddress),
+      rateLimit: await this.getRateLimitAddress(await this.getMailboxAddress(receiverAddress), sender),
+      outbox: await this.getOutboxAddress(sender),
+      config: await this.getMessagingConfigAddress(),
+    };
+  }
+
+  /// Extra accounts the receiver's settings need: token accounts for postage and for
+  /// token-based allow and block list rules. Postage to a shared mailbox goes to its owner.
+  private async getSettingsAccounts(
+    mailboxSettingsAddress: web3.PublicKey,
+    receiverAddress: web3.PublicKey,
+    sender: web3.PublicKey = this.mailboxOwner,
+  ) {
+    const settings = await this.messagingProgram.account.mailboxSettings.fetchNullable(mailboxSettingsAddress);
+    if (!settings || receiverAddress.equals(sender)) {
+      return [];
+    }
+    const accounts: web3.AccountMeta[] = [];
+    for (const setting of settings.settings as any[]) {
+      const rules = setting.senderAllowList?.rules ?? setting.senderBlockList?.rules ?? [];
+      for (const rule of rules) {
+        if (rule.tokenOwnership) {
+          const senderAta = await splToken.getAssociatedTokenAddress(rule.tokenOwnership.mint, sender, true);
+          accounts.push({ pubkey: senderAta, isWritable: false, isSigner: false });
+        }
+      }
+      const beneficiary = setting.postage ? await this.getIncentiveBeneficiary(receiverAddress) : receiverAddress;
+      if (setting.postage?.postage.lamports && !beneficiary.equals(receiverAddress)) {
+        accounts.push({ pubkey: beneficiary, isWritable: true, isSigner: false });
+      }
+      const tokenPostage = setting.postage?.postage.token;
+      if (tokenPostage) {
+        const payerAta = await splToken.getAssociatedTokenAddress(tokenPostage.mint, this.payer ?? this.mailboxOwner, true);
+        const beneficiaryAta = await splToken.getAssociatedTokenAddress(tokenPostage.mint, beneficiary, true);
+        accounts.push(
+          { pubkey: payerAta, isWritable: true, isSigner: false },
+          { pubkey: beneficiaryAta, isWritable: true, isSigner: false },
+          { pubkey: splToken.TOKEN_PROGRAM_ID, isWritable: false, isSigner: false },
+        );
+      }
+    }
+    return accounts;
+  }
+
+  /// Senders outside the receiver's contacts must go through the request queue when the
+  /// receiver has message requests enabled
+  private async mustSendAsRequest(receiverAddress: web3.PublicKey) {
+    if (receiverAddress.equals(this.mailboxOwner)) {
+      return false;
+    }
+    const settings = await this.fetchMailboxSettings(receiverAddress);
+    const requests = (settings?.settings as any[] | undefined)?.find((s) => s.messageRequests);
+    if (!requests?.messageRequests.enabled) {
+      return false;
+    }
+    const contacts = await this.fetchContacts(receiverAddress);
+    return !contacts.some((c) => c.equals(this.mailboxOwner));
+  }
+
+  private async makeSendRequestTx(
+    message: string,
+    receiverAddress: web3.PublicKey,
+    remainingAccounts: web3.AccountMeta[],
+  ): Promise<web3.Transaction> {
+    const requestBox = await this.fetchMailbox(await this.getRequestBoxAddress(receiverAddress));
+    const messageIndex = requestBox ? requestBox.messageCount : 0;
+    const tx = await this.messagingProgram.methods
+      .sendMessageRequest(message)
+      .accounts({
+        mailbox: await this.getMailboxAddress(receiverAddress),
+        mailboxSettings: await this.getMailboxSettingsAddress(receiverAddress),
+        rateLimit: await this.getRateLimitAddress(await this.getRequestBoxAddress(receiverAddress)),
+        outbox: await this.getOutboxAddress(),
+        config: await this.getMessagingConfigAddress(),
+        receiver: receiverAddress,
+        message: await this.getRequestMessageAddress(messageIndex, receiverAddress),
+        payer: this.payer ?? this.mailboxOwner,
+        sender: this.mailboxOwner,
+        feeReceiver: (await this.fetchMessagingConfig()).treasury,
+      })
+      .remainingAccounts(remainingAccounts)
+      .transaction();
+    return this.setTransactionPayer(tx);
+  }
+
+  /// The proof of the sender's protocol pass has to come after every other extra account
+  private async appendFeePassAccounts(accounts: web3.AccountMeta[], sender: web3.PublicKey = this.mailboxOwner) {
+    const config = await this.fetchMessagingConfig();
+    return [...accounts, ...(await this.getFeePassAccounts(config.fees.pass, sender))];
+  }
+
   private validateWallet() {
     if (!this.wallet.publicKey!.equals(this.mailboxOwner)) {
       throw new Error('`mailboxOwner` must equal `wallet.publicKey` to send transaction');
@@ -377,6 +1633,16 @@ export class Mailbox extends DispatchConnection {
     }
   }
 
+  /// Receiver operations may also be signed by a delegate of `mailboxOwner` or a member of a
+  /// shared mailbox, which the program checks against the delegates or roles the owner set
+  private validateAuthority() {
+    if (this.wallet.publicKey!.equals(this.mailboxOwner)) {
+      this.validateWallet();
+    } else if (this.payer && !this.payer.equals(this.wallet.publicKey!)) {
+      throw new Error('`payer` must equal `wallet.publicKey` when acting as a delegate');
+    }
+  }
+
   private setTransactionPayer(tx: web3.Transaction): web3.Transaction {
     if (this.payer) {
       tx.feePayer = this.wallet.publicKey!;
@@ -424,6 +1690,18 @@ export class Mailbox extends DispatchConnection {
     return message;
   }
 
+  private unpackEnvelope(envelope: any): MessageData {
+    const meta: Record<string, string> = {};
+    for (const entry of envelope.metadata) {
+      meta[entry.key] = entry.value;
+    }
+    return {
+      subj: envelope.subject ?? undefined,
+      body: envelope.body,
+      meta,
+    };
+  }
+
   private unpackMessageData(message: string, sender: web3.PublicKey, receiver: web3.PublicKey): MessageData {
     const data = this.unObfuscateMessage(message, sender, receiver);
     try {
@@ -463,11 +1741,29 @@ export class Mailbox extends DispatchConnection {
       sender: messageAccount.sender,
       receiver: this.mailboxOwner,
       payer: messageAccount.payer,
-      data: this.unpackMessageData(messageAccount.data, messageAccount.sender, this.mailboxOwner),
+      data: messageAccount.envelope
+        ? this.unpackEnvelope(messageAccount.envelope)
+        : this.unpackMessageData(messageAccount.data, messageAccount.sender, this.mailboxOwner),
       messageId,
       incentiveMint: web3.PublicKey.default.equals(messageAccount.incentiveMint)
         ? undefined
         : messageAccount.incentiveMint,
+      incentiveLamports: messageAccount.incentiveLamports.isZero()
+        ? undefined
+        : messageAccount.incentiveLamports.toNumber(),
+      incentiveExpiresAt: messageAccount.incentiveConditions.expiresAt
+        ? new Date(1000 * messageAccount.incentiveConditions.expiresAt.toNumber())
+        : undefined,
+      incentiveRequiresReply: messageAccount.incentiveConditions.requiresReply || undefined,
+      expiresAt: messageAccount.expiresAt ? new Date(1000 * messageAccount.expiresAt.toNumber()) : undefined,
+      replyTo: messageAccount.replyTo
+        ? { mailbox: messageAccount.replyTo.mailbox, messageId: messageAccount.replyTo.messageIndex }
+        : undefined,
+      incentiveNft: messageAccount.incentiveNft
+        ? { collection: messageAccount.incentiveNft.collection ?? undefined }
+        : undefined,
+      attachmentCount: messageAccount.attachmentCount || undefined,
+      contentType: messageAccount.envelope?.contentType,
     } as MessageAccount;
   }
 }
@@ -161,8 +161,9 @@ export class Postbox {
       .accounts({
         signer: this.dispatch.wallet.publicKey!,
         targetAccount: this.target.key,
-        treasury: this.dispatch.addresses.treasuryAddress,
+        treasury: (await this.dispatch.fetchPostboxConfig()).treasury,
       })
+      .remainingAccounts(await this.getFeePassAccounts())
       .transaction();
     await addNewPostbox(this.dispatch.cluster, this.target.key);
     return ix;
@@ -181,6 +182,11 @@ export class Postbox {
 
   // Some helpers for basic commands
 
+  async getFeePassAccounts() {
+    const config = await this.dispatch.fetchPostboxConfig();
+    return this.dispatch.getFeePassAccounts(config.fees.pass, this.dispatch.wallet.publicKey!);
+  }
+
   async _getTokenPostRestrictionAccounts(tokenPostRestriction: TokenPostRestriction) {
     const ata = await splToken.getAssociatedTokenAddress(tokenPostRestriction.mint, this.dispatch.wallet.publicKey!);
     const info = await this.dispatch.conn.getAccountInfo(ata);
@@ -305,10 +311,10 @@ export class Postbox {
       .accounts({
         postbox: await this.getAddress(),
         poster: this.dispatch.wallet.publicKey!,
-        treasury: this.dispatch.addresses.treasuryAddress,
+        treasury: (await this.dispatch.fetchPostboxConfig()).treasury,
         replyTo: replyTo?.address ?? web3.PublicKey.default,
       })
-      .remainingAccounts(postRestrictions.pra)
+      .remainingAccounts([...postRestrictions.pra, ...(await this.getFeePassAccounts())])
       .transaction();
     return this.dispatch.sendTransaction(ix);
   }
@@ -362,9 +368,9 @@ export class Postbox {
       .accounts({
         postbox: await this.getAddress(),
         post: post.address,
-        treasury: this.dispatch.addresses.treasuryAddress,
+        treasury: (await this.dispatch.fetchPostboxConfig()).treasury,
       })
-      .remainingAccounts(postRestrictions.pra)
+      .remainingAccounts([...postRestrictions.pra, ...(await this.getFeePassAccounts())])
       .transaction();
     tx.add(ix);
     return this.dispatch.sendTransaction(tx);
@@ -13,6 +13,7 @@ export interface WalletInterface {
   sendTransaction?: WalletAdapterProps['sendTransaction'];
   signTransaction?: AnchorExpectedWalletInterface['signTransaction'];
   signAllTransactions?: AnchorExpectedWalletInterface['signAllTransactions'];
+  signMessage?(message: Uint8Array): Promise<Uint8Array>;
 }
 
 export interface AnchorNodeWalletInterface extends WalletInterface {